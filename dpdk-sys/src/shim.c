@@ -135,7 +135,6 @@ _pkt_stop_and_close_ports()
                 rte_eth_dev_stop(port_id);
                 rte_eth_dev_close(port_id);
         }
-        exit(0);
 }
 
 struct rte_ether_hdr *
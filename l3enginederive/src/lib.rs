@@ -0,0 +1,29 @@
+//! Derive macros for the traits of l3enginelib
+//!
+//! The macros are re-exported by l3enginelib next to their traits, so
+//! `use l3enginelib::apis::SizeOf;` brings both the trait and `#[derive(SizeOf)]`.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput};
+
+/// Derives `l3enginelib::apis::SizeOf` as `std::mem::size_of::<Self>()`
+///
+/// Types read straight out of packet data should be `#[repr(C, packed)]`, so their
+/// size is exactly that of the bytes on the wire.
+#[proc_macro_derive(SizeOf)]
+pub fn derive_size_of(input: TokenStream) -> TokenStream {
+	let input = parse_macro_input!(input as DeriveInput);
+	let name = &input.ident;
+	let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+	let expanded = quote! {
+		impl #impl_generics ::l3enginelib::apis::SizeOf for #name #ty_generics #where_clause {
+			#[inline]
+			fn size_of() -> usize {
+				::std::mem::size_of::<Self>()
+			}
+		}
+	};
+	TokenStream::from(expanded)
+}
@@ -0,0 +1,267 @@
+//! Software counters of the engine, kept in shared memory
+//!
+//! The engine places an `EngineCounters` in the memzone `EngineCounters::MEMZONE`
+//! and counts what every port, pipeline stage and client moves and drops.
+//! Any other process, e.g. a monitoring tool, can read them while the engine runs:
+//! `SharedRef::<EngineCounters>::attach(EngineCounters::MEMZONE)`.
+//!
+//! Counters are relaxed atomics that only ever grow, so updating them takes no lock
+//! and readers see values at most a few packets behind.
+
+use std::{
+	fmt,
+	sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::{Mbuf, Shareable};
+
+/// Number of ports with counters; ports with higher ids are not counted
+pub const MAX_COUNTED_PORTS: usize = dpdk_sys::RTE_MAX_ETHPORTS as usize;
+/// Number of clients with counters; clients with higher ids are not counted
+pub const MAX_COUNTED_CLIENTS: usize = 64;
+
+/// Why a packet was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+	/// A flow rule dropped it
+	Filtered,
+	/// It had no ethernet header to speak of
+	Malformed,
+	/// Its port tag names no port the engine drives
+	NoPort,
+	/// The NIC did not take it
+	TxFull,
+	/// The ring to the client was full
+	RingFull,
+	/// It was meant for a client that is not registered
+	NoClient,
+	/// The sending lcore has no tx queue on its port
+	NoTxQueue,
+}
+
+impl DropReason {
+	/// Every reason, in counter order
+	pub const ALL: [DropReason; 7] = [
+		DropReason::Filtered,
+		DropReason::Malformed,
+		DropReason::NoPort,
+		DropReason::TxFull,
+		DropReason::RingFull,
+		DropReason::NoClient,
+		DropReason::NoTxQueue,
+	];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			DropReason::Filtered => "filtered",
+			DropReason::Malformed => "malformed",
+			DropReason::NoPort => "no port",
+			DropReason::TxFull => "tx full",
+			DropReason::RingFull => "ring full",
+			DropReason::NoClient => "no client",
+			DropReason::NoTxQueue => "no tx queue",
+		}
+	}
+}
+
+/// A stage of the engine's packet pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+	/// Packets received from the ports
+	GetExternalPkts,
+	/// Packets handed to the packetiser
+	SendToPacketiser,
+	/// Packets taken back from the packetiser
+	GetFromPacketiser,
+	/// Packets sent out of the ports
+	SendPktsOut,
+}
+
+impl Stage {
+	/// Every stage, in pipeline order
+	pub const ALL: [Stage; 4] = [
+		Stage::GetExternalPkts,
+		Stage::SendToPacketiser,
+		Stage::GetFromPacketiser,
+		Stage::SendPktsOut,
+	];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Stage::GetExternalPkts => "get_external_pkts",
+			Stage::SendToPacketiser => "send_to_packetiser",
+			Stage::GetFromPacketiser => "get_from_packetiser",
+			Stage::SendPktsOut => "send_pkts_out",
+		}
+	}
+}
+
+/// A counter in shared memory
+#[repr(transparent)]
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+	#[inline]
+	pub fn add(&self, n: u64) {
+		if n != 0 {
+			self.0.fetch_add(n, Ordering::Relaxed);
+		}
+	}
+
+	#[inline]
+	pub fn get(&self) -> u64 {
+		self.0.load(Ordering::Relaxed)
+	}
+}
+
+/// Counters of a single port, stage or client
+///
+/// `in` is what it took in and `out` what it passed on: for a port, received and sent
+/// packets; for a client, packets from and to the client.
+/// Every block has a cache line of its own, so lcores counting different blocks do not contend.
+#[repr(C, align(64))]
+#[derive(Default)]
+pub struct Counters {
+	pub pkts_in: Counter,
+	pub bytes_in: Counter,
+	pub pkts_out: Counter,
+	pub bytes_out: Counter,
+	/// Dropped packets, indexed like `DropReason::ALL`
+	pub drops: [Counter; DropReason::ALL.len()],
+	/// Times a ring was found full
+	pub ring_full: Counter,
+	/// Times an mbuf could not be allocated
+	pub alloc_failures: Counter,
+}
+
+impl Counters {
+	#[inline]
+	pub fn count_in(&self, pkts: u64, bytes: u64) {
+		self.pkts_in.add(pkts);
+		self.bytes_in.add(bytes);
+	}
+
+	#[inline]
+	pub fn count_out(&self, pkts: u64, bytes: u64) {
+		self.pkts_out.add(pkts);
+		self.bytes_out.add(bytes);
+	}
+
+	/// Count `pkts` as taken in
+	#[inline]
+	pub fn count_in_pkts(&self, pkts: &[Mbuf]) {
+		self.count_in(pkts.len() as u64, total_len(pkts));
+	}
+
+	/// Count `pkts` as passed on
+	#[inline]
+	pub fn count_out_pkts(&self, pkts: &[Mbuf]) {
+		self.count_out(pkts.len() as u64, total_len(pkts));
+	}
+
+	#[inline]
+	pub fn count_drops(&self, reason: DropReason, pkts: u64) {
+		self.drops[reason as usize].add(pkts);
+	}
+
+	/// Current values
+	pub fn snapshot(&self) -> CountersSnapshot {
+		let mut drops = [0; DropReason::ALL.len()];
+		for (value, counter) in drops.iter_mut().zip(&self.drops) {
+			*value = counter.get();
+		}
+		CountersSnapshot {
+			pkts_in: self.pkts_in.get(),
+			bytes_in: self.bytes_in.get(),
+			pkts_out: self.pkts_out.get(),
+			bytes_out: self.bytes_out.get(),
+			drops,
+			ring_full: self.ring_full.get(),
+			alloc_failures: self.alloc_failures.get(),
+		}
+	}
+}
+
+/// Bytes in `pkts`, every segment included
+#[inline]
+pub fn total_len(pkts: &[Mbuf]) -> u64 {
+	pkts.iter().map(|pkt| pkt.pkt_len() as u64).sum()
+}
+
+/// Values of a `Counters` block at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CountersSnapshot {
+	pub pkts_in: u64,
+	pub bytes_in: u64,
+	pub pkts_out: u64,
+	pub bytes_out: u64,
+	pub drops: [u64; DropReason::ALL.len()],
+	pub ring_full: u64,
+	pub alloc_failures: u64,
+}
+
+impl CountersSnapshot {
+	/// Packets dropped for `reason`
+	pub fn drops(&self, reason: DropReason) -> u64 {
+		self.drops[reason as usize]
+	}
+
+	/// Packets dropped for any reason
+	pub fn total_drops(&self) -> u64 {
+		self.drops.iter().sum()
+	}
+}
+
+impl fmt::Display for CountersSnapshot {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"in {} pkts ({} B), out {} pkts ({} B), ring full {}, alloc failures {}",
+			self.pkts_in,
+			self.bytes_in,
+			self.pkts_out,
+			self.bytes_out,
+			self.ring_full,
+			self.alloc_failures
+		)?;
+		for reason in DropReason::ALL.iter().filter(|&&reason| self.drops(reason) != 0) {
+			write!(f, ", {} {}", reason.name(), self.drops(*reason))?;
+		}
+		Ok(())
+	}
+}
+
+/// Every counter the engine keeps
+#[repr(C)]
+pub struct EngineCounters {
+	ports: [Counters; MAX_COUNTED_PORTS],
+	stages: [Counters; Stage::ALL.len()],
+	clients: [Counters; MAX_COUNTED_CLIENTS],
+}
+
+unsafe impl Shareable for Counter {}
+unsafe impl Shareable for Counters {}
+unsafe impl Shareable for EngineCounters {}
+
+impl EngineCounters {
+	/// Name of the memzone the engine keeps its counters in
+	pub const MEMZONE: &'static str = "L3ENGINE_COUNTERS";
+
+	/// Counters of port `id`, if it is low enough to be counted
+	#[inline]
+	pub fn port(&self, id: u16) -> Option<&Counters> {
+		self.ports.get(id as usize)
+	}
+
+	#[inline]
+	pub fn stage(&self, stage: Stage) -> &Counters {
+		&self.stages[stage as usize]
+	}
+
+	/// Counters of client `id`, if it is low enough to be counted
+	#[inline]
+	pub fn client(&self, id: u16) -> Option<&Counters> {
+		self.clients.get(id as usize)
+	}
+}
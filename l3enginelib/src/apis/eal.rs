@@ -0,0 +1,498 @@
+//! A typed builder for the arguments passed to `rte_eal_init`
+//!
+//! `EalArgs` renders to the argv expected by the EAL, program name included,
+//! so callers no longer assemble string vectors by hand.
+//! Options follow the DPDK 19.11 spelling (`-w`/`-b` for the PCI allow/block lists).
+
+use std::fmt;
+
+/// The DPDK process type (`--proc-type`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcType {
+	Primary,
+	Secondary,
+	Auto,
+}
+
+impl fmt::Display for ProcType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ProcType::Primary => write!(f, "primary"),
+			ProcType::Secondary => write!(f, "secondary"),
+			ProcType::Auto => write!(f, "auto"),
+		}
+	}
+}
+
+/// Global EAL log level (`--log-level`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+	Emergency = 1,
+	Alert = 2,
+	Critical = 3,
+	Error = 4,
+	Warning = 5,
+	Notice = 6,
+	Info = 7,
+	Debug = 8,
+}
+
+/// Whether an EAL option takes a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OptValue {
+	No,
+	Required,
+	Optional,
+}
+
+/// Short options of the DPDK 19.11 EAL
+const SHORT_OPTIONS: &[(char, OptValue)] = &[
+	('b', OptValue::Required),
+	('c', OptValue::Required),
+	('d', OptValue::Required),
+	('h', OptValue::No),
+	('l', OptValue::Required),
+	('m', OptValue::Required),
+	('n', OptValue::Required),
+	('r', OptValue::Required),
+	('s', OptValue::Required),
+	('S', OptValue::Required),
+	('v', OptValue::No),
+	('w', OptValue::Required),
+];
+
+/// Long options of the DPDK 19.11 EAL
+const LONG_OPTIONS: &[(&str, OptValue)] = &[
+	("base-virtaddr", OptValue::Required),
+	("create-uio-dev", OptValue::No),
+	("file-prefix", OptValue::Required),
+	("help", OptValue::No),
+	("huge-dir", OptValue::Required),
+	("huge-unlink", OptValue::No),
+	("in-memory", OptValue::No),
+	("iova-mode", OptValue::Required),
+	("lcores", OptValue::Required),
+	("legacy-mem", OptValue::No),
+	("log-level", OptValue::Required),
+	("master-lcore", OptValue::Required),
+	("match-allocations", OptValue::No),
+	("mbuf-pool-ops-name", OptValue::Required),
+	("no-hpet", OptValue::No),
+	("no-huge", OptValue::No),
+	("no-pci", OptValue::No),
+	("no-shconf", OptValue::No),
+	("no-telemetry", OptValue::No),
+	("pci-blacklist", OptValue::Required),
+	("pci-whitelist", OptValue::Required),
+	("proc-type", OptValue::Required),
+	("single-file-segments", OptValue::No),
+	("socket-limit", OptValue::Required),
+	("socket-mem", OptValue::Required),
+	("syslog", OptValue::Optional),
+	("telemetry", OptValue::No),
+	("vdev", OptValue::Required),
+	("vfio-intr", OptValue::Required),
+	("vmware-tsc-map", OptValue::No),
+];
+
+/// Arguments for `eal_init`
+///
+/// ```ignore
+/// let args = EalArgs::new("l3enginebin")
+///     .lcores(&[0, 1])
+///     .memory_channels(4)
+///     .proc_type(ProcType::Primary)
+///     .vdev("net_ring0")
+///     .no_pci();
+/// eal_init(args)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EalArgs {
+	program: String,
+	lcores: Vec<u32>,
+	memory_channels: Option<u32>,
+	proc_type: Option<ProcType>,
+	file_prefix: Option<String>,
+	base_virtaddr: Option<u64>,
+	vdevs: Vec<String>,
+	no_huge: bool,
+	no_pci: bool,
+	allow: Vec<String>,
+	block: Vec<String>,
+	socket_mem: Vec<u32>,
+	log_level: Option<LogLevel>,
+	extra: Vec<String>,
+}
+
+impl EalArgs {
+	/// Start an argument list for the program `program` (argv[0])
+	pub fn new(program: &str) -> Self {
+		Self {
+			program: String::from(program),
+			..Default::default()
+		}
+	}
+
+	/// Lcores to run on (`-l`)
+	pub fn lcores(mut self, lcores: &[u32]) -> Self {
+		self.lcores = lcores.to_vec();
+		self
+	}
+
+	/// Number of memory channels (`-n`)
+	pub fn memory_channels(mut self, channels: u32) -> Self {
+		self.memory_channels = Some(channels);
+		self
+	}
+
+	/// Process type (`--proc-type`)
+	pub fn proc_type(mut self, proc_type: ProcType) -> Self {
+		self.proc_type = Some(proc_type);
+		self
+	}
+
+	/// Prefix of the hugepage files; processes sharing memory must use the same one (`--file-prefix`)
+	pub fn file_prefix(mut self, prefix: &str) -> Self {
+		self.file_prefix = Some(String::from(prefix));
+		self
+	}
+
+	/// Base virtual address of the shared memory mappings (`--base-virtaddr`)
+	pub fn base_virtaddr(mut self, addr: u64) -> Self {
+		self.base_virtaddr = Some(addr);
+		self
+	}
+
+	/// Add a virtual device, e.g. `net_ring0` or `net_pcap0,iface=eth0` (`--vdev`)
+	pub fn vdev(mut self, devargs: &str) -> Self {
+		self.vdevs.push(String::from(devargs));
+		self
+	}
+
+	/// Run without hugepages (`--no-huge`)
+	pub fn no_huge(mut self) -> Self {
+		self.no_huge = true;
+		self
+	}
+
+	/// Do not probe the PCI bus (`--no-pci`)
+	pub fn no_pci(mut self) -> Self {
+		self.no_pci = true;
+		self
+	}
+
+	/// Only probe this PCI device (`-w`)
+	pub fn allow(mut self, pci_addr: &str) -> Self {
+		self.allow.push(String::from(pci_addr));
+		self
+	}
+
+	/// Never probe this PCI device (`-b`)
+	pub fn block(mut self, pci_addr: &str) -> Self {
+		self.block.push(String::from(pci_addr));
+		self
+	}
+
+	/// Megabytes of hugepage memory to preallocate on each socket, in socket order (`--socket-mem`)
+	pub fn socket_mem(mut self, mb_per_socket: &[u32]) -> Self {
+		self.socket_mem = mb_per_socket.to_vec();
+		self
+	}
+
+	/// Global log level (`--log-level`)
+	pub fn log_level(mut self, level: LogLevel) -> Self {
+		self.log_level = Some(level);
+		self
+	}
+
+	/// Append an argument that has no typed setter; it is passed through verbatim
+	pub fn arg(mut self, arg: &str) -> Self {
+		self.extra.push(String::from(arg));
+		self
+	}
+
+	/// Render the arguments to an argv, program name first
+	pub fn to_argv(&self) -> Vec<String> {
+		let mut argv = vec![self.program.clone()];
+		if !self.lcores.is_empty() {
+			argv.push(format!("-l{}", Self::lcore_list(&self.lcores)));
+		}
+		if let Some(n) = self.memory_channels {
+			argv.push(format!("-n{}", n));
+		}
+		if let Some(proc_type) = self.proc_type {
+			argv.push(format!("--proc-type={}", proc_type));
+		}
+		if let Some(prefix) = &self.file_prefix {
+			argv.push(format!("--file-prefix={}", prefix));
+		}
+		if let Some(addr) = self.base_virtaddr {
+			argv.push(format!("--base-virtaddr={:#x}", addr));
+		}
+		for vdev in &self.vdevs {
+			argv.push(format!("--vdev={}", vdev));
+		}
+		if self.no_huge {
+			argv.push(String::from("--no-huge"));
+		}
+		if self.no_pci {
+			argv.push(String::from("--no-pci"));
+		}
+		for addr in &self.allow {
+			argv.push(format!("-w{}", addr));
+		}
+		for addr in &self.block {
+			argv.push(format!("-b{}", addr));
+		}
+		if !self.socket_mem.is_empty() {
+			let mem = self
+				.socket_mem
+				.iter()
+				.map(|mb| mb.to_string())
+				.collect::<Vec<_>>();
+			argv.push(format!("--socket-mem={}", mem.join(",")));
+		}
+		if let Some(level) = self.log_level {
+			argv.push(format!("--log-level={}", level as u8));
+		}
+		argv.extend(self.extra.iter().cloned());
+		argv
+	}
+
+	/// Find the argument of `argv` the EAL rejects, with the reason
+	///
+	/// Spots options the EAL does not know, options missing their value or given one they
+	/// do not take, and malformed values of the options `EalArgs` has setters for.
+	/// Meant to explain a failed `rte_eal_init`: options newer than DPDK 19.11 are not known.
+	/// A malformed value given as an argument of its own is reported rather than its option.
+	pub fn find_rejected(argv: &[String]) -> Option<(usize, &'static str)> {
+		let mut i = 1;
+		while i < argv.len() {
+			let arg = argv[i].as_str();
+			if arg == "--" {
+				// the application's own arguments follow
+				return None;
+			}
+			if let Some(long) = arg.strip_prefix("--") {
+				let (name, inline) = match long.find('=') {
+					Some(eq) => (&long[..eq], Some(&long[eq + 1..])),
+					None => (long, None),
+				};
+				let (name, kind) = match Self::long_option(name) {
+					Ok(option) => option,
+					Err(reason) => return Some((i, reason)),
+				};
+				let value = match (kind, inline) {
+					(OptValue::No, Some(_)) => return Some((i, "option takes no value")),
+					(OptValue::Required, None) => match argv.get(i + 1) {
+						Some(value) => {
+							i += 1;
+							value.as_str()
+						}
+						None => return Some((i, "option needs a value")),
+					},
+					(_, value) => value.unwrap_or_default(),
+				};
+				if kind != OptValue::No && !Self::valid_value(name, value) {
+					return Some((i, "malformed value"));
+				}
+			} else if let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) {
+				for (pos, flag) in flags.char_indices() {
+					let kind = match SHORT_OPTIONS.iter().find(|(c, _)| *c == flag) {
+						Some(&(_, kind)) => kind,
+						None => return Some((i, "unknown option")),
+					};
+					if kind == OptValue::No {
+						continue;
+					}
+					// the value is the rest of the argument, or the next one
+					let rest = &flags[pos + flag.len_utf8()..];
+					let value = if rest.is_empty() {
+						match argv.get(i + 1) {
+							Some(value) => {
+								i += 1;
+								value.as_str()
+							}
+							None => return Some((i, "option needs a value")),
+						}
+					} else {
+						rest
+					};
+					if !Self::valid_value(&flag.to_string(), value) {
+						return Some((i, "malformed value"));
+					}
+					break;
+				}
+			}
+			// anything else is left to the application, as getopt does
+			i += 1;
+		}
+		None
+	}
+
+	/// The long option `name` stands for, as getopt accepts unambiguous abbreviations
+	fn long_option(name: &str) -> Result<(&'static str, OptValue), &'static str> {
+		if let Some(&option) = LONG_OPTIONS.iter().find(|(long, _)| *long == name) {
+			return Ok(option);
+		}
+		let mut candidates = LONG_OPTIONS
+			.iter()
+			.filter(|(long, _)| !name.is_empty() && long.starts_with(name));
+		match (candidates.next(), candidates.next()) {
+			(Some(&option), None) => Ok(option),
+			(Some(_), Some(_)) => Err("ambiguous option"),
+			_ => Err("unknown option"),
+		}
+	}
+
+	/// Is `value` well formed for the option `name`, short options by their letter
+	fn valid_value(name: &str, value: &str) -> bool {
+		let is_list = |v: &str, extra: char| {
+			!v.is_empty() && v.chars().all(|c| c.is_ascii_digit() || c == ',' || c == extra)
+		};
+		match name {
+			"l" => is_list(value, '-'),
+			"n" => value.parse::<u32>().is_ok_and(|n| n > 0),
+			"r" => value.parse::<u32>().is_ok_and(|n| (1..=16).contains(&n)),
+			"proc-type" => ["primary", "secondary", "auto"].contains(&value),
+			"socket-mem" | "socket-limit" => is_list(value, ','),
+			"base-virtaddr" => {
+				let hex = value.trim_start_matches("0x").trim_start_matches("0X");
+				u64::from_str_radix(hex, 16).is_ok()
+			}
+			"syslog" => true,
+			_ => !value.is_empty(),
+		}
+	}
+
+	/// Render lcores in EAL list notation, collapsing consecutive ids into ranges
+	fn lcore_list(lcores: &[u32]) -> String {
+		let mut sorted = lcores.to_vec();
+		sorted.sort_unstable();
+		sorted.dedup();
+
+		let mut parts = Vec::new();
+		let mut i = 0;
+		while i < sorted.len() {
+			let lo = sorted[i];
+			let mut hi = lo;
+			while i + 1 < sorted.len() && sorted[i + 1] == hi + 1 {
+				hi += 1;
+				i += 1;
+			}
+			if lo == hi {
+				parts.push(lo.to_string());
+			} else {
+				parts.push(format!("{}-{}", lo, hi));
+			}
+			i += 1;
+		}
+		parts.join(",")
+	}
+}
+
+impl From<EalArgs> for Vec<String> {
+	fn from(args: EalArgs) -> Self {
+		args.to_argv()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn argv(args: &[&str]) -> Vec<String> {
+		args.iter().map(|arg| String::from(*arg)).collect()
+	}
+
+	#[test]
+	fn renders_every_setter() {
+		let args = EalArgs::new("prog")
+			.lcores(&[3, 0, 1, 2, 5])
+			.memory_channels(4)
+			.proc_type(ProcType::Secondary)
+			.file_prefix("l3")
+			.base_virtaddr(0x7f000000000)
+			.vdev("net_ring0")
+			.no_huge()
+			.no_pci()
+			.allow("0000:01:00.0")
+			.block("0000:02:00.0")
+			.socket_mem(&[1024, 512])
+			.log_level(LogLevel::Debug)
+			.arg("--in-memory");
+		assert_eq!(
+			args.to_argv(),
+			argv(&[
+				"prog",
+				"-l0-3,5",
+				"-n4",
+				"--proc-type=secondary",
+				"--file-prefix=l3",
+				"--base-virtaddr=0x7f000000000",
+				"--vdev=net_ring0",
+				"--no-huge",
+				"--no-pci",
+				"-w0000:01:00.0",
+				"-b0000:02:00.0",
+				"--socket-mem=1024,512",
+				"--log-level=8",
+				"--in-memory",
+			])
+		);
+		assert_eq!(EalArgs::find_rejected(&args.to_argv()), None);
+	}
+
+	#[test]
+	fn renders_only_the_program_by_default() {
+		assert_eq!(EalArgs::new("prog").to_argv(), argv(&["prog"]));
+	}
+
+	#[test]
+	fn rejects_unknown_options() {
+		let rejected = EalArgs::find_rejected(&argv(&["prog", "-l0-1", "--no-such-option"]));
+		assert_eq!(rejected, Some((2, "unknown option")));
+		let rejected = EalArgs::find_rejected(&argv(&["prog", "-x"]));
+		assert_eq!(rejected, Some((1, "unknown option")));
+	}
+
+	#[test]
+	fn rejects_options_missing_their_value() {
+		let rejected = EalArgs::find_rejected(&argv(&["prog", "-n4", "--file-prefix"]));
+		assert_eq!(rejected, Some((2, "option needs a value")));
+		let rejected = EalArgs::find_rejected(&argv(&["prog", "-l"]));
+		assert_eq!(rejected, Some((1, "option needs a value")));
+		let rejected = EalArgs::find_rejected(&argv(&["prog", "--no-pci=yes"]));
+		assert_eq!(rejected, Some((1, "option takes no value")));
+	}
+
+	#[test]
+	fn takes_values_from_the_next_argument() {
+		let args = argv(&["prog", "-l", "0-1", "--proc-type", "primary", "-n", "4"]);
+		assert_eq!(EalArgs::find_rejected(&args), None);
+	}
+
+	#[test]
+	fn rejects_malformed_values() {
+		let rejected = EalArgs::find_rejected(&argv(&["prog", "-n0"]));
+		assert_eq!(rejected, Some((1, "malformed value")));
+		let rejected = EalArgs::find_rejected(&argv(&["prog", "-n4", "--proc-type=main"]));
+		assert_eq!(rejected, Some((2, "malformed value")));
+		// a value given separately is reported itself
+		let rejected = EalArgs::find_rejected(&argv(&["prog", "--base-virtaddr", "0xzz"]));
+		assert_eq!(rejected, Some((2, "malformed value")));
+	}
+
+	#[test]
+	fn resolves_long_option_prefixes() {
+		assert_eq!(EalArgs::find_rejected(&argv(&["prog", "--proc=auto"])), None);
+		let rejected = EalArgs::find_rejected(&argv(&["prog", "--no"]));
+		assert_eq!(rejected, Some((1, "ambiguous option")));
+	}
+
+	#[test]
+	fn leaves_application_arguments_alone() {
+		let args = argv(&["prog", "-l0", "--", "--anything", "-x"]);
+		assert_eq!(EalArgs::find_rejected(&args), None);
+	}
+}
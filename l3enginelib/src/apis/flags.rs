@@ -0,0 +1,136 @@
+//! Sets of DPDK bit flags
+//!
+//! `Flags<K>` holds a word of flags of the kind `K`: the offloads of a port
+//! (`Offloads<D>`) or the `ol_flags` of an mbuf (`OffloadFlags`).
+//! The kinds only differ in the flags they name and in their own helpers.
+
+use std::{fmt, hash::Hash, marker::PhantomData, ops};
+
+/// A kind of flag word
+pub trait FlagKind: Copy + Eq + Hash {
+	/// Name and value of every flag `Display` shows; a value may span several bits
+	const NAMES: &'static [(&'static str, u64)];
+	/// What the flags are, for `Debug`
+	const KIND: &'static str;
+}
+
+/// A set of flags of the kind `K`
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Flags<K> {
+	bits: u64,
+	_kind: PhantomData<K>,
+}
+
+impl<K: FlagKind> Flags<K> {
+	pub const fn from_bits(bits: u64) -> Self {
+		Self {
+			bits,
+			_kind: PhantomData,
+		}
+	}
+
+	pub const fn empty() -> Self {
+		Self::from_bits(0)
+	}
+
+	#[inline]
+	pub const fn bits(&self) -> u64 {
+		self.bits
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.bits == 0
+	}
+
+	/// Returns true if every flag of `other` is set
+	#[inline]
+	pub fn contains(&self, other: Self) -> bool {
+		self.bits & other.bits == other.bits
+	}
+
+	/// Returns true if any flag of `other` is set
+	#[inline]
+	pub fn intersects(&self, other: Self) -> bool {
+		self.bits & other.bits != 0
+	}
+
+	#[inline]
+	pub fn intersection(&self, other: Self) -> Self {
+		Self::from_bits(self.bits & other.bits)
+	}
+
+	#[inline]
+	pub fn union(&self, other: Self) -> Self {
+		Self::from_bits(self.bits | other.bits)
+	}
+
+	/// The flags of the set that are not in `other`
+	#[inline]
+	pub fn difference(&self, other: Self) -> Self {
+		Self::from_bits(self.bits & !other.bits)
+	}
+
+	#[inline]
+	pub fn insert(&mut self, other: Self) {
+		self.bits |= other.bits;
+	}
+
+	#[inline]
+	pub fn remove(&mut self, other: Self) {
+		self.bits &= !other.bits;
+	}
+
+	/// Names of the flags in the set; unknown flags are shown in hex
+	pub fn names(&self) -> Vec<String> {
+		let mut rest = *self;
+		let mut names = Vec::new();
+		for &(name, bits) in K::NAMES {
+			let flag = Self::from_bits(bits);
+			if !flag.is_empty() && rest.contains(flag) {
+				names.push(String::from(name));
+				rest.remove(flag);
+			}
+		}
+		if !rest.is_empty() {
+			names.push(format!("{:#x}", rest.bits));
+		}
+		names
+	}
+}
+
+impl<K: FlagKind> Default for Flags<K> {
+	fn default() -> Self {
+		Self::empty()
+	}
+}
+
+impl<K: FlagKind> ops::BitOr for Flags<K> {
+	type Output = Self;
+
+	#[inline]
+	fn bitor(self, other: Self) -> Self {
+		self.union(other)
+	}
+}
+
+impl<K: FlagKind> ops::BitAnd for Flags<K> {
+	type Output = Self;
+
+	#[inline]
+	fn bitand(self, other: Self) -> Self {
+		self.intersection(other)
+	}
+}
+
+impl<K: FlagKind> fmt::Display for Flags<K> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "[{}]", self.names().join(", "))
+	}
+}
+
+impl<K: FlagKind> fmt::Debug for Flags<K> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} {}", K::KIND, self)
+	}
+}
@@ -0,0 +1,733 @@
+//! Flow steering rules
+//!
+//! A `FlowRule` matches on Ethernet, IPv4/IPv6 and TCP/UDP fields and sends
+//! matching packets to an rx queue, drops them or marks them.
+//! `Port::add_flow` installs the rule with `rte_flow` when the PMD accepts it,
+//! e.g. on the TAP PMD or most physical NICs. Otherwise the rule is kept by the
+//! port and applied in software to every packet received with `Port::receive`,
+//! through `Port::apply_flows`.
+//!
+//! A software `Queue` rule cannot move a packet to another rx queue of the NIC;
+//! instead the packet is handed to the lcore polling that queue, which takes it
+//! in its next `Port::apply_flows` as if it had been received there.
+//!
+//! Priorities only order rules of the same kind: the NIC applies every hardware rule
+//! before software ever sees the packet, so a hardware rule acts before any software
+//! rule, whatever their priorities. A packet a hardware rule drops or steers never
+//! meets a software rule of a lower priority value.
+
+use libc::ENOSYS;
+use serde::{de, Deserialize, Deserializer};
+use std::{
+	ffi::CStr,
+	fmt,
+	net::IpAddr,
+	os::raw,
+	ptr::{self, NonNull},
+	str::FromStr,
+};
+
+use super::{current_lcore, Mbuf, Port, PortError};
+use crate::net::MacAddr;
+
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const ETHER_TYPE_IPV6: u16 = 0x86dd;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+const ETHER_HDR_LEN: usize = 14;
+
+/// What happens to packets matching a rule
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowAction {
+	/// Receive on this rx queue
+	Queue(u16),
+	/// Drop the packet
+	#[default]
+	Drop,
+	/// Receive as usual and mark with this id (see `Mbuf::mark`)
+	Mark(u32),
+}
+
+/// An IPv4 or IPv6 network, written `10.0.0.0/8` or as a bare address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpPrefix {
+	pub addr: IpAddr,
+	pub len: u8,
+}
+
+impl IpPrefix {
+	pub fn new(addr: IpAddr, len: u8) -> Result<Self, String> {
+		let max = if addr.is_ipv4() { 32 } else { 128 };
+		if len > max {
+			return Err(format!("prefix length {} is longer than {}", len, max));
+		}
+		Ok(Self { addr, len })
+	}
+
+	/// The address bytes and the matching mask bytes, in network byte order
+	fn masked_octets(&self) -> (Vec<u8>, Vec<u8>) {
+		let addr = match self.addr {
+			IpAddr::V4(addr) => addr.octets().to_vec(),
+			IpAddr::V6(addr) => addr.octets().to_vec(),
+		};
+		let mask = (0..addr.len())
+			.map(|i| {
+				let bits = (self.len as u32).saturating_sub(i as u32 * 8);
+				!0xffu8.checked_shr(bits).unwrap_or(0)
+			})
+			.collect::<Vec<_>>();
+		let addr = addr.iter().zip(mask.iter()).map(|(a, m)| a & m).collect();
+		(addr, mask)
+	}
+
+	/// Returns true if `addr`, in network byte order, is in the network
+	fn contains(&self, addr: &[u8]) -> bool {
+		let (net, mask) = self.masked_octets();
+		net.len() == addr.len() && net.iter().zip(mask.iter()).zip(addr).all(|((n, m), a)| a & m == *n)
+	}
+}
+
+impl FromStr for IpPrefix {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (addr, len) = match s.find('/') {
+			Some(i) => (&s[..i], Some(&s[i + 1..])),
+			None => (s, None),
+		};
+		let addr = addr
+			.parse::<IpAddr>()
+			.map_err(|_| format!("{:?} is not an IP address", addr))?;
+		let len = match len {
+			Some(len) => len
+				.parse::<u8>()
+				.map_err(|_| format!("{:?} is not a prefix length", len))?,
+			None if addr.is_ipv4() => 32,
+			None => 128,
+		};
+		Self::new(addr, len)
+	}
+}
+
+impl fmt::Display for IpPrefix {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}/{}", self.addr, self.len)
+	}
+}
+
+impl<'de> Deserialize<'de> for IpPrefix {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let s = String::deserialize(deserializer)?;
+		s.parse().map_err(de::Error::custom)
+	}
+}
+
+/// A flow steering rule; fields left out match anything
+///
+/// The IP version comes from `ip_src`/`ip_dst`, then from `ether_type`;
+/// rules that name neither but match on `ip_proto` or ports match IPv4.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlowRule {
+	/// Rules with a lower value are matched first; hardware rules still act
+	/// before software ones (see the module documentation)
+	pub priority: u32,
+	pub eth_src: Option<MacAddr>,
+	pub eth_dst: Option<MacAddr>,
+	pub ether_type: Option<u16>,
+	pub ip_src: Option<IpPrefix>,
+	pub ip_dst: Option<IpPrefix>,
+	/// IP protocol / IPv6 next header; must be TCP (6) or UDP (17) to match on ports
+	pub ip_proto: Option<u8>,
+	pub src_port: Option<u16>,
+	pub dst_port: Option<u16>,
+	pub action: FlowAction,
+}
+
+impl FlowRule {
+	/// Check the fields are consistent with each other and with a port of `rx_queues` queues
+	pub fn validate(&self, rx_queues: u16) -> Result<(), String> {
+		if let (Some(src), Some(dst)) = (self.ip_src, self.ip_dst) {
+			if src.addr.is_ipv4() != dst.addr.is_ipv4() {
+				return Err(String::from("ip_src and ip_dst are of different IP versions"));
+			}
+		}
+		if self.needs_ip() {
+			let ether_type = if self.is_ipv6() {
+				ETHER_TYPE_IPV6
+			} else {
+				ETHER_TYPE_IPV4
+			};
+			if self.ether_type.is_some_and(|t| t != ether_type) {
+				return Err(format!(
+					"ether_type {:#06x} does not match the IP fields",
+					self.ether_type.unwrap_or_default()
+				));
+			}
+		}
+		if (self.src_port.is_some() || self.dst_port.is_some())
+			&& self.ip_proto != Some(IPPROTO_TCP)
+			&& self.ip_proto != Some(IPPROTO_UDP)
+		{
+			return Err(String::from("matching on ports needs ip_proto 6 (tcp) or 17 (udp)"));
+		}
+		if let FlowAction::Queue(queue) = self.action {
+			if queue >= rx_queues {
+				return Err(format!("queue {} is not below rx_queues ({})", queue, rx_queues));
+			}
+		}
+		Ok(())
+	}
+
+	fn needs_ip(&self) -> bool {
+		self.ip_src.is_some()
+			|| self.ip_dst.is_some()
+			|| self.ip_proto.is_some()
+			|| self.src_port.is_some()
+			|| self.dst_port.is_some()
+	}
+
+	fn is_ipv6(&self) -> bool {
+		match self.ip_src.or(self.ip_dst) {
+			Some(prefix) => prefix.addr.is_ipv6(),
+			None => self.ether_type == Some(ETHER_TYPE_IPV6),
+		}
+	}
+
+	/// Returns true if the ethernet frame `frame` matches the rule
+	pub fn matches(&self, frame: &[u8]) -> bool {
+		if frame.len() < ETHER_HDR_LEN {
+			return false;
+		}
+		if self.eth_dst.is_some_and(|mac| frame[..6] != mac.octets()) {
+			return false;
+		}
+		if self.eth_src.is_some_and(|mac| frame[6..12] != mac.octets()) {
+			return false;
+		}
+		let ether_type = u16::from_be_bytes([frame[12], frame[13]]);
+		if self.ether_type.is_some_and(|t| t != ether_type) {
+			return false;
+		}
+		if !self.needs_ip() {
+			return true;
+		}
+
+		let ip = &frame[ETHER_HDR_LEN..];
+		// (source, destination, protocol, L4 header if present)
+		let (src, dst, proto, l4) = if self.is_ipv6() {
+			if ether_type != ETHER_TYPE_IPV6 || ip.len() < 40 {
+				return false;
+			}
+			(&ip[8..24], &ip[24..40], ip[6], &ip[40..])
+		} else {
+			if ether_type != ETHER_TYPE_IPV4 || ip.len() < 20 {
+				return false;
+			}
+			let ihl = (ip[0] & 0x0f) as usize * 4;
+			let frag_offset = u16::from_be_bytes([ip[6], ip[7]]) & 0x1fff;
+			// only the first fragment carries the L4 header
+			let l4 = match ip.get(ihl..) {
+				Some(l4) if frag_offset == 0 => l4,
+				_ => &[][..],
+			};
+			(&ip[12..16], &ip[16..20], ip[9], l4)
+		};
+		if self.ip_src.is_some_and(|prefix| !prefix.contains(src)) {
+			return false;
+		}
+		if self.ip_dst.is_some_and(|prefix| !prefix.contains(dst)) {
+			return false;
+		}
+		if self.ip_proto.is_some_and(|p| p != proto) {
+			return false;
+		}
+		if self.src_port.is_none() && self.dst_port.is_none() {
+			return true;
+		}
+		if l4.len() < 4 {
+			return false;
+		}
+		let sport = u16::from_be_bytes([l4[0], l4[1]]);
+		let dport = u16::from_be_bytes([l4[2], l4[3]]);
+		self.src_port.unwrap_or(sport) == sport && self.dst_port.unwrap_or(dport) == dport
+	}
+}
+
+/// A rule installed on a port by `Port::add_flow`
+#[derive(Debug)]
+pub enum FlowHandle {
+	/// The rule runs on the NIC
+	Hardware(NonNull<dpdk_sys::rte_flow>),
+	/// The rule is applied by `Port::apply_flows`; the value identifies it on the port
+	Software(u64),
+}
+
+unsafe impl Send for FlowHandle {}
+
+/// Spec and mask of every pattern item; they must outlive the calls to `rte_flow`
+#[derive(Default)]
+struct FlowSpecs {
+	eth: [dpdk_sys::rte_flow_item_eth; 2],
+	ipv4: [dpdk_sys::rte_flow_item_ipv4; 2],
+	ipv6: [dpdk_sys::rte_flow_item_ipv6; 2],
+	tcp: [dpdk_sys::rte_flow_item_tcp; 2],
+	udp: [dpdk_sys::rte_flow_item_udp; 2],
+	queue: dpdk_sys::rte_flow_action_queue,
+	mark: dpdk_sys::rte_flow_action_mark,
+}
+
+impl FlowSpecs {
+	fn new(rule: &FlowRule) -> Self {
+		let mut specs = Self::default();
+		let [eth, eth_mask] = &mut specs.eth;
+		if let Some(mac) = rule.eth_dst {
+			eth.dst = MacAddr::into_ether_addr(mac);
+			eth_mask.dst = MacAddr::into_ether_addr(MacAddr::BROADCAST);
+		}
+		if let Some(mac) = rule.eth_src {
+			eth.src = MacAddr::into_ether_addr(mac);
+			eth_mask.src = MacAddr::into_ether_addr(MacAddr::BROADCAST);
+		}
+		if let Some(ether_type) = rule.ether_type {
+			eth.type_ = ether_type.to_be();
+			eth_mask.type_ = u16::MAX;
+		}
+
+		let [v4, v4_mask] = &mut specs.ipv4;
+		let [v6, v6_mask] = &mut specs.ipv6;
+		if let Some(proto) = rule.ip_proto {
+			v4.hdr.next_proto_id = proto;
+			v4_mask.hdr.next_proto_id = u8::MAX;
+			v6.hdr.proto = proto;
+			v6_mask.hdr.proto = u8::MAX;
+		}
+		let prefixes = [(rule.ip_src, true), (rule.ip_dst, false)];
+		for (prefix, is_src) in prefixes.iter() {
+			let (addr, mask) = match prefix {
+				Some(prefix) => prefix.masked_octets(),
+				None => continue,
+			};
+			if addr.len() == 4 {
+				let (addr, mask) = (Self::be32(&addr), Self::be32(&mask));
+				if *is_src {
+					v4.hdr.src_addr = addr;
+					v4_mask.hdr.src_addr = mask;
+				} else {
+					v4.hdr.dst_addr = addr;
+					v4_mask.hdr.dst_addr = mask;
+				}
+			} else if *is_src {
+				v6.hdr.src_addr.copy_from_slice(&addr);
+				v6_mask.hdr.src_addr.copy_from_slice(&mask);
+			} else {
+				v6.hdr.dst_addr.copy_from_slice(&addr);
+				v6_mask.hdr.dst_addr.copy_from_slice(&mask);
+			}
+		}
+
+		let ports = [
+			rule.src_port.map(|p| (p.to_be(), u16::MAX)).unwrap_or_default(),
+			rule.dst_port.map(|p| (p.to_be(), u16::MAX)).unwrap_or_default(),
+		];
+		let [tcp, tcp_mask] = &mut specs.tcp;
+		(tcp.hdr.src_port, tcp_mask.hdr.src_port) = ports[0];
+		(tcp.hdr.dst_port, tcp_mask.hdr.dst_port) = ports[1];
+		let [udp, udp_mask] = &mut specs.udp;
+		(udp.hdr.src_port, udp_mask.hdr.src_port) = ports[0];
+		(udp.hdr.dst_port, udp_mask.hdr.dst_port) = ports[1];
+
+		match rule.action {
+			FlowAction::Queue(index) => specs.queue.index = index,
+			FlowAction::Mark(id) => specs.mark.id = id,
+			FlowAction::Drop => {}
+		}
+		specs
+	}
+
+	/// Read bytes in network order into a `rte_be32_t`
+	fn be32(bytes: &[u8]) -> u32 {
+		u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+	}
+
+	fn item<T>(type_: dpdk_sys::rte_flow_item_type::Type, spec_mask: &[T; 2]) -> dpdk_sys::rte_flow_item {
+		dpdk_sys::rte_flow_item {
+			type_,
+			spec: &spec_mask[0] as *const T as *const raw::c_void,
+			last: ptr::null(),
+			mask: &spec_mask[1] as *const T as *const raw::c_void,
+		}
+	}
+
+	/// The rte_flow pattern of `rule`, END item included
+	fn pattern(&self, rule: &FlowRule) -> Vec<dpdk_sys::rte_flow_item> {
+		use dpdk_sys::rte_flow_item_type::*;
+
+		let mut pattern = vec![Self::item(RTE_FLOW_ITEM_TYPE_ETH, &self.eth)];
+		if rule.needs_ip() {
+			if rule.is_ipv6() {
+				pattern.push(Self::item(RTE_FLOW_ITEM_TYPE_IPV6, &self.ipv6));
+			} else {
+				pattern.push(Self::item(RTE_FLOW_ITEM_TYPE_IPV4, &self.ipv4));
+			}
+			match rule.ip_proto {
+				Some(IPPROTO_TCP) => pattern.push(Self::item(RTE_FLOW_ITEM_TYPE_TCP, &self.tcp)),
+				Some(IPPROTO_UDP) => pattern.push(Self::item(RTE_FLOW_ITEM_TYPE_UDP, &self.udp)),
+				_ => {}
+			}
+		}
+		pattern.push(dpdk_sys::rte_flow_item {
+			type_: RTE_FLOW_ITEM_TYPE_END,
+			..Default::default()
+		});
+		pattern
+	}
+
+	/// The rte_flow actions of `rule`, END action included
+	fn actions(&self, rule: &FlowRule) -> Vec<dpdk_sys::rte_flow_action> {
+		use dpdk_sys::rte_flow_action_type::*;
+
+		let action = match rule.action {
+			FlowAction::Queue(_) => dpdk_sys::rte_flow_action {
+				type_: RTE_FLOW_ACTION_TYPE_QUEUE,
+				conf: &self.queue as *const _ as *const raw::c_void,
+			},
+			FlowAction::Drop => dpdk_sys::rte_flow_action {
+				type_: RTE_FLOW_ACTION_TYPE_DROP,
+				conf: ptr::null(),
+			},
+			FlowAction::Mark(_) => dpdk_sys::rte_flow_action {
+				type_: RTE_FLOW_ACTION_TYPE_MARK,
+				conf: &self.mark as *const _ as *const raw::c_void,
+			},
+		};
+		vec![
+			action,
+			dpdk_sys::rte_flow_action {
+				type_: RTE_FLOW_ACTION_TYPE_END,
+				conf: ptr::null(),
+			},
+		]
+	}
+}
+
+/// Describe an `rte_flow_error` for logging
+fn flow_error_message(error: &dpdk_sys::rte_flow_error) -> String {
+	if error.message.is_null() {
+		return format!("error type {}", error.type_);
+	}
+	unsafe { CStr::from_ptr(error.message) }
+		.to_string_lossy()
+		.into_owned()
+}
+
+impl Port {
+	/// Install `rule` on the NIC, or in software if the PMD cannot run it
+	pub fn add_flow(&mut self, rule: FlowRule) -> Result<FlowHandle, PortError> {
+		if let Err(e) = rule.validate(self.rx_queue_count()?) {
+			log::error!("port {}: bad flow rule: {}", self.id, e);
+			return Err(PortError::Invalid);
+		}
+
+		let specs = FlowSpecs::new(&rule);
+		let pattern = specs.pattern(&rule);
+		let actions = specs.actions(&rule);
+		let mut attr = dpdk_sys::rte_flow_attr {
+			priority: rule.priority,
+			..Default::default()
+		};
+		attr.set_ingress(1);
+		let mut error = dpdk_sys::rte_flow_error::default();
+
+		let ret = unsafe {
+			dpdk_sys::rte_flow_validate(
+				self.id,
+				&attr,
+				pattern.as_ptr(),
+				actions.as_ptr(),
+				&mut error,
+			)
+		};
+		if ret == 0 {
+			let flow = unsafe {
+				dpdk_sys::rte_flow_create(
+					self.id,
+					&attr,
+					pattern.as_ptr(),
+					actions.as_ptr(),
+					&mut error,
+				)
+			};
+			if let Some(flow) = NonNull::new(flow) {
+				return Ok(FlowHandle::Hardware(flow));
+			}
+		}
+
+		let reason = flow_error_message(&error);
+		log::info!("port {}: PMD rejected flow rule ({}); applying it in software", self.id, reason);
+		let id = self.next_sw_flow;
+		self.next_sw_flow += 1;
+		let pos = self
+			.sw_flows
+			.iter()
+			.position(|(_, r)| r.priority > rule.priority)
+			.unwrap_or(self.sw_flows.len());
+		self.sw_flows.insert(pos, (id, rule));
+		Ok(FlowHandle::Software(id))
+	}
+
+	/// Remove a rule installed by `add_flow`
+	pub fn remove_flow(&mut self, flow: FlowHandle) -> Result<(), PortError> {
+		match flow {
+			FlowHandle::Hardware(flow) => {
+				let mut error = dpdk_sys::rte_flow_error::default();
+				match unsafe { dpdk_sys::rte_flow_destroy(self.id, flow.as_ptr(), &mut error) } {
+					0 => Ok(()),
+					_ => {
+						log::error!(
+							"port {}: cannot remove flow rule: {}",
+							self.id,
+							flow_error_message(&error)
+						);
+						Err(PortError::new())
+					}
+				}
+			}
+			FlowHandle::Software(id) => {
+				self.sw_flows.retain(|(i, _)| *i != id);
+				Ok(())
+			}
+		}
+	}
+
+	/// Remove every rule, in hardware and in software
+	pub fn flush_flows(&mut self) -> Result<(), PortError> {
+		self.sw_flows.clear();
+		let mut error = dpdk_sys::rte_flow_error::default();
+		match unsafe { dpdk_sys::rte_flow_flush(self.id, &mut error) } {
+			0 => Ok(()),
+			// the PMD has no flow support, so there was nothing to flush
+			ret if -ret == ENOSYS => Ok(()),
+			_ => Err(PortError::new()),
+		}
+	}
+
+	/// Number of rx queues the port is configured with
+	fn rx_queue_count(&self) -> Result<u16, PortError> {
+		let mut dev_info = dpdk_sys::rte_eth_dev_info::default();
+		match unsafe { dpdk_sys::rte_eth_dev_info_get(self.id, &mut dev_info) } {
+			0 => Ok(dev_info.nb_rx_queues),
+			_ => Err(PortError::new()),
+		}
+	}
+
+	/// Apply the software rules to packets the calling lcore received on this port
+	///
+	/// Dropped packets are freed; marked packets get their mark set as the NIC would set it;
+	/// packets for another rx queue are handed to the lcore polling it.
+	/// Returns the packets to process on this lcore, including those other lcores steered
+	/// to its queue, and the number of packets dropped.
+	pub fn apply_flows(&self, pkts: Vec<Mbuf>) -> (Vec<Mbuf>, usize) {
+		let own_queue = self.rx_queue(current_lcore());
+		let mut kept = Vec::with_capacity(pkts.len());
+		let mut dropped = Vec::new();
+		if self.sw_flows.is_empty() {
+			kept = pkts;
+		} else {
+			self.filter(pkts, own_queue, &mut kept, &mut dropped);
+		}
+		if let Some(steered) = own_queue.and_then(|q| self.steered.get(q as usize)) {
+			// only what is there now, so a busy sender cannot keep this lcore here
+			for _ in 0..steered.len() {
+				match steered.pop() {
+					Some(pkt) => kept.push(pkt),
+					None => break,
+				}
+			}
+		}
+		let count = dropped.len();
+		if !dropped.is_empty() {
+			Mbuf::free_bulk(dropped);
+		}
+		(kept, count)
+	}
+
+	/// Free the packets steered to rx queues whose lcores did not take them; returns how many
+	///
+	/// The lcores polling the port must have stopped.
+	pub fn drain_steered(&self) -> usize {
+		let mut count = 0;
+		for queue in self.steered.iter() {
+			while let Some(pkt) = queue.pop() {
+				drop(pkt);
+				count += 1;
+			}
+		}
+		count
+	}
+
+	/// Sort `pkts` received on `own_queue` by the action of the first software rule they match
+	fn filter(
+		&self,
+		pkts: Vec<Mbuf>,
+		own_queue: Option<u16>,
+		kept: &mut Vec<Mbuf>,
+		dropped: &mut Vec<Mbuf>,
+	) {
+		for mut pkt in pkts {
+			let action = {
+				let frame = match pkt.read_data_slice::<u8>(0, pkt.data_len()) {
+					Ok(frame) => unsafe { frame.as_ref() },
+					Err(_) => &[][..],
+				};
+				self.sw_flows
+					.iter()
+					.find(|(_, rule)| rule.matches(frame))
+					.map(|(_, rule)| rule.action)
+			};
+			match action {
+				Some(FlowAction::Drop) => dropped.push(pkt),
+				Some(FlowAction::Mark(id)) => {
+					pkt.set_mark(id);
+					kept.push(pkt);
+				}
+				Some(FlowAction::Queue(q)) if Some(q) != own_queue => {
+					match self.steered.get(q as usize) {
+						Some(steered) => steered.push(pkt),
+						None => kept.push(pkt),
+					}
+				}
+				_ => kept.push(pkt),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::net::{testing::ipv4_frame, IpProtocol};
+
+	fn rule(toml: &str) -> FlowRule {
+		toml::from_str(toml).unwrap()
+	}
+
+	/// A UDP datagram from port 1234 to port 4789, from 10.0.0.1 to 10.0.0.2
+	fn udp_frame() -> Vec<u8> {
+		ipv4_frame(IpProtocol::UDP, &[0x04, 0xd2, 0x12, 0xb5, 0, 8, 0, 0])
+	}
+
+	/// A TCP segment from 2001:db8::1 port 80 to 2001:db8::2 port 8080
+	fn ipv6_tcp_frame() -> Vec<u8> {
+		let mut frame = vec![0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x86, 0xdd];
+		frame.extend_from_slice(&[0x60, 0, 0, 0, 0, 20, IPPROTO_TCP, 64]);
+		frame.extend_from_slice(&"2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+		frame.extend_from_slice(&"2001:db8::2".parse::<std::net::Ipv6Addr>().unwrap().octets());
+		let mut tcp = [0u8; 20];
+		tcp[..4].copy_from_slice(&[0, 80, 0x1f, 0x90]);
+		tcp[12] = 0x50;
+		frame.extend_from_slice(&tcp);
+		frame
+	}
+
+	#[test]
+	fn parses_prefixes() {
+		let prefix = "10.1.2.3/16".parse::<IpPrefix>().unwrap();
+		assert_eq!(prefix.to_string(), "10.1.2.3/16");
+		assert_eq!(prefix.masked_octets(), (vec![10, 1, 0, 0], vec![255, 255, 0, 0]));
+		assert_eq!("10.0.0.1".parse::<IpPrefix>().unwrap().len, 32);
+		assert_eq!("::1".parse::<IpPrefix>().unwrap().len, 128);
+		assert!("10.0.0.0/33".parse::<IpPrefix>().is_err());
+		assert!("10.0.0/8".parse::<IpPrefix>().is_err());
+		assert!("10.0.0.0/x".parse::<IpPrefix>().is_err());
+	}
+
+	#[test]
+	fn prefixes_mask_partial_bytes() {
+		let prefix = "10.0.0.128/25".parse::<IpPrefix>().unwrap();
+		assert!(prefix.contains(&[10, 0, 0, 200]));
+		assert!(!prefix.contains(&[10, 0, 0, 127]));
+		let any = "0.0.0.0/0".parse::<IpPrefix>().unwrap();
+		assert!(any.contains(&[192, 168, 1, 1]));
+		// an IPv6 address is never in an IPv4 network
+		assert!(!any.contains(&[0; 16]));
+	}
+
+	#[test]
+	fn validates_rules() {
+		let vxlan = rule("ip_dst = \"10.0.0.0/8\"\nip_proto = 17\ndst_port = 4789");
+		assert!(vxlan.validate(1).is_ok());
+		assert!(rule("ip_src = \"10.0.0.1\"\nip_dst = \"::1\"").validate(1).is_err());
+		assert!(rule("ether_type = 0x86dd\nip_dst = \"10.0.0.0/8\"").validate(1).is_err());
+		assert!(rule("dst_port = 53").validate(1).is_err());
+		assert!(rule("ip_proto = 1\ndst_port = 53").validate(1).is_err());
+		assert!(rule("action = { queue = 1 }").validate(2).is_ok());
+		assert!(rule("action = { queue = 2 }").validate(2).is_err());
+	}
+
+	#[test]
+	fn matches_ethernet_fields() {
+		let frame = udp_frame();
+		assert!(rule("").matches(&frame));
+		assert!(rule("eth_dst = \"02:00:00:00:00:01\"").matches(&frame));
+		assert!(!rule("eth_dst = \"02:00:00:00:00:02\"").matches(&frame));
+		assert!(rule("eth_src = \"02:00:00:00:00:02\"").matches(&frame));
+		assert!(rule("ether_type = 0x0800").matches(&frame));
+		assert!(!rule("ether_type = 0x0806").matches(&frame));
+		assert!(!rule("").matches(&frame[..13]));
+	}
+
+	#[test]
+	fn matches_ipv4_prefixes_and_ports() {
+		let frame = udp_frame();
+		assert!(rule("ip_src = \"10.0.0.0/24\"\nip_dst = \"10.0.0.2\"").matches(&frame));
+		assert!(!rule("ip_dst = \"10.0.1.0/24\"").matches(&frame));
+		assert!(rule("ip_proto = 17\nsrc_port = 1234\ndst_port = 4789").matches(&frame));
+		assert!(!rule("ip_proto = 17\ndst_port = 53").matches(&frame));
+		assert!(!rule("ip_proto = 6\ndst_port = 4789").matches(&frame));
+		// an IPv6 rule never matches an IPv4 packet
+		assert!(!rule("ip_dst = \"::/0\"").matches(&frame));
+	}
+
+	#[test]
+	fn matches_ports_in_first_fragments_only() {
+		let port_rule = rule("ip_proto = 17\ndst_port = 4789");
+		let mut frame = udp_frame();
+		// more fragments follow, offset 0: the UDP header is there
+		frame[20] = 0x20;
+		assert!(port_rule.matches(&frame));
+		// a later fragment: the bytes after the IP header are payload
+		frame[21] = 0x10;
+		assert!(!port_rule.matches(&frame));
+		assert!(rule("ip_proto = 17").matches(&frame));
+	}
+
+	#[test]
+	fn matches_ipv4_options() {
+		// ihl 6: the UDP header starts after a 4 byte option
+		let mut frame = udp_frame();
+		frame[14] = 0x46;
+		frame.splice(34..34, [1, 1, 1, 1]);
+		assert!(rule("ip_proto = 17\ndst_port = 4789").matches(&frame));
+		assert!(!rule("ip_proto = 17\ndst_port = 257").matches(&frame));
+	}
+
+	#[test]
+	fn matches_truncated_l4_headers_without_ports_only() {
+		let frame = ipv4_frame(IpProtocol::UDP, &[0x04, 0xd2]);
+		assert!(rule("ip_proto = 17").matches(&frame));
+		assert!(!rule("ip_proto = 17\nsrc_port = 1234").matches(&frame));
+	}
+
+	#[test]
+	fn matches_ipv6() {
+		let frame = ipv6_tcp_frame();
+		assert!(rule("ip_src = \"2001:db8::/32\"\nip_proto = 6\ndst_port = 8080").matches(&frame));
+		assert!(!rule("ip_dst = \"2001:db8::1/128\"").matches(&frame));
+		assert!(rule("ether_type = 0x86dd\nip_proto = 6\nsrc_port = 80").matches(&frame));
+		// without an IPv6 address or ether type the rule is an IPv4 one
+		assert!(!rule("ip_proto = 6").matches(&frame));
+		assert!(!rule("ip_dst = \"2001:db8::2\"").matches(&frame[..40]));
+	}
+}
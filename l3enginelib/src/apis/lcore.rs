@@ -0,0 +1,176 @@
+//! Safe wrappers to run Rust closures on EAL worker lcores
+//!
+//! `launch` hands a closure to `rte_eal_remote_launch` and returns a handle that
+//! waits for the lcore and yields the closure's result.
+//! `StopSignal` is the shared flag long running lcore loops poll to know when to return.
+
+use std::{
+	any::Any,
+	os::raw,
+	panic::{self, AssertUnwindSafe},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, Mutex,
+	},
+};
+
+use super::LcoreError;
+
+/// Id of the lcore the caller runs on
+#[inline]
+pub fn current_lcore() -> u32 {
+	unsafe { dpdk_sys::_rte_lcore_id() }
+}
+
+/// Id of the main lcore, the one `eal_init` was called from
+#[inline]
+pub fn main_lcore() -> u32 {
+	unsafe { dpdk_sys::rte_get_master_lcore() }
+}
+
+/// Every lcore enabled in the EAL, main lcore included
+pub fn lcores() -> Vec<u32> {
+	next_lcores(false)
+}
+
+/// Every enabled lcore closures can be launched on, i.e. all but the main lcore
+pub fn worker_lcores() -> Vec<u32> {
+	next_lcores(true)
+}
+
+fn next_lcores(skip_main: bool) -> Vec<u32> {
+	let mut lcores = Vec::new();
+	// u32::MAX wraps to 0 inside `rte_get_next_lcore`
+	let mut lcore = u32::MAX;
+	loop {
+		lcore = unsafe { dpdk_sys::rte_get_next_lcore(lcore, skip_main as raw::c_int, 0) };
+		if lcore >= dpdk_sys::RTE_MAX_LCORE {
+			break;
+		}
+		lcores.push(lcore);
+	}
+	lcores
+}
+
+/// A flag shared between the main lcore and the loops it launched
+///
+/// Clones share the same flag; once `stop` is called every clone reports it.
+#[derive(Clone, Debug)]
+pub struct StopSignal(Arc<AtomicBool>);
+
+impl StopSignal {
+	pub fn new() -> Self {
+		Self(Arc::new(AtomicBool::new(true)))
+	}
+
+	/// Ask every holder of the signal to return
+	#[inline]
+	pub fn stop(&self) {
+		self.0.store(false, Ordering::SeqCst);
+	}
+
+	/// Returns false once `stop` has been called
+	#[inline]
+	pub fn is_running(&self) -> bool {
+		self.0.load(Ordering::SeqCst)
+	}
+
+	/// The underlying flag, for APIs such as signal handlers that want an `Arc<AtomicBool>`
+	pub fn flag(&self) -> Arc<AtomicBool> {
+		self.0.clone()
+	}
+}
+
+impl Default for StopSignal {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+type LaunchResult<T> = Arc<Mutex<Option<Result<T, Box<dyn Any + Send>>>>>;
+
+struct LaunchCtx<F, T> {
+	func: F,
+	result: LaunchResult<T>,
+}
+
+/// Entry point handed to the EAL; runs the boxed closure and stores its result
+unsafe extern "C" fn trampoline<F, T>(arg: *mut raw::c_void) -> raw::c_int
+where
+	F: FnOnce() -> T,
+{
+	let ctx = Box::from_raw(arg as *mut LaunchCtx<F, T>);
+	let LaunchCtx { func, result } = *ctx;
+	// a panic must not unwind into the EAL
+	let res = panic::catch_unwind(AssertUnwindSafe(func));
+	let ret = if res.is_ok() { 0 } else { -1 };
+	if let Ok(mut slot) = result.lock() {
+		*slot = Some(res);
+	}
+	ret
+}
+
+/// A closure running on a worker lcore
+pub struct LcoreHandle<T> {
+	lcore: u32,
+	result: LaunchResult<T>,
+}
+
+impl<T> LcoreHandle<T> {
+	/// The lcore the closure runs on
+	pub fn lcore(&self) -> u32 {
+		self.lcore
+	}
+
+	/// Returns true once the closure has returned
+	pub fn is_finished(&self) -> bool {
+		unsafe {
+			dpdk_sys::rte_eal_get_lcore_state(self.lcore) != dpdk_sys::rte_lcore_state_t::RUNNING
+		}
+	}
+
+	/// Wait for the closure to return and get its result
+	pub fn join(self) -> Result<T, LcoreError> {
+		unsafe { dpdk_sys::rte_eal_wait_lcore(self.lcore) };
+		let res = match self.result.lock() {
+			Ok(mut slot) => slot.take(),
+			Err(_) => None,
+		};
+		match res {
+			Some(Ok(val)) => Ok(val),
+			_ => Err(LcoreError::Panicked(self.lcore)),
+		}
+	}
+}
+
+/// Run `func` on the worker lcore `lcore`
+///
+/// The lcore must be enabled, must not be the main lcore and must be idle.
+pub fn launch<F, T>(lcore: u32, func: F) -> Result<LcoreHandle<T>, LcoreError>
+where
+	F: FnOnce() -> T + Send + 'static,
+	T: Send + 'static,
+{
+	if lcore >= dpdk_sys::RTE_MAX_LCORE
+		|| lcore == main_lcore()
+		|| unsafe { dpdk_sys::rte_lcore_is_enabled(lcore) } == 0
+	{
+		return Err(LcoreError::NotAvailable(lcore));
+	}
+
+	let result: LaunchResult<T> = Arc::new(Mutex::new(None));
+	let ctx = Box::new(LaunchCtx {
+		func,
+		result: result.clone(),
+	});
+	let arg = Box::into_raw(ctx) as *mut raw::c_void;
+
+	match unsafe { dpdk_sys::rte_eal_remote_launch(Some(trampoline::<F, T>), arg, lcore) } {
+		0 => Ok(LcoreHandle { lcore, result }),
+		_ => {
+			// the closure never ran; reclaim it
+			drop(unsafe { Box::from_raw(arg as *mut LaunchCtx<F, T>) });
+			Err(LcoreError::Busy(lcore))
+		}
+	}
+}
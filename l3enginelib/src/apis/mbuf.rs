@@ -1,18 +1,31 @@
 //! The Mbuf structure wraps around DPDK packets such that they are guaranteed to be non null
+//!
+//! A packet can span a chain of mbufs (segments), e.g. a jumbo frame received with
+//! scattered rx. The mbuf a `Mbuf` points to is the first segment; its `pkt_len` and
+//! `nb_segs` describe the whole chain. The typed accessors (`read_data`, `read_data_slice`,
+//! `write_data_slice`) hand out pointers and so stay within the first segment, while
+//! `read_bytes`, `write_bytes` and `segments` cover the whole packet.
+//!
+//! `clone_shallow` gives a second `Mbuf` for the same packet data without copying it:
+//! the clone's segments are indirect mbufs attached to the original ones, whose
+//! reference counts keep the data alive until every clone is freed.
 
 use std::{
 	error::Error,
 	fmt,
-	marker::{Send, Sync},
+	marker::{PhantomData, Send, Sync},
 	mem,
 	os::raw,
 	ptr,
 	ptr::NonNull,
 	slice,
+	sync::atomic::{AtomicU16, Ordering},
 };
 
 use super::{BufError, MemoryError, Mempool};
 
+pub use l3enginederive::SizeOf;
+
 /// A trait for returning the size type in bytes
 ///
 /// Size of structs are used for bound checks when reading and writing packets
@@ -43,6 +56,12 @@ impl SizeOf for [u8; 2] {
 	}
 }
 
+impl SizeOf for [u8; 4] {
+	fn size_of() -> usize {
+		std::mem::size_of::<[u8; 4]>()
+	}
+}
+
 impl SizeOf for [u8; 16] {
 	fn size_of() -> usize {
 		std::mem::size_of::<[u8; 16]>()
@@ -59,6 +78,89 @@ pub struct Mbuf {
 	pub raw: NonNull<dpdk_sys::rte_mbuf>,
 }
 
+/// `ol_flags` of an mbuf attached to another mbuf's data (`IND_ATTACHED_MBUF`)
+const IND_ATTACHED_MBUF: u64 = 1 << 62;
+/// `ol_flags` of an mbuf attached to an external buffer (`EXT_ATTACHED_MBUF`)
+const EXT_ATTACHED_MBUF: u64 = 1 << 61;
+
+/// Start of the data of segment `seg`
+#[inline]
+unsafe fn seg_data(seg: *const dpdk_sys::rte_mbuf) -> *mut u8 {
+	((*seg).buf_addr as *mut u8).add((*seg).data_off as usize)
+}
+
+/// Bytes left after the data of segment `seg`
+#[inline]
+unsafe fn seg_tailroom(seg: *const dpdk_sys::rte_mbuf) -> usize {
+	((*seg).buf_len - (*seg).data_off - (*seg).data_len) as usize
+}
+
+/// Reference count of segment `seg`
+#[inline]
+unsafe fn seg_refcnt<'a>(seg: *const dpdk_sys::rte_mbuf) -> &'a AtomicU16 {
+	&*(&(*seg).__bindgen_anon_2 as *const _ as *const AtomicU16)
+}
+
+/// Is the data of segment `seg` reachable through another mbuf as well
+#[inline]
+unsafe fn seg_is_shared(seg: *const dpdk_sys::rte_mbuf) -> bool {
+	let flags = (*seg).ol_flags;
+	if flags & EXT_ATTACHED_MBUF != 0 {
+		return true;
+	}
+	let direct = if flags & IND_ATTACHED_MBUF != 0 {
+		// the direct mbuf sits in front of the buffer (`rte_mbuf_from_indirect`)
+		((*seg).buf_addr as *const u8)
+			.sub(mem::size_of::<dpdk_sys::rte_mbuf>() + (*seg).priv_size as usize)
+			as *const dpdk_sys::rte_mbuf
+	} else {
+		seg
+	};
+	seg_refcnt(direct).load(Ordering::Acquire) > 1
+}
+
+/// The data of every segment of a packet, in order
+pub struct Segments<'a> {
+	seg: *const dpdk_sys::rte_mbuf,
+	_pkt: PhantomData<&'a Mbuf>,
+}
+
+impl<'a> Iterator for Segments<'a> {
+	type Item = &'a [u8];
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.seg.is_null() {
+			return None;
+		}
+		unsafe {
+			let data = slice::from_raw_parts(seg_data(self.seg), (*self.seg).data_len as usize);
+			self.seg = (*self.seg).next;
+			Some(data)
+		}
+	}
+}
+
+/// The data of every segment of a packet, in order, writable
+pub struct SegmentsMut<'a> {
+	seg: *mut dpdk_sys::rte_mbuf,
+	_pkt: PhantomData<&'a mut Mbuf>,
+}
+
+impl<'a> Iterator for SegmentsMut<'a> {
+	type Item = &'a mut [u8];
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.seg.is_null() {
+			return None;
+		}
+		unsafe {
+			let data = slice::from_raw_parts_mut(seg_data(self.seg), (*self.seg).data_len as usize);
+			self.seg = (*self.seg).next;
+			Some(data)
+		}
+	}
+}
+
 unsafe impl Sync for Mbuf {}
 unsafe impl Send for Mbuf {}
 
@@ -78,11 +180,12 @@ impl Mbuf {
 	}
 
 	/// Create a new message buffer from a byte array
+	///
+	/// Data that does not fit in a single mbuf is spread over a chain of them.
 	#[inline]
 	pub fn from_bytes(data: &[u8], mp: &Mempool) -> Result<Self, Box<dyn Error>> {
 		let mut mbuf = Mbuf::new(mp)?;
-		mbuf.extend(0, data.len())?;
-		mbuf.write_data_slice(0, data)?;
+		mbuf.append_data(data, mp)?;
 		Ok(mbuf)
 	}
 
@@ -124,42 +227,415 @@ impl Mbuf {
 		ptr
 	}
 
-	/// Returns amount of data stored in the buffer
+	/// Returns amount of data stored in the first segment
 	#[inline]
 	pub fn data_len(&self) -> usize {
 		self.raw().data_len as usize
 	}
 
-	/// Returns the raw pointer from the offset
+	/// Returns the length of the whole packet, over every segment
+	#[inline]
+	pub fn pkt_len(&self) -> usize {
+		self.raw().pkt_len as usize
+	}
+
+	/// Returns the number of segments of the packet
+	#[inline]
+	pub fn nb_segs(&self) -> u16 {
+		self.raw().nb_segs
+	}
+
+	/// Is the whole packet in the first segment
+	#[inline]
+	pub fn is_contiguous(&self) -> bool {
+		self.raw().next.is_null()
+	}
+
+	/// The data of every segment, in order
+	#[inline]
+	pub fn segments(&self) -> Segments<'_> {
+		Segments {
+			seg: self.get_ptr(),
+			_pkt: PhantomData,
+		}
+	}
+
+	/// The data of every segment, in order, writable
+	#[inline]
+	pub fn segments_mut(&mut self) -> SegmentsMut<'_> {
+		SegmentsMut {
+			seg: self.get_ptr(),
+			_pkt: PhantomData,
+		}
+	}
+
+	/// Returns a new `Mbuf` for the same packet data, allocated from `mp`
+	///
+	/// Every segment of the clone is an indirect mbuf attached to the matching
+	/// segment here, so nothing is copied; the clone gets its own metadata
+	/// (port tag, offload flags, ...). The data stays until both are freed.
+	/// As long as the data is shared (`is_shared`), writing it through either
+	/// `Mbuf` changes it for the other as well.
+	pub fn clone_shallow(&self, mp: &Mempool) -> Result<Mbuf, MemoryError> {
+		let clone = unsafe { dpdk_sys::rte_pktmbuf_clone(self.get_ptr(), mp.get_ptr()) };
+		match NonNull::new(clone) {
+			Some(raw) => Ok(Mbuf { raw }),
+			None => Err(MemoryError::NoBuf),
+		}
+	}
+
+	/// Returns the reference count of the first segment
+	#[inline]
+	pub fn refcnt(&self) -> u16 {
+		unsafe { seg_refcnt(self.get_ptr()) }.load(Ordering::Acquire)
+	}
+
+	/// Adds `delta` to the reference count of every segment and returns the new count of the first
+	///
+	/// # Safety
+	///
+	/// Every reference added must be dropped by exactly one more free of the packet,
+	/// e.g. by an `Mbuf` made with `from_ptr`, and no more may be dropped than are held.
+	pub unsafe fn refcnt_update(&self, delta: i16) -> u16 {
+		let mut seg = self.get_ptr();
+		let mut first = None;
+		while !seg.is_null() {
+			let refcnt = seg_refcnt(seg);
+			let new = if delta >= 0 {
+				refcnt.fetch_add(delta as u16, Ordering::AcqRel).wrapping_add(delta as u16)
+			} else {
+				refcnt.fetch_sub(delta.unsigned_abs(), Ordering::AcqRel).wrapping_sub(delta.unsigned_abs())
+			};
+			first.get_or_insert(new);
+			seg = (*seg).next;
+		}
+		first.unwrap_or(0)
+	}
+
+	/// Is this an indirect mbuf, i.e. a clone attached to another mbuf's data
+	#[inline]
+	pub fn is_indirect(&self) -> bool {
+		self.raw().ol_flags & IND_ATTACHED_MBUF != 0
+	}
+
+	/// Is any of the packet data reachable through another `Mbuf` as well
+	///
+	/// Shared data should not be written: the change would show in every clone.
+	pub fn is_shared(&self) -> bool {
+		let mut seg = self.get_ptr() as *const dpdk_sys::rte_mbuf;
+		unsafe {
+			while !seg.is_null() {
+				if seg_is_shared(seg) {
+					return true;
+				}
+				seg = (*seg).next;
+			}
+		}
+		false
+	}
+
+	/// The last segment of the packet
+	fn last_segment(&self) -> *mut dpdk_sys::rte_mbuf {
+		let mut seg = self.get_ptr();
+		unsafe {
+			while !(*seg).next.is_null() {
+				seg = (*seg).next;
+			}
+		}
+		seg
+	}
+
+	/// The segment holding packet byte `offset` and the offset within that segment
+	fn find_segment(&self, mut offset: usize) -> Option<(*mut dpdk_sys::rte_mbuf, usize)> {
+		let mut seg = self.get_ptr();
+		unsafe {
+			while !seg.is_null() {
+				let len = (*seg).data_len as usize;
+				if offset < len {
+					return Some((seg, offset));
+				}
+				offset -= len;
+				seg = (*seg).next;
+			}
+		}
+		None
+	}
+
+	/// Copies `buf.len()` bytes of the packet from `offset` into `buf`, across segments
+	pub fn read_bytes(&self, offset: usize, buf: &mut [u8]) -> Result<(), BufError> {
+		self.check_range(offset, buf.len())?;
+		if buf.is_empty() {
+			return Ok(());
+		}
+		let (mut seg, mut seg_off) = self.find_segment(offset).ok_or(BufError::BadOffset(offset, self.pkt_len()))?;
+		let mut done = 0;
+		while done < buf.len() {
+			unsafe {
+				let n = ((*seg).data_len as usize - seg_off).min(buf.len() - done);
+				ptr::copy_nonoverlapping(seg_data(seg).add(seg_off), buf[done..].as_mut_ptr(), n);
+				done += n;
+				seg = (*seg).next;
+			}
+			seg_off = 0;
+		}
+		Ok(())
+	}
+
+	/// Overwrites the packet from `offset` with `data`, across segments
+	///
+	/// The packet does not grow: `offset + data.len()` must be within `pkt_len`.
+	pub fn write_bytes(&mut self, offset: usize, data: &[u8]) -> Result<(), BufError> {
+		self.check_range(offset, data.len())?;
+		if data.is_empty() {
+			return Ok(());
+		}
+		let (mut seg, mut seg_off) = self.find_segment(offset).ok_or(BufError::BadOffset(offset, self.pkt_len()))?;
+		let mut done = 0;
+		while done < data.len() {
+			unsafe {
+				let n = ((*seg).data_len as usize - seg_off).min(data.len() - done);
+				ptr::copy_nonoverlapping(data[done..].as_ptr(), seg_data(seg).add(seg_off), n);
+				done += n;
+				seg = (*seg).next;
+			}
+			seg_off = 0;
+		}
+		Ok(())
+	}
+
+	/// Checks that `len` bytes from `offset` are within the packet
+	fn check_range(&self, offset: usize, len: usize) -> Result<(), BufError> {
+		let pkt_len = self.pkt_len();
+		if offset > pkt_len || (offset == pkt_len && len > 0) {
+			return Err(BufError::BadOffset(offset, pkt_len));
+		}
+		if len > pkt_len - offset {
+			return Err(BufError::OutOfBuffer(len, pkt_len - offset));
+		}
+		Ok(())
+	}
+
+	/// Chains `tail` after the last segment of the packet
+	///
+	/// `tail` and its own segments become part of the packet; it is freed if it cannot be chained.
+	pub fn chain(&mut self, tail: Mbuf) -> Result<(), BufError> {
+		let nb_segs = self.nb_segs() as u32 + tail.nb_segs() as u32;
+		if nb_segs > dpdk_sys::RTE_MBUF_MAX_NB_SEGS {
+			return Err(BufError::TooManySegments);
+		}
+		let pkt_len = self.raw().pkt_len.checked_add(tail.raw().pkt_len).ok_or(BufError::TooLong)?;
+		let last = self.last_segment();
+		unsafe { (*last).next = tail.into_ptr() };
+		let raw = self.raw_mut();
+		raw.nb_segs = nb_segs as u16;
+		raw.pkt_len = pkt_len;
+		Ok(())
+	}
+
+	/// Appends `data` at the end of the packet
+	///
+	/// The tailroom of the last segment is filled first, then segments allocated from `mp`
+	/// are chained on. If an allocation fails the data appended so far stays.
+	pub fn append_data(&mut self, data: &[u8], mp: &Mempool) -> Result<(), BufError> {
+		if data.len() > u32::MAX as usize || self.raw().pkt_len.checked_add(data.len() as u32).is_none() {
+			return Err(BufError::TooLong);
+		}
+		let last = self.last_segment();
+		let n = unsafe { seg_tailroom(last) }.min(data.len());
+		unsafe {
+			ptr::copy_nonoverlapping(data.as_ptr(), seg_data(last).add((*last).data_len as usize), n);
+			(*last).data_len += n as u16;
+		}
+		self.raw_mut().pkt_len += n as u32;
+
+		let mut rest = &data[n..];
+		while !rest.is_empty() {
+			let mut seg = Mbuf::new(mp).map_err(|_| BufError::NoMbuf)?;
+			let n = seg.tailroom().min(rest.len());
+			unsafe { ptr::copy_nonoverlapping(rest.as_ptr(), seg.data_address(0), n) };
+			seg.raw_mut().data_len = n as u16;
+			seg.raw_mut().pkt_len = n as u32;
+			self.chain(seg)?;
+			rest = &rest[n..];
+		}
+		Ok(())
+	}
+
+	/// Moves the whole packet into the first segment and frees the others
+	///
+	/// Fails if the first segment has not enough tailroom or the data is shared
+	/// with a clone (`is_shared`), leaving the packet as it was.
+	pub fn linearize(&mut self) -> Result<(), BufError> {
+		if self.is_contiguous() {
+			return Ok(());
+		}
+		if self.is_shared() {
+			return Err(BufError::Shared);
+		}
+		let extra = self.pkt_len() - self.data_len();
+		if extra > self.tailroom() {
+			return Err(BufError::NoTailroom(extra, self.tailroom()));
+		}
+		unsafe {
+			let mut dst = self.data_address(self.data_len());
+			let rest = self.raw().next;
+			let mut seg = rest;
+			while !seg.is_null() {
+				let len = (*seg).data_len as usize;
+				ptr::copy_nonoverlapping(seg_data(seg), dst, len);
+				dst = dst.add(len);
+				seg = (*seg).next;
+			}
+			let raw = self.raw_mut();
+			raw.next = ptr::null_mut();
+			raw.data_len += extra as u16;
+			raw.nb_segs = 1;
+			dpdk_sys::_rte_pktmbuf_free(rest);
+		}
+		Ok(())
+	}
+
+	/// Returns the port tag of the buffer
+	///
+	/// Received packets carry the id of the port they arrived on;
+	/// the engine sends outgoing packets through the port named here
+	#[inline]
+	pub fn port(&self) -> u16 {
+		self.raw().port
+	}
+
+	/// Sets the port the buffer should leave through
+	#[inline]
+	pub fn set_port(&mut self, port: u16) {
+		self.raw_mut().port = port;
+	}
+
+	/// Returns the id a `FlowAction::Mark` rule tagged the packet with, if any
+	#[inline]
+	pub fn mark(&self) -> Option<u32> {
+		let raw = self.raw();
+		if raw.ol_flags & dpdk_sys::PKT_RX_FDIR_ID as u64 == 0 {
+			return None;
+		}
+		Some(unsafe { raw.__bindgen_anon_4.hash.fdir.hi })
+	}
+
+	/// Marks the packet the way the NIC does for a `FlowAction::Mark` rule
+	#[inline]
+	pub fn set_mark(&mut self, id: u32) {
+		let raw = self.raw_mut();
+		raw.ol_flags |= (dpdk_sys::PKT_RX_FDIR | dpdk_sys::PKT_RX_FDIR_ID) as u64;
+		raw.__bindgen_anon_4.hash.fdir.hi = id;
+	}
+
+	/// Returns the raw pointer from the offset in the first segment
 	#[inline]
 	pub unsafe fn data_address(&self, offset: usize) -> *mut u8 {
 		let raw = self.raw();
 		(raw.buf_addr as *mut u8).offset(raw.data_off as isize + offset as isize)
 	}
 
-	/// Returns the amount of bytes left in the buffer
+	/// Returns the amount of bytes free before the data of the first segment
 	#[inline]
-	fn tailroom(&self) -> usize {
-		let raw = self.raw();
-		(raw.buf_len - raw.data_off - raw.data_len) as usize
+	pub fn headroom(&self) -> usize {
+		self.raw().data_off as usize
+	}
+
+	/// Returns the amount of bytes left in the first segment
+	#[inline]
+	pub fn tailroom(&self) -> usize {
+		unsafe { seg_tailroom(self.get_ptr()) }
+	}
+
+	/// Adds `len` bytes in front of the data, taken from the headroom
+	///
+	/// Nothing is copied; the new bytes are left as they were and should be written over.
+	#[inline]
+	pub fn prepend(&mut self, len: usize) -> Result<(), BufError> {
+		if len > self.headroom() {
+			return Err(BufError::NoHeadroom(len, self.headroom()));
+		}
+		let raw = self.raw_mut();
+		raw.data_off -= len as u16;
+		raw.data_len += len as u16;
+		raw.pkt_len += len as u32;
+		Ok(())
+	}
+
+	/// Removes `len` bytes from the front of the data, giving them back to the headroom
+	///
+	/// `len` must be within the first segment. Nothing is copied.
+	#[inline]
+	pub fn adj(&mut self, len: usize) -> Result<(), BufError> {
+		if len > self.data_len() {
+			return Err(BufError::NotResized);
+		}
+		let raw = self.raw_mut();
+		raw.data_off += len as u16;
+		raw.data_len -= len as u16;
+		raw.pkt_len -= len as u32;
+		Ok(())
+	}
+
+	/// Adds `len` bytes after the data of the last segment, taken from its tailroom
+	///
+	/// Nothing is copied; the new bytes are left as they were and should be written over.
+	#[inline]
+	pub fn append(&mut self, len: usize) -> Result<(), BufError> {
+		let last = self.last_segment();
+		let room = unsafe { seg_tailroom(last) };
+		if len > room {
+			return Err(BufError::NoTailroom(len, room));
+		}
+		unsafe { (*last).data_len += len as u16 };
+		self.raw_mut().pkt_len += len as u32;
+		Ok(())
+	}
+
+	/// Removes `len` bytes from the end of the data of the last segment
+	///
+	/// `len` must be within the last segment. Nothing is copied.
+	#[inline]
+	pub fn trim(&mut self, len: usize) -> Result<(), BufError> {
+		let last = self.last_segment();
+		if len > unsafe { (*last).data_len } as usize {
+			return Err(BufError::NotResized);
+		}
+		unsafe { (*last).data_len -= len as u16 };
+		self.raw_mut().pkt_len -= len as u32;
+		Ok(())
 	}
 
-	/// Extends the data buffer at offset `len`
+	/// Extends the first segment by `len` bytes at `offset`
 	///
-	/// If the offset is not the end of data
-	/// data after offset is shifted down to make room
+	/// At the start of the data the headroom is used and at its end the tailroom,
+	/// so nothing is copied. In the middle, the shorter side of the data is
+	/// shifted, into the headroom or the tailroom, to make room.
 	#[inline]
 	pub fn extend(&mut self, offset: usize, len: usize) -> Result<(), BufError> {
-		if len <= 0 || offset > self.data_len() || len > self.tailroom() {
+		let data_len = self.data_len();
+		if len == 0 || offset > data_len {
 			return Err(BufError::NotResized);
 		}
-		// shift down data to make room
-		let to_copy = self.data_len() - offset;
-		if to_copy > 0 {
-			unsafe {
+		if offset == 0 && len <= self.headroom() {
+			return self.prepend(len);
+		}
+		let front = len <= self.headroom() && (offset < data_len - offset || len > self.tailroom());
+		if !front && len > self.tailroom() {
+			return Err(BufError::NotResized);
+		}
+		unsafe {
+			if front {
+				// shift the data before offset up into the headroom
+				let src = self.data_address(0);
+				let dst = src.sub(len);
+				ptr::copy(src, dst, offset);
+				self.raw_mut().data_off -= len as u16;
+			} else if offset < data_len {
+				// shift the data after offset down into the tailroom
 				let src = self.data_address(offset);
 				let dst = self.data_address(offset + len);
-				ptr::copy(src, dst, to_copy); // this is an expensive copy op
+				ptr::copy(src, dst, data_len - offset);
 			}
 		}
 
@@ -170,22 +646,32 @@ impl Mbuf {
 		Ok(())
 	}
 
-	/// Shrinks the data buffer at offset by `len` bytes
+	/// Shrinks the first segment by `len` bytes at `offset`
 	///
-	/// The data at offset is shifted up
+	/// At the start or the end of the data nothing is copied. In the middle,
+	/// the shorter side of the data is shifted over the removed bytes.
 	#[inline]
 	pub fn shrink(&mut self, offset: usize, len: usize) -> Result<(), BufError> {
-		if len <= 0 || offset + len > self.data_len() {
+		let data_len = self.data_len();
+		if len == 0 || offset + len > data_len {
 			return Err(BufError::NotResized);
 		}
-
-		// shifts up data to fill the room
-		let to_copy = self.data_len() - offset - len;
-		if to_copy > 0 {
-			unsafe {
+		if offset == 0 {
+			return self.adj(len);
+		}
+		let after = data_len - offset - len;
+		unsafe {
+			if offset < after {
+				// shift the data before offset down over the removed bytes
+				let src = self.data_address(0);
+				let dst = src.add(len);
+				ptr::copy(src, dst, offset);
+				self.raw_mut().data_off += len as u16;
+			} else if after > 0 {
+				// shift the data after the removed bytes up
 				let src = self.data_address(offset + len);
 				let dst = self.data_address(offset);
-				ptr::copy(src, dst, to_copy); // expensive copy
+				ptr::copy(src, dst, after);
 			}
 		}
 
@@ -206,14 +692,37 @@ impl Mbuf {
 		}
 	}
 
-	/// Truncates the data buffer to len
+	/// Truncates the packet to `to_len` bytes
+	///
+	/// Segments left without data are freed; the first segment is always kept.
 	#[inline]
 	pub fn truncate(&mut self, to_len: usize) -> Result<(), BufError> {
-		if to_len >= self.data_len() {
+		if to_len >= self.pkt_len() {
 			return Err(BufError::NotResized);
 		}
 
-		self.raw_mut().data_len = to_len as u16;
+		let (seg, keep) = match to_len {
+			0 => (self.get_ptr(), 0),
+			_ => {
+				let (seg, off) = self.find_segment(to_len - 1).ok_or(BufError::NotResized)?;
+				(seg, off + 1)
+			}
+		};
+		let mut nb_segs = 1;
+		let mut s = self.get_ptr();
+		unsafe {
+			while s != seg {
+				nb_segs += 1;
+				s = (*s).next;
+			}
+			(*seg).data_len = keep as u16;
+			let rest = (*seg).next;
+			if !rest.is_null() {
+				(*seg).next = ptr::null_mut();
+				dpdk_sys::_rte_pktmbuf_free(rest);
+			}
+		}
+		self.raw_mut().nb_segs = nb_segs;
 		self.raw_mut().pkt_len = to_len as u32;
 
 		Ok(())
@@ -250,7 +759,7 @@ impl Mbuf {
 		if offset >= self.data_len() {
 			return Err(BufError::BadOffset(offset, self.data_len()));
 		}
-		if offset + T::size_of() * count > self.data_len() - offset {
+		if offset + T::size_of() * count > self.data_len() {
 			return Err(BufError::OutOfBuffer(
 				T::size_of() * count,
 				self.data_len() - offset,
@@ -328,32 +837,40 @@ impl Mbuf {
 	}
 
 	/// Frees the `rte_mbuf` in bulk.
+	///
+	/// Plain single segment mbufs go straight back to their mempools;
+	/// chained, shared and attached ones are freed segment by segment.
 	pub fn mbuf_free_bulk(mbufs: Vec<*mut dpdk_sys::rte_mbuf>) {
 		if mbufs.is_empty() {
 			return;
 		}
 
 		let mut to_free = Vec::with_capacity(mbufs.len());
-		let pool = unsafe { (*mbufs[0]).pool };
+		let mut pool = unsafe { (*mbufs[0]).pool };
 
 		for mbuf in mbufs.into_iter() {
-			if pool == unsafe { (*mbuf).pool } {
-				to_free.push(mbuf as *mut raw::c_void);
-			} else {
+			let raw = unsafe { &*mbuf };
+			let plain = raw.next.is_null()
+				&& unsafe { seg_refcnt(mbuf) }.load(Ordering::Acquire) == 1
+				&& raw.ol_flags & (IND_ATTACHED_MBUF | EXT_ATTACHED_MBUF) == 0;
+			if !plain {
+				unsafe { dpdk_sys::_rte_pktmbuf_free(mbuf) };
+				continue;
+			}
+			if pool != raw.pool {
 				unsafe {
-					let len = to_free.len();
-					dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
-					to_free.set_len(0);
+					dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), to_free.len() as u32);
 				}
-
-				to_free.push(mbuf as *mut raw::c_void);
+				to_free.clear();
+				pool = raw.pool;
 			}
+			to_free.push(mbuf as *mut raw::c_void);
 		}
 
-		unsafe {
-			let len = to_free.len();
-			dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
-			to_free.set_len(0);
+		if !to_free.is_empty() {
+			unsafe {
+				dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), to_free.len() as u32);
+			}
 		}
 	}
 }
@@ -366,6 +883,8 @@ impl fmt::Debug for Mbuf {
 			.field("pkt_len", &raw.pkt_len)
 			.field("data_len", &raw.data_len)
 			.field("data_off", &raw.data_off)
+			.field("nb_segs", &raw.nb_segs)
+			.field("refcnt", &self.refcnt())
 			.finish()
 	}
 }
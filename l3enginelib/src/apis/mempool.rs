@@ -1,54 +1,176 @@
 //! The Mempool struct contains a pointer to a DPDK mempool that is guaranteed to be non null
+//!
+//! `MempoolConfig` describes the pools the engine creates. `MempoolSet` holds one pool
+//! per NUMA socket, so every port can receive into memory local to it.
+//!
+//! `Mempool::stats` reports how many mbufs are free, in use and parked in lcore caches.
+//! A pool with a low-water mark set raises an alarm, logged once, when `check_low_water`
+//! finds fewer mbufs available; the alarm clears once twice the mark is available again.
 
-use std::{ptr::{self, NonNull}, ffi, fmt, mem};
-use super::{MemoryError, WrappedCString};
+use std::{
+	ptr::{self, NonNull},
+	ffi, fmt, mem,
+	os::raw,
+	sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+};
+use super::{MemoryError, Ownership, PacketMeta, WrappedCString};
+
+/// How a mempool of packet mbufs is laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MempoolConfig {
+	/// Number of mbufs in the pool
+	pub capacity: u32,
+	/// Size of the per-lcore cache
+	pub cache_size: u32,
+	/// Bytes of packet data per mbuf, headroom excluded
+	pub data_size: u32,
+	/// Bytes of application private area per mbuf; a multiple of `RTE_MBUF_PRIV_ALIGN`,
+	/// at least `PacketMeta::PRIV_SIZE` for the mbufs to carry a `PacketMeta`
+	pub priv_size: u16,
+	/// NUMA socket to allocate on; the calling lcore's socket if `None`
+	pub socket: Option<i32>,
+	/// Mempool ops backend, e.g. `ring_mp_mc` or `stack`; the platform default if `None`
+	pub ops: Option<String>,
+}
+
+impl Default for MempoolConfig {
+	fn default() -> Self {
+		Self {
+			capacity: Mempool::NUM_MBUFS,
+			cache_size: Mempool::MBUF_CACHE_SIZE,
+			data_size: Mempool::RX_MBUF_DATA_SIZE,
+			priv_size: PacketMeta::PRIV_SIZE,
+			socket: None,
+			ops: None,
+		}
+	}
+}
+
+/// Occupancy of a mempool at one point in time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MempoolStats {
+	pub name: String,
+	/// Number of mbufs in the pool
+	pub capacity: u32,
+	/// Mbufs that can be allocated, lcore caches included
+	pub available: u32,
+	/// Mbufs allocated and not yet freed
+	pub in_use: u32,
+	/// Mbufs parked in the cache of every lcore that has any, as `(lcore, count)`
+	pub cached: Vec<(u32, u32)>,
+}
+
+impl MempoolStats {
+	/// Share of the pool that is available, in percent
+	pub fn available_pct(&self) -> f64 {
+		if self.capacity == 0 {
+			return 0.0;
+		}
+		self.available as f64 * 100.0 / self.capacity as f64
+	}
+}
+
+impl fmt::Display for MempoolStats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"mempool {}: {} of {} mbufs available ({:.1}%), {} in use, {} in lcore caches",
+			self.name,
+			self.available,
+			self.capacity,
+			self.available_pct(),
+			self.in_use,
+			self.cached.iter().map(|(_, count)| count).sum::<u32>()
+		)
+	}
+}
 
 pub struct Mempool {
 	raw: NonNull<dpdk_sys::rte_mempool>,
+	ownership: Ownership,
+	// available mbufs below which the alarm is raised; 0 disables it
+	low_water: AtomicU32,
+	alarm_raised: AtomicBool,
+	alarms: AtomicU64,
 }
 
 impl Mempool {
 	const RX_MBUF_DATA_SIZE: u32 = 2048;
 	const RTE_PKTMBUF_HEADROOM: u32 = 128;
-	const MBUF_OVERHEAD: u32 = mem::size_of::<dpdk_sys::rte_mbuf>() as u32 + Self::RTE_PKTMBUF_HEADROOM;
 	const NUM_MBUFS: u32 = 32767; // 2^15 - 1
-	const MBUF_SIZE: u32 = Self::RX_MBUF_DATA_SIZE + Self::MBUF_OVERHEAD;
 	const MBUF_CACHE_SIZE: u32 = 512;
-	const NO_FLAGS: u32 = 0;
 
-	pub fn new(
+	pub fn new(name: &str) -> Result<Self, MemoryError> {
+		Self::with_config(name, &MempoolConfig::default())
+	}
+
+	/// Create a mempool of `capacity` mbufs with a per-lcore cache of `cache_size` mbufs
+	pub fn with_capacity(
+		name: &str,
+		capacity: u32,
+		cache_size: u32,
+	) -> Result<Self, MemoryError> {
+		Self::with_data_size(name, capacity, cache_size, Self::RX_MBUF_DATA_SIZE)
+	}
+
+	/// Create a mempool whose mbufs hold `data_size` bytes of packet data after the headroom
+	///
+	/// Frames larger than `data_size` are only received by ports with scattered rx.
+	pub fn with_data_size(
 		name: &str,
-		// capacity: usize,
-		// cache_size: usize,
-		// socket_id: raw::c_int
+		capacity: u32,
+		cache_size: u32,
+		data_size: u32,
 	) -> Result<Self, MemoryError> {
+		Self::with_config(
+			name,
+			&MempoolConfig {
+				capacity,
+				cache_size,
+				data_size,
+				..MempoolConfig::default()
+			},
+		)
+	}
+
+	/// Create a mempool laid out as `config` describes
+	pub fn with_config(name: &str, config: &MempoolConfig) -> Result<Self, MemoryError> {
+		// the data room, headroom included, is stored in a u16
+		if config.data_size == 0 || config.data_size + Self::RTE_PKTMBUF_HEADROOM > u16::MAX as u32 {
+			log::error!("mempool {}: bad mbuf data size {}", name, config.data_size);
+			return Err(MemoryError::Invalid);
+		}
+		if config.priv_size as u32 & (dpdk_sys::RTE_MBUF_PRIV_ALIGN - 1) != 0 {
+			log::error!(
+				"mempool {}: private area of {} bytes is not a multiple of {}",
+				name,
+				config.priv_size,
+				dpdk_sys::RTE_MBUF_PRIV_ALIGN
+			);
+			return Err(MemoryError::Invalid);
+		}
 		let n = WrappedCString::to_cstring(name)?;
-		// let raw = unsafe {dpdk_sys::rte_pktmbuf_pool_create(
-		// 		n.as_ptr(),
-		// 		capacity as raw::c_uint,
-		// 		cache_size as raw::c_uint,
-		// 		0,
-		// 		dpdk_sys::RTE_MBUF_DEFAULT_BUF_SIZE as u16,
-		// 		socket_id,
-		// 	)};
-		let raw = unsafe { dpdk_sys::rte_mempool_create(
+		let ops = match &config.ops {
+			Some(ops) => Some(WrappedCString::to_cstring(ops.as_str())?),
+			None => None,
+		};
+		let socket_id = config
+			.socket
+			.unwrap_or_else(|| unsafe { dpdk_sys::rte_socket_id() } as i32);
+		let raw = unsafe { dpdk_sys::rte_pktmbuf_pool_create_by_ops(
 			n.as_ptr(),
-			Self::NUM_MBUFS,
-			Self::MBUF_SIZE,
-			Self::MBUF_CACHE_SIZE,
-			mem::size_of::<dpdk_sys::rte_pktmbuf_pool_private>() as u32,
-			Some(dpdk_sys::rte_pktmbuf_pool_init),
-			ptr::null::<ffi::c_void>() as *mut _,
-			Some(dpdk_sys::rte_pktmbuf_init),
-			ptr::null::<ffi::c_void>() as *mut _,
-			dpdk_sys::rte_socket_id() as i32,
-			Self::NO_FLAGS,
+			config.capacity,
+			config.cache_size,
+			config.priv_size,
+			(config.data_size + Self::RTE_PKTMBUF_HEADROOM) as u16,
+			socket_id as raw::c_int,
+			ops.as_ref().map_or(ptr::null(), |ops| ops.as_ptr()),
 		) };
 		let mempool = NonNull::new(raw);
 		match mempool {
 			Some(mem) => { 
-				log::info!("created mempool: {}", &name);
-				Ok(Self { raw: mem })
+				log::info!("created mempool: {} on socket {}", &name, socket_id);
+				Ok(Self::from_raw(mem, Ownership::Owned))
 			},
 			None => {
 				log::error!("mempool invalid");
@@ -57,6 +179,16 @@ impl Mempool {
 		}
 	}
 
+	fn from_raw(raw: NonNull<dpdk_sys::rte_mempool>, ownership: Ownership) -> Self {
+		Self {
+			raw,
+			ownership,
+			low_water: AtomicU32::new(0),
+			alarm_raised: AtomicBool::new(false),
+			alarms: AtomicU64::new(0),
+		}
+	}
+
 	/// Returns the raw struct pointer
 	#[inline]
 	pub fn raw(&self) -> &dpdk_sys::rte_mempool {
@@ -76,17 +208,135 @@ impl Mempool {
 		self.raw.as_ptr()
 	}
 
+	/// Returns the number of bytes of packet data an mbuf holds after its headroom
+	#[inline]
+	pub fn data_size(&self) -> u32 {
+		self.pool_private().mbuf_data_room_size as u32 - Self::RTE_PKTMBUF_HEADROOM
+	}
+
+	/// Returns the size of the application private area of every mbuf
+	#[inline]
+	pub fn priv_size(&self) -> u16 {
+		self.pool_private().mbuf_priv_size
+	}
+
+	/// Returns the NUMA socket the pool was allocated on
+	#[inline]
+	pub fn socket_id(&self) -> i32 {
+		self.raw().socket_id
+	}
+
+	/// Number of mbufs that can be allocated right now, lcore caches included
+	#[inline]
+	pub fn available(&self) -> u32 {
+		unsafe { dpdk_sys::rte_mempool_avail_count(self.get_ptr()) }
+	}
+
+	/// Take a snapshot of the occupancy of the pool
+	pub fn stats(&self) -> MempoolStats {
+		let raw = self.raw();
+		let mut cached = Vec::new();
+		if raw.cache_size != 0 && !raw.local_cache.is_null() {
+			for lcore in 0..dpdk_sys::RTE_MAX_LCORE {
+				let len = unsafe { (*raw.local_cache.add(lcore as usize)).len };
+				if len > 0 {
+					cached.push((lcore, len));
+				}
+			}
+		}
+		MempoolStats {
+			name: self.name(),
+			capacity: raw.size,
+			available: self.available(),
+			in_use: unsafe { dpdk_sys::rte_mempool_in_use_count(self.get_ptr()) },
+			cached,
+		}
+	}
+
+	/// Raise an alarm when fewer than `available` mbufs are left; 0 disables the alarm
+	pub fn set_low_water(&self, available: u32) {
+		self.low_water.store(available, Ordering::Relaxed);
+	}
+
+	/// Set the low-water mark to `pct` percent of the capacity
+	pub fn set_low_water_pct(&self, pct: u8) {
+		self.set_low_water((self.raw().size as u64 * pct.min(100) as u64 / 100) as u32);
+	}
+
+	/// The low-water mark; 0 if there is none
+	#[inline]
+	pub fn low_water(&self) -> u32 {
+		self.low_water.load(Ordering::Relaxed)
+	}
+
+	/// Compare the available mbufs with the low-water mark and raise or clear the alarm
+	///
+	/// The alarm is logged when it is raised and when it clears, not on every check.
+	/// Returns whether the alarm is raised.
+	pub fn check_low_water(&self) -> bool {
+		let low_water = self.low_water();
+		if low_water == 0 {
+			return false;
+		}
+		let available = self.available();
+		if available < low_water {
+			if !self.alarm_raised.swap(true, Ordering::Relaxed) {
+				self.alarms.fetch_add(1, Ordering::Relaxed);
+				log::warn!("{} (low water {})", self.stats(), low_water);
+			}
+			true
+		} else if available >= low_water.saturating_mul(2).min(self.raw().size) {
+			if self.alarm_raised.swap(false, Ordering::Relaxed) {
+				log::info!("mempool {}: {} mbufs available again", self.name(), available);
+			}
+			false
+		} else {
+			self.alarm_raised.load(Ordering::Relaxed)
+		}
+	}
+
+	/// Number of times the low-water alarm has been raised
+	#[inline]
+	pub fn alarms(&self) -> u64 {
+		self.alarms.load(Ordering::Relaxed)
+	}
+
+	/// The mbuf layout the pool was created with, `rte_mempool_get_priv` in Rust
+	fn pool_private(&self) -> &dpdk_sys::rte_pktmbuf_pool_private {
+		// the private area follows the header and, if the pool has a cache, the per-lcore caches
+		let mut offset = mem::size_of::<dpdk_sys::rte_mempool>();
+		if self.raw().cache_size != 0 {
+			offset += mem::size_of::<dpdk_sys::rte_mempool_cache>() * dpdk_sys::RTE_MAX_LCORE as usize;
+		}
+		unsafe { &*((self.get_ptr() as *const u8).add(offset) as *const dpdk_sys::rte_pktmbuf_pool_private) }
+	}
+
 	/// Returns the name of the mempool
 	#[inline]
 	pub fn name(&self) -> String {
-		unsafe { ffi::CString::from_raw(&self.raw().name.clone() as *const i8 as *mut i8).to_str().unwrap_or("Name undefined").to_owned() }
+		unsafe { ffi::CStr::from_ptr(self.raw().name.as_ptr()) }.to_string_lossy().into_owned()
+	}
+
+	/// Whether this handle created the mempool or was attached to it by `lookup`
+	#[inline]
+	pub fn ownership(&self) -> Ownership {
+		self.ownership
+	}
+
+	/// Whether dropping this handle frees the mempool
+	#[inline]
+	pub fn is_owner(&self) -> bool {
+		self.ownership == Ownership::Owned
 	}
 
+	/// Attach to the mempool created under `name`, usually by another process
+	///
+	/// The returned handle does not free the mempool when dropped.
 	pub fn lookup(name: &str) -> Result<Self, MemoryError> {
 		let nm = WrappedCString::to_cstring(name)?;
 		let r = unsafe { dpdk_sys::rte_mempool_lookup(nm.as_ptr()) };
 		match NonNull::new(r) {
-			Some(raw) => Ok(Self { raw }),
+			Some(raw) => Ok(Self::from_raw(raw, Ownership::Attached)),
 			None => Err(MemoryError::NoEntries),
 		}
 	}
@@ -107,10 +357,127 @@ impl fmt::Debug for Mempool {
 	}
 }
 
+/// Mempools sharing one layout, one per NUMA socket or a single one for all of them
+pub struct MempoolSet {
+	// at least one
+	pools: Vec<Mempool>,
+}
+
+impl MempoolSet {
+	/// A set of just `pool`, used whatever the socket
+	pub fn single(pool: Mempool) -> Self {
+		Self { pools: vec![pool] }
+	}
+
+	/// Create a pool named `<name>_<socket>` on every NUMA socket
+	///
+	/// `config.socket` is ignored. Sockets whose pool cannot be created, e.g. for lack of
+	/// hugepages, are skipped and use another socket's pool.
+	pub fn per_socket(name: &str, config: &MempoolConfig) -> Result<Self, MemoryError> {
+		let mut pools = Vec::new();
+		let mut error = None;
+		for socket in Self::sockets() {
+			let config = MempoolConfig {
+				socket: Some(socket),
+				..config.clone()
+			};
+			match Mempool::with_config(&Self::socket_name(name, socket), &config) {
+				Ok(pool) => pools.push(pool),
+				Err(e) => {
+					log::warn!("mempool {}: no pool on socket {}: {}", name, socket, e);
+					error = Some(e);
+				}
+			}
+		}
+		match (pools.is_empty(), error) {
+			(false, _) => Ok(Self { pools }),
+			(true, Some(e)) => Err(e),
+			(true, None) => Err(MemoryError::NoMem),
+		}
+	}
+
+	/// Attach to the pools created by `single` or `per_socket` under `name`
+	pub fn lookup(name: &str) -> Result<Self, MemoryError> {
+		if let Ok(pool) = Mempool::lookup(name) {
+			return Ok(Self::single(pool));
+		}
+		let pools = Self::sockets()
+			.into_iter()
+			.filter_map(|socket| Mempool::lookup(&Self::socket_name(name, socket)).ok())
+			.collect::<Vec<_>>();
+		if pools.is_empty() {
+			return Err(MemoryError::NoEntries);
+		}
+		Ok(Self { pools })
+	}
+
+	/// The pool on `socket`, or any pool if there is none there or `socket` is `SOCKET_ID_ANY`
+	pub fn for_socket(&self, socket: i32) -> &Mempool {
+		self.pools
+			.iter()
+			.find(|pool| pool.socket_id() == socket)
+			.unwrap_or(&self.pools[0])
+	}
+
+	/// The pool local to the calling lcore
+	#[inline]
+	pub fn local(&self) -> &Mempool {
+		self.for_socket(unsafe { dpdk_sys::rte_socket_id() } as i32)
+	}
+
+	/// Every pool in the set
+	#[inline]
+	pub fn pools(&self) -> &[Mempool] {
+		&self.pools
+	}
+
+	/// Set the low-water mark of every pool to `pct` percent of its capacity
+	pub fn set_low_water_pct(&self, pct: u8) {
+		self.pools.iter().for_each(|pool| pool.set_low_water_pct(pct));
+	}
+
+	/// Check the low-water mark of every pool; returns whether any alarm is raised
+	pub fn check_low_water(&self) -> bool {
+		// every pool is checked, not just up to the first one in alarm
+		let mut raised = false;
+		for pool in &self.pools {
+			raised |= pool.check_low_water();
+		}
+		raised
+	}
+
+	/// Snapshots of every pool
+	pub fn stats(&self) -> Vec<MempoolStats> {
+		self.pools.iter().map(Mempool::stats).collect()
+	}
+
+	/// Name of the pool `per_socket` creates on `socket`
+	pub fn socket_name(name: &str, socket: i32) -> String {
+		format!("{}_{}", name, socket)
+	}
+
+	/// NUMA sockets the EAL detected
+	fn sockets() -> Vec<i32> {
+		let count = unsafe { dpdk_sys::rte_socket_count() };
+		(0..count)
+			.map(|idx| unsafe { dpdk_sys::rte_socket_id_by_idx(idx) })
+			.filter(|&socket| socket >= 0)
+			.collect()
+	}
+}
+
+impl fmt::Debug for MempoolSet {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_list().entries(&self.pools).finish()
+	}
+}
+
 impl Drop for Mempool {
 	fn drop(&mut self) {
-		unsafe {
-			dpdk_sys::rte_mempool_free(self.raw_mut());
+		if self.is_owner() {
+			unsafe {
+				dpdk_sys::rte_mempool_free(self.raw_mut());
+			}
 		}
 	}
-}
\ No newline at end of file
+}
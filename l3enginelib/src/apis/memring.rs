@@ -10,13 +10,14 @@
 use anyhow::Result;
 use chashmap::CHashMap;
 use std::{
+	cell::{Cell, RefCell},
 	marker::{Send, Sync},
 	os::raw,
 	ptr,
 	ptr::NonNull,
 };
 
-use super::{Mbuf, MemoryError, RingClientMapError, WrappedCString};
+use super::{Mbuf, MemoryError, Ownership, RingClientMapError, WrappedCString};
 
 /// The RingType is whether message is being sent from engine to container or from contianer to engine
 pub enum RingType {
@@ -33,6 +34,7 @@ pub struct Ring {
 	client_id: u16,
 	rtype: RingType,
 	raw: NonNull<dpdk_sys::rte_ring>,
+	ownership: Ownership,
 }
 
 impl Ring {
@@ -43,6 +45,8 @@ impl Ring {
 	const RING_CAPACITY: usize = 512;
 
 	/// Return a Ring created from a pointer if the pointer is not null
+	///
+	/// The Ring is attached: it does not free the ring when dropped.
 	pub fn from_ptr(
 		client_id: u16,
 		rtype: RingType,
@@ -53,6 +57,7 @@ impl Ring {
 				client_id,
 				rtype,
 				raw,
+				ownership: Ownership::Attached,
 			})
 		} else {
 			Err(MemoryError::NoBuf)
@@ -63,6 +68,16 @@ impl Ring {
 		client_id: u16,
 		rtype: RingType,
 		socket_id: raw::c_int,
+	) -> Result<Self, MemoryError> {
+		Self::with_capacity(client_id, rtype, socket_id, Self::RING_CAPACITY)
+	}
+
+	/// Create a ring with `capacity` slots; `capacity` must be a power of two
+	pub fn with_capacity(
+		client_id: u16,
+		rtype: RingType,
+		socket_id: raw::c_int,
+		capacity: usize,
 	) -> Result<Self, MemoryError> {
 		let r;
 		match &rtype {
@@ -73,7 +88,7 @@ impl Ring {
 		match NonNull::new(unsafe {
 			dpdk_sys::rte_ring_create(
 				nm.as_ptr(),
-				Self::RING_CAPACITY as raw::c_uint,
+				capacity as raw::c_uint,
 				socket_id,
 				Self::RING_FLAGS as raw::c_uint,
 			)
@@ -82,6 +97,7 @@ impl Ring {
 				client_id,
 				rtype,
 				raw,
+				ownership: Ownership::Owned,
 			}),
 			None => Err(MemoryError::new()),
 		}
@@ -99,7 +115,21 @@ impl Ring {
 		format!("{}{}", name, id)
 	}
 
+	/// Whether this Ring created the ring or was attached to it
+	#[inline]
+	pub fn ownership(&self) -> Ownership {
+		self.ownership
+	}
+
+	/// Whether dropping this Ring frees the ring
+	#[inline]
+	pub fn is_owner(&self) -> bool {
+		self.ownership == Ownership::Owned
+	}
+
 	/// Lookup a Ring
+	///
+	/// The ring stays owned by its creator; dropping the returned Ring does not free it.
 	pub fn lookup(rtype: RingType, client_id: u16) -> Result<Self, MemoryError> {
 		let r;
 		match &rtype {
@@ -119,31 +149,64 @@ impl Ring {
 
 	/// Enqueue a single packet onto the ring
 	pub fn enqueue(&self, pkt: Mbuf) -> Result<(), MemoryError> {
-		match unsafe {
-			dpdk_sys::_rte_ring_enqueue(self.get_ptr(), pkt.into_ptr() as *mut raw::c_void)
-		} {
+		let ptr = pkt.into_ptr();
+		match unsafe { dpdk_sys::_rte_ring_enqueue(self.get_ptr(), ptr as *mut raw::c_void) } {
 			0 => {
 				#[cfg(feature = "debug")]
 				println!("enqueued packets");
 				Ok(())
 			}
-			_ => Err(MemoryError::new()),
+			_ => {
+				// the ring is full; the packet is dropped
+				drop(unsafe { Mbuf::from_ptr(ptr) });
+				Err(MemoryError::NoBuf)
+			}
 		}
 	}
 
 	/// Dequeue a single packet from the ring
+	///
+	/// The dequeued packet replaces `pkt`, which is freed.
 	pub fn dequeue(&self, pkt: &mut Mbuf) -> Result<(), MemoryError> {
-		match unsafe {
-			dpdk_sys::_rte_ring_dequeue(
-				self.get_ptr(),
-				&mut (pkt.get_ptr() as *mut _ as *mut raw::c_void),
-			)
-		} {
-			0 => Ok(()),
+		let mut obj: *mut raw::c_void = ptr::null_mut();
+		match unsafe { dpdk_sys::_rte_ring_dequeue(self.get_ptr(), &mut obj) } {
+			0 => {
+				*pkt = unsafe { Mbuf::from_ptr(obj as *mut dpdk_sys::rte_mbuf) };
+				Ok(())
+			}
 			_ => Err(MemoryError::new()),
 		}
 	}
 
+	/// Dequeue and free every packet left on the ring
+	///
+	/// Returns the number of packets freed. Nothing may be dequeuing from the ring at the same time.
+	pub fn drain(&self) -> usize {
+		const DRAIN_BURST: usize = 32;
+		let mut objs = [ptr::null_mut::<raw::c_void>(); DRAIN_BURST];
+		let mut count = 0;
+		loop {
+			let n = unsafe {
+				dpdk_sys::_rte_ring_dequeue_burst(
+					self.get_ptr(),
+					objs.as_mut_ptr(),
+					DRAIN_BURST as u32,
+					ptr::null_mut(),
+				)
+			} as usize;
+			if n == 0 {
+				return count;
+			}
+			Mbuf::mbuf_free_bulk(
+				objs[..n]
+					.iter()
+					.map(|&obj| obj as *mut dpdk_sys::rte_mbuf)
+					.collect(),
+			);
+			count += n;
+		}
+	}
+
 	/// Enqueue a single packet onto the ring
 	pub fn enqueue_bulk(&self, mut pkts: Vec<Mbuf>) -> usize {
 		#[cfg(feature = "debug")]
@@ -219,8 +282,10 @@ impl Ring {
 
 impl Drop for Ring {
 	fn drop(&mut self) {
-		unsafe {
-			dpdk_sys::rte_ring_free(self.raw_mut());
+		if self.is_owner() {
+			unsafe {
+				dpdk_sys::rte_ring_free(self.raw_mut());
+			}
 		}
 	}
 }
@@ -238,10 +303,17 @@ unsafe impl Sync for Channel {}
 
 impl Channel {
 	pub fn new(client_id: u16) -> Result<Self, MemoryError> {
+		Self::with_capacity(client_id, Ring::RING_CAPACITY)
+	}
+
+	/// Create a channel whose rings have `capacity` slots each
+	pub fn with_capacity(client_id: u16, capacity: usize) -> Result<Self, MemoryError> {
 		let socket_id = unsafe { dpdk_sys::rte_socket_id() };
 
-		let engine_to_client = Ring::new(client_id, RingType::E2C, socket_id as i32)?;
-		let client_to_engine = Ring::new(client_id, RingType::C2E, socket_id as i32)?;
+		let engine_to_client =
+			Ring::with_capacity(client_id, RingType::E2C, socket_id as i32, capacity)?;
+		let client_to_engine =
+			Ring::with_capacity(client_id, RingType::C2E, socket_id as i32, capacity)?;
 
 		Ok(Self {
 			client_to_engine,
@@ -250,6 +322,8 @@ impl Channel {
 	}
 
 	/// Lookup both C2E and E2C rings for this channel
+	///
+	/// The rings are attached and left to the process that created them.
 	pub fn lookup(client_id: u16) -> Result<Self, MemoryError> {
 		let engine_to_client = Ring::lookup(RingType::E2C, client_id)?;
 		let client_to_engine = Ring::lookup(RingType::C2E, client_id)?;
@@ -311,17 +385,43 @@ impl Channel {
 		println!("recv_from_engine_bulk");
 		self.engine_to_client.dequeue_burst(pkts, rx_burst_max)
 	}
+
+	/// Free the packets left in both directions; returns how many there were
+	pub fn drain(&self) -> usize {
+		self.drain_from_client() + self.drain_to_client()
+	}
+
+	/// Free the packets the client sent and the engine has not received yet
+	///
+	/// The engine is the only consumer of this ring, so it must not be receiving at the same time.
+	pub fn drain_from_client(&self) -> usize {
+		self.client_to_engine.drain()
+	}
+
+	/// Free the packets sent to the client and not received yet
+	///
+	/// The client is the only consumer of this ring, so it must have stopped.
+	pub fn drain_to_client(&self) -> usize {
+		self.engine_to_client.drain()
+	}
 }
 
 /// Channel to Client mapping
 pub struct RingClientMap {
 	pub(crate) ringmap: CHashMap<u16, Channel>,
+	ring_size: usize,
 }
 
 impl RingClientMap {
 	pub fn new() -> Self {
+		Self::with_ring_size(Ring::RING_CAPACITY)
+	}
+
+	/// Create a map whose client channels use rings of `ring_size` slots
+	pub fn with_ring_size(ring_size: usize) -> Self {
 		Self {
 			ringmap: CHashMap::new(),
+			ring_size,
 		}
 	}
 
@@ -334,7 +434,7 @@ impl RingClientMap {
 		#[cfg(feature = "debug")]
 		println!("add_client: adding {}", client_id);
 		let channel;
-		match Channel::new(client_id) {
+		match Channel::with_capacity(client_id, self.ring_size) {
 			Ok(ch) => {
 				#[cfg(feature = "debug")]
 				{
@@ -360,6 +460,54 @@ impl RingClientMap {
 		self.ringmap.remove(&client_id);
 	}
 
+	/// Ids of every client
+	pub fn client_ids(&self) -> Vec<u16> {
+		let ids = RefCell::new(Vec::with_capacity(self.len()));
+		self.ringmap.retain(|&id, _| {
+			ids.borrow_mut().push(id);
+			true
+		});
+		ids.into_inner()
+	}
+
+	/// Free the packets left in both directions of the channel of `client_id`;
+	/// the client must have stopped
+	pub fn drain_client(&self, client_id: u16) -> Result<usize, RingClientMapError> {
+		match self.ringmap.get(&client_id) {
+			Some(channel) => Ok(channel.drain()),
+			None => Err(RingClientMapError::ClientNotFound(client_id)),
+		}
+	}
+
+	/// Free the packets left in the channels of every client; returns how many there were
+	pub fn drain(&self) -> usize {
+		self.drain_with(Channel::drain)
+	}
+
+	/// Free the packets every client sent and the engine has not received yet
+	pub fn drain_from_clients(&self) -> usize {
+		self.drain_with(Channel::drain_from_client)
+	}
+
+	/// Free the packets sent to every client and not received yet; the clients must have stopped
+	pub fn drain_to_clients(&self) -> usize {
+		self.drain_with(Channel::drain_to_client)
+	}
+
+	fn drain_with<F: Fn(&Channel) -> usize>(&self, drain: F) -> usize {
+		let count = Cell::new(0);
+		self.ringmap.retain(|_, channel| {
+			count.set(count.get() + drain(channel));
+			true
+		});
+		count.get()
+	}
+
+	/// Remove every client, freeing its rings
+	pub fn clear(&self) {
+		self.ringmap.clear();
+	}
+
 	/// Send a packet to a client
 	pub fn send(&self, key: u16, pkt: Mbuf) -> Result<(), RingClientMapError> {
 		let channel;
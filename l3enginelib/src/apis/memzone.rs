@@ -3,12 +3,13 @@
  * Created by Ratnadeep Bhattacharya
  */
 
-use super::{MemoryError, WrappedCString};
+use super::{MemoryError, Ownership, WrappedCString};
 use dpdk_sys;
-use std::result::Result;
+use std::{ffi, os::raw, result::Result};
 
 pub struct Memzone {
 	raw: *const dpdk_sys::rte_memzone,
+	ownership: Ownership,
 }
 
 impl Memzone {
@@ -25,26 +26,101 @@ impl Memzone {
 		if raw.is_null() {
 			return Err(MemoryError::new());
 		}
-		Ok(Self { raw })
+		Ok(Self {
+			raw,
+			ownership: Ownership::Owned,
+		})
 	}
 
+	/// Reserve `len` bytes on `socket` starting at a multiple of `align`, a power of two
+	///
+	/// 2MB pages are preferred; other page sizes are used if there are none left.
+	pub fn reserve_aligned(
+		name: &str,
+		len: usize,
+		socket: i32,
+		align: u32,
+	) -> Result<Self, MemoryError> {
+		let nm = WrappedCString::to_cstring(name)?;
+		let raw = unsafe {
+			dpdk_sys::rte_memzone_reserve_aligned(
+				nm.as_ptr(),
+				len as u64,
+				socket,
+				dpdk_sys::RTE_MEMZONE_2MB | dpdk_sys::RTE_MEMZONE_SIZE_HINT_ONLY,
+				align,
+			)
+		};
+		if raw.is_null() {
+			return Err(MemoryError::new());
+		}
+		Ok(Self {
+			raw,
+			ownership: Ownership::Owned,
+		})
+	}
+
+	/// Attach to the memzone reserved under `name`; dropping the result does not free it
 	pub fn lookup(name: &str) -> Result<Self, MemoryError> {
 		let nm = WrappedCString::to_cstring(name)?;
 		let raw = unsafe { dpdk_sys::rte_memzone_lookup(nm.as_ptr()) };
 		if raw.is_null() {
 			return Err(MemoryError::new());
 		}
-		Ok(Self { raw })
+		Ok(Self {
+			raw,
+			ownership: Ownership::Attached,
+		})
+	}
+
+	/// Whether this handle reserved the memzone or was attached to it
+	#[inline]
+	pub fn ownership(&self) -> Ownership {
+		self.ownership
+	}
+
+	/// Whether dropping this handle frees the memzone
+	#[inline]
+	pub fn is_owner(&self) -> bool {
+		self.ownership == Ownership::Owned
 	}
 
 	pub fn virt_addr(&self) -> u64 {
 		// unsafe { (*self.raw).__bindgen_anon_1.addr_64 }
 		unsafe { (*self.raw).__bindgen_anon_2.addr_64 }
 	}
+
+	/// Start of the memzone in this process
+	#[inline]
+	pub fn addr(&self) -> *mut raw::c_void {
+		unsafe { (*self.raw).__bindgen_anon_2.addr }
+	}
+
+	/// Length of the memzone in bytes; at least the length it was reserved with
+	#[inline]
+	pub fn size(&self) -> usize {
+		unsafe { (*self.raw).len as usize }
+	}
+
+	/// NUMA socket the memzone was reserved on
+	#[inline]
+	pub fn socket_id(&self) -> i32 {
+		unsafe { (*self.raw).socket_id }
+	}
+
+	/// Name the memzone was reserved under
+	pub fn name(&self) -> String {
+		unsafe { ffi::CStr::from_ptr((*self.raw).name.as_ptr()) }
+			.to_string_lossy()
+			.into_owned()
+	}
 }
 
 impl Drop for Memzone {
 	fn drop(&mut self) {
+		if !self.is_owner() {
+			return;
+		}
 		match unsafe { dpdk_sys::rte_memzone_free(self.raw) } {
 			0 => {}
 			_ => log::error!("resource release failed; invalid memzone"),
@@ -0,0 +1,423 @@
+//! Typed access to the metadata the NIC and the PMD leave in an mbuf
+//!
+//! `OffloadFlags` wraps `ol_flags` and `PacketType` wraps `packet_type`, the
+//! classification of the packet's headers. With them the rx path can use what the
+//! NIC found out about a packet (checksums, headers, RSS hash, stripped VLAN tag,
+//! timestamp) instead of parsing the packet again.
+
+use std::fmt;
+
+use super::{FlagKind, Flags, Mbuf};
+
+/// Marker for the `PKT_RX_*` and `PKT_TX_*` flags of an mbuf
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MbufFlags {}
+
+impl FlagKind for MbufFlags {
+	const NAMES: &'static [(&'static str, u64)] = &[
+		("rx_vlan", OffloadFlags::RX_VLAN.bits()),
+		("rx_vlan_stripped", OffloadFlags::RX_VLAN_STRIPPED.bits()),
+		("rx_qinq", OffloadFlags::RX_QINQ.bits()),
+		("rx_qinq_stripped", OffloadFlags::RX_QINQ_STRIPPED.bits()),
+		("rx_rss_hash", OffloadFlags::RX_RSS_HASH.bits()),
+		("rx_fdir", OffloadFlags::RX_FDIR.bits()),
+		("rx_fdir_id", OffloadFlags::RX_FDIR_ID.bits()),
+		("rx_ip_cksum_good", OffloadFlags::RX_IP_CKSUM_GOOD.bits()),
+		("rx_ip_cksum_bad", OffloadFlags::RX_IP_CKSUM_BAD.bits()),
+		("rx_l4_cksum_good", OffloadFlags::RX_L4_CKSUM_GOOD.bits()),
+		("rx_l4_cksum_bad", OffloadFlags::RX_L4_CKSUM_BAD.bits()),
+		("rx_lro", OffloadFlags::RX_LRO.bits()),
+		("rx_timestamp", OffloadFlags::RX_TIMESTAMP.bits()),
+		("tx_vlan", OffloadFlags::TX_VLAN.bits()),
+		("tx_qinq", OffloadFlags::TX_QINQ.bits()),
+		("tx_ipv4", OffloadFlags::TX_IPV4.bits()),
+		("tx_ipv6", OffloadFlags::TX_IPV6.bits()),
+		("tx_ip_cksum", OffloadFlags::TX_IP_CKSUM.bits()),
+		// the tx l4 checksum flags are values of a two bit field
+		("tx_udp_cksum", OffloadFlags::TX_UDP_CKSUM.bits()),
+		("tx_tcp_cksum", OffloadFlags::TX_TCP_CKSUM.bits()),
+		("tx_tcp_seg", OffloadFlags::TX_TCP_SEG.bits()),
+	];
+	const KIND: &'static str = "ol_flags";
+}
+
+/// The `PKT_RX_*` and `PKT_TX_*` flags of an mbuf (`ol_flags`)
+pub type OffloadFlags = Flags<MbufFlags>;
+
+impl OffloadFlags {
+	/// The VLAN tag is in `vlan_tci`
+	pub const RX_VLAN: Self = Self::from_bits(dpdk_sys::PKT_RX_VLAN as u64);
+	/// The VLAN tag was stripped from the packet
+	pub const RX_VLAN_STRIPPED: Self = Self::from_bits(dpdk_sys::PKT_RX_VLAN_STRIPPED as u64);
+	/// Both VLAN tags are in `vlan_tci` and `vlan_tci_outer`
+	pub const RX_QINQ: Self = Self::from_bits(dpdk_sys::PKT_RX_QINQ as u64);
+	/// Both VLAN tags were stripped from the packet
+	pub const RX_QINQ_STRIPPED: Self = Self::from_bits(dpdk_sys::PKT_RX_QINQ_STRIPPED as u64);
+	/// The RSS hash is valid
+	pub const RX_RSS_HASH: Self = Self::from_bits(dpdk_sys::PKT_RX_RSS_HASH as u64);
+	/// A flow director rule matched
+	pub const RX_FDIR: Self = Self::from_bits(dpdk_sys::PKT_RX_FDIR as u64);
+	/// A flow rule marked the packet
+	pub const RX_FDIR_ID: Self = Self::from_bits(dpdk_sys::PKT_RX_FDIR_ID as u64);
+	pub const RX_IP_CKSUM_GOOD: Self = Self::from_bits(dpdk_sys::PKT_RX_IP_CKSUM_GOOD as u64);
+	pub const RX_IP_CKSUM_BAD: Self = Self::from_bits(dpdk_sys::PKT_RX_IP_CKSUM_BAD as u64);
+	pub const RX_L4_CKSUM_GOOD: Self = Self::from_bits(dpdk_sys::PKT_RX_L4_CKSUM_GOOD as u64);
+	pub const RX_L4_CKSUM_BAD: Self = Self::from_bits(dpdk_sys::PKT_RX_L4_CKSUM_BAD as u64);
+	/// The packet was coalesced by LRO
+	pub const RX_LRO: Self = Self::from_bits(dpdk_sys::PKT_RX_LRO as u64);
+	/// The timestamp is valid
+	pub const RX_TIMESTAMP: Self = Self::from_bits(dpdk_sys::PKT_RX_TIMESTAMP as u64);
+	/// Insert the VLAN tag in `vlan_tci` on transmit
+	pub const TX_VLAN: Self = Self::from_bits(dpdk_sys::PKT_TX_VLAN);
+	/// Insert both VLAN tags on transmit
+	pub const TX_QINQ: Self = Self::from_bits(dpdk_sys::PKT_TX_QINQ);
+	pub const TX_IPV4: Self = Self::from_bits(dpdk_sys::PKT_TX_IPV4);
+	pub const TX_IPV6: Self = Self::from_bits(dpdk_sys::PKT_TX_IPV6);
+	pub const TX_IP_CKSUM: Self = Self::from_bits(dpdk_sys::PKT_TX_IP_CKSUM);
+	pub const TX_TCP_CKSUM: Self = Self::from_bits(dpdk_sys::PKT_TX_TCP_CKSUM);
+	pub const TX_UDP_CKSUM: Self = Self::from_bits(dpdk_sys::PKT_TX_UDP_CKSUM);
+	pub const TX_TCP_SEG: Self = Self::from_bits(dpdk_sys::PKT_TX_TCP_SEG);
+
+	/// What the NIC found of the IPv4 header checksum
+	#[inline]
+	pub fn ip_cksum(&self) -> CksumStatus {
+		CksumStatus::from_bits(
+			self.bits() & dpdk_sys::PKT_RX_IP_CKSUM_MASK as u64,
+			dpdk_sys::PKT_RX_IP_CKSUM_GOOD as u64,
+			dpdk_sys::PKT_RX_IP_CKSUM_BAD as u64,
+		)
+	}
+
+	/// What the NIC found of the TCP or UDP checksum
+	#[inline]
+	pub fn l4_cksum(&self) -> CksumStatus {
+		CksumStatus::from_bits(
+			self.bits() & dpdk_sys::PKT_RX_L4_CKSUM_MASK as u64,
+			dpdk_sys::PKT_RX_L4_CKSUM_GOOD as u64,
+			dpdk_sys::PKT_RX_L4_CKSUM_BAD as u64,
+		)
+	}
+}
+
+/// Checksum verdict of the NIC, or of the software fallback, for a received packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CksumStatus {
+	/// Not checked
+	Unknown,
+	Good,
+	Bad,
+	/// The checksum is not set in the packet but the data is valid
+	None,
+}
+
+impl CksumStatus {
+	fn from_bits(bits: u64, good: u64, bad: u64) -> Self {
+		match bits {
+			0 => CksumStatus::Unknown,
+			b if b == good => CksumStatus::Good,
+			b if b == bad => CksumStatus::Bad,
+			_ => CksumStatus::None,
+		}
+	}
+}
+
+/// Link layer of a packet as classified by the NIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum L2Type {
+	Unknown,
+	Ether,
+	EtherTimesync,
+	EtherArp,
+	EtherLldp,
+	EtherNsh,
+	EtherVlan,
+	EtherQinq,
+	EtherPppoe,
+	EtherFcoe,
+	EtherMpls,
+	Other(u32),
+}
+
+/// Network layer of a packet as classified by the NIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum L3Type {
+	Unknown,
+	Ipv4,
+	/// IPv4 with options
+	Ipv4Ext,
+	/// IPv4, with or without options
+	Ipv4ExtUnknown,
+	Ipv6,
+	/// IPv6 with extension headers
+	Ipv6Ext,
+	/// IPv6, with or without extension headers
+	Ipv6ExtUnknown,
+	Other(u32),
+}
+
+/// Transport layer of a packet as classified by the NIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum L4Type {
+	Unknown,
+	Tcp,
+	Udp,
+	/// An IP fragment
+	Frag,
+	Sctp,
+	Icmp,
+	/// Some other protocol over an unfragmented IP packet
+	NonFrag,
+	Igmp,
+	Other(u32),
+}
+
+/// Classification of a packet's headers (`packet_type`, `RTE_PTYPE_*`)
+///
+/// Only the outer headers are decoded; `tunnel` tells whether inner ones follow.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PacketType {
+	bits: u32,
+}
+
+impl PacketType {
+	pub const fn from_bits(bits: u32) -> Self {
+		Self { bits }
+	}
+
+	/// Nothing known about the packet
+	pub const fn unknown() -> Self {
+		Self::from_bits(0)
+	}
+
+	/// A packet type made of the three outer layers
+	pub fn new(l2: L2Type, l3: L3Type, l4: L4Type) -> Self {
+		Self::from_bits(l2.bits() | l3.bits() | l4.bits())
+	}
+
+	#[inline]
+	pub fn bits(&self) -> u32 {
+		self.bits
+	}
+
+	#[inline]
+	pub fn is_unknown(&self) -> bool {
+		self.bits == 0
+	}
+
+	pub fn l2(&self) -> L2Type {
+		match self.bits & dpdk_sys::RTE_PTYPE_L2_MASK {
+			0 => L2Type::Unknown,
+			dpdk_sys::RTE_PTYPE_L2_ETHER => L2Type::Ether,
+			dpdk_sys::RTE_PTYPE_L2_ETHER_TIMESYNC => L2Type::EtherTimesync,
+			dpdk_sys::RTE_PTYPE_L2_ETHER_ARP => L2Type::EtherArp,
+			dpdk_sys::RTE_PTYPE_L2_ETHER_LLDP => L2Type::EtherLldp,
+			dpdk_sys::RTE_PTYPE_L2_ETHER_NSH => L2Type::EtherNsh,
+			dpdk_sys::RTE_PTYPE_L2_ETHER_VLAN => L2Type::EtherVlan,
+			dpdk_sys::RTE_PTYPE_L2_ETHER_QINQ => L2Type::EtherQinq,
+			dpdk_sys::RTE_PTYPE_L2_ETHER_PPPOE => L2Type::EtherPppoe,
+			dpdk_sys::RTE_PTYPE_L2_ETHER_FCOE => L2Type::EtherFcoe,
+			dpdk_sys::RTE_PTYPE_L2_ETHER_MPLS => L2Type::EtherMpls,
+			other => L2Type::Other(other),
+		}
+	}
+
+	pub fn l3(&self) -> L3Type {
+		match self.bits & dpdk_sys::RTE_PTYPE_L3_MASK {
+			0 => L3Type::Unknown,
+			dpdk_sys::RTE_PTYPE_L3_IPV4 => L3Type::Ipv4,
+			dpdk_sys::RTE_PTYPE_L3_IPV4_EXT => L3Type::Ipv4Ext,
+			dpdk_sys::RTE_PTYPE_L3_IPV4_EXT_UNKNOWN => L3Type::Ipv4ExtUnknown,
+			dpdk_sys::RTE_PTYPE_L3_IPV6 => L3Type::Ipv6,
+			dpdk_sys::RTE_PTYPE_L3_IPV6_EXT => L3Type::Ipv6Ext,
+			dpdk_sys::RTE_PTYPE_L3_IPV6_EXT_UNKNOWN => L3Type::Ipv6ExtUnknown,
+			other => L3Type::Other(other),
+		}
+	}
+
+	pub fn l4(&self) -> L4Type {
+		match self.bits & dpdk_sys::RTE_PTYPE_L4_MASK {
+			0 => L4Type::Unknown,
+			dpdk_sys::RTE_PTYPE_L4_TCP => L4Type::Tcp,
+			dpdk_sys::RTE_PTYPE_L4_UDP => L4Type::Udp,
+			dpdk_sys::RTE_PTYPE_L4_FRAG => L4Type::Frag,
+			dpdk_sys::RTE_PTYPE_L4_SCTP => L4Type::Sctp,
+			dpdk_sys::RTE_PTYPE_L4_ICMP => L4Type::Icmp,
+			dpdk_sys::RTE_PTYPE_L4_NONFRAG => L4Type::NonFrag,
+			dpdk_sys::RTE_PTYPE_L4_IGMP => L4Type::Igmp,
+			other => L4Type::Other(other),
+		}
+	}
+
+	/// Is the packet a tunnel, with inner headers after the outer ones
+	#[inline]
+	pub fn is_tunnel(&self) -> bool {
+		self.bits & dpdk_sys::RTE_PTYPE_TUNNEL_MASK != 0
+	}
+
+	#[inline]
+	pub fn is_ipv4(&self) -> bool {
+		matches!(self.l3(), L3Type::Ipv4 | L3Type::Ipv4Ext | L3Type::Ipv4ExtUnknown)
+	}
+
+	#[inline]
+	pub fn is_ipv6(&self) -> bool {
+		matches!(self.l3(), L3Type::Ipv6 | L3Type::Ipv6Ext | L3Type::Ipv6ExtUnknown)
+	}
+}
+
+impl fmt::Debug for PacketType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("PacketType")
+			.field("l2", &self.l2())
+			.field("l3", &self.l3())
+			.field("l4", &self.l4())
+			.field("tunnel", &self.is_tunnel())
+			.finish()
+	}
+}
+
+impl L2Type {
+	fn bits(self) -> u32 {
+		match self {
+			L2Type::Unknown => 0,
+			L2Type::Ether => dpdk_sys::RTE_PTYPE_L2_ETHER,
+			L2Type::EtherTimesync => dpdk_sys::RTE_PTYPE_L2_ETHER_TIMESYNC,
+			L2Type::EtherArp => dpdk_sys::RTE_PTYPE_L2_ETHER_ARP,
+			L2Type::EtherLldp => dpdk_sys::RTE_PTYPE_L2_ETHER_LLDP,
+			L2Type::EtherNsh => dpdk_sys::RTE_PTYPE_L2_ETHER_NSH,
+			L2Type::EtherVlan => dpdk_sys::RTE_PTYPE_L2_ETHER_VLAN,
+			L2Type::EtherQinq => dpdk_sys::RTE_PTYPE_L2_ETHER_QINQ,
+			L2Type::EtherPppoe => dpdk_sys::RTE_PTYPE_L2_ETHER_PPPOE,
+			L2Type::EtherFcoe => dpdk_sys::RTE_PTYPE_L2_ETHER_FCOE,
+			L2Type::EtherMpls => dpdk_sys::RTE_PTYPE_L2_ETHER_MPLS,
+			L2Type::Other(bits) => bits & dpdk_sys::RTE_PTYPE_L2_MASK,
+		}
+	}
+}
+
+impl L3Type {
+	fn bits(self) -> u32 {
+		match self {
+			L3Type::Unknown => 0,
+			L3Type::Ipv4 => dpdk_sys::RTE_PTYPE_L3_IPV4,
+			L3Type::Ipv4Ext => dpdk_sys::RTE_PTYPE_L3_IPV4_EXT,
+			L3Type::Ipv4ExtUnknown => dpdk_sys::RTE_PTYPE_L3_IPV4_EXT_UNKNOWN,
+			L3Type::Ipv6 => dpdk_sys::RTE_PTYPE_L3_IPV6,
+			L3Type::Ipv6Ext => dpdk_sys::RTE_PTYPE_L3_IPV6_EXT,
+			L3Type::Ipv6ExtUnknown => dpdk_sys::RTE_PTYPE_L3_IPV6_EXT_UNKNOWN,
+			L3Type::Other(bits) => bits & dpdk_sys::RTE_PTYPE_L3_MASK,
+		}
+	}
+}
+
+impl L4Type {
+	fn bits(self) -> u32 {
+		match self {
+			L4Type::Unknown => 0,
+			L4Type::Tcp => dpdk_sys::RTE_PTYPE_L4_TCP,
+			L4Type::Udp => dpdk_sys::RTE_PTYPE_L4_UDP,
+			L4Type::Frag => dpdk_sys::RTE_PTYPE_L4_FRAG,
+			L4Type::Sctp => dpdk_sys::RTE_PTYPE_L4_SCTP,
+			L4Type::Icmp => dpdk_sys::RTE_PTYPE_L4_ICMP,
+			L4Type::NonFrag => dpdk_sys::RTE_PTYPE_L4_NONFRAG,
+			L4Type::Igmp => dpdk_sys::RTE_PTYPE_L4_IGMP,
+			L4Type::Other(bits) => bits & dpdk_sys::RTE_PTYPE_L4_MASK,
+		}
+	}
+}
+
+impl Mbuf {
+	/// Returns the offload flags
+	#[inline]
+	pub fn ol_flags(&self) -> OffloadFlags {
+		OffloadFlags::from_bits(self.raw().ol_flags)
+	}
+
+	/// Replaces the offload flags
+	#[inline]
+	pub fn set_ol_flags(&mut self, flags: OffloadFlags) {
+		self.raw_mut().ol_flags = flags.bits();
+	}
+
+	/// Returns what the NIC found the headers to be
+	#[inline]
+	pub fn packet_type(&self) -> PacketType {
+		PacketType::from_bits(unsafe { self.raw().__bindgen_anon_3.packet_type })
+	}
+
+	#[inline]
+	pub fn set_packet_type(&mut self, ptype: PacketType) {
+		self.raw_mut().__bindgen_anon_3.packet_type = ptype.bits();
+	}
+
+	/// Returns the RSS hash the NIC computed, if it did
+	#[inline]
+	pub fn rss_hash(&self) -> Option<u32> {
+		if !self.ol_flags().contains(OffloadFlags::RX_RSS_HASH) {
+			return None;
+		}
+		Some(unsafe { self.raw().__bindgen_anon_4.hash.rss })
+	}
+
+	/// Sets the RSS hash, e.g. one computed in software
+	#[inline]
+	pub fn set_rss_hash(&mut self, hash: u32) {
+		let raw = self.raw_mut();
+		raw.__bindgen_anon_4.hash.rss = hash;
+		raw.ol_flags |= dpdk_sys::PKT_RX_RSS_HASH as u64;
+	}
+
+	/// Returns the VLAN tag control information (TCI) the NIC stripped, or is to insert
+	#[inline]
+	pub fn vlan(&self) -> Option<u16> {
+		if self.ol_flags().intersects(OffloadFlags::RX_VLAN | OffloadFlags::TX_VLAN) {
+			Some(self.raw().vlan_tci)
+		} else {
+			None
+		}
+	}
+
+	/// Returns the outer VLAN TCI of a QinQ packet
+	#[inline]
+	pub fn vlan_outer(&self) -> Option<u16> {
+		if self.ol_flags().intersects(OffloadFlags::RX_QINQ | OffloadFlags::TX_QINQ) {
+			Some(self.raw().vlan_tci_outer)
+		} else {
+			None
+		}
+	}
+
+	/// Asks the NIC to insert a VLAN tag with `tci` on transmit, or not to with `None`
+	///
+	/// The port needs the `vlan_insert` tx offload.
+	#[inline]
+	pub fn set_vlan(&mut self, tci: Option<u16>) {
+		let raw = self.raw_mut();
+		match tci {
+			Some(tci) => {
+				raw.vlan_tci = tci;
+				raw.ol_flags |= dpdk_sys::PKT_TX_VLAN;
+			}
+			None => {
+				raw.vlan_tci = 0;
+				raw.ol_flags &= !(dpdk_sys::PKT_TX_VLAN | dpdk_sys::PKT_RX_VLAN as u64);
+			}
+		}
+	}
+
+	/// Returns the rx timestamp, in the unit of the NIC, if the port stamps packets
+	#[inline]
+	pub fn timestamp(&self) -> Option<u64> {
+		if !self.ol_flags().contains(OffloadFlags::RX_TIMESTAMP) {
+			return None;
+		}
+		Some(self.raw().timestamp)
+	}
+
+	#[inline]
+	pub fn set_timestamp(&mut self, timestamp: u64) {
+		let raw = self.raw_mut();
+		raw.timestamp = timestamp;
+		raw.ol_flags |= dpdk_sys::PKT_RX_TIMESTAMP as u64;
+	}
+}
@@ -4,17 +4,41 @@
 //! 
 //! DPDK EAL startup and cleanup ops
 
+mod counters;
+mod eal;
+mod flags;
+mod flow;
+mod lcore;
 mod mbuf;
 mod mempool;
 mod memring;
 mod memzone;
+mod metadata;
+mod offload;
+mod packetmeta;
 mod port;
+mod portset;
+mod rss;
+mod shared;
+mod stats;
 
+pub use counters::*;
+pub use eal::*;
+pub use flags::*;
+pub use flow::*;
+pub use lcore::*;
 pub use mbuf::*;
 pub use mempool::*;
 pub use memring::*;
 pub use memzone::*;
+pub use metadata::*;
+pub use offload::*;
+pub use packetmeta::*;
 pub use port::*;
+pub use portset::*;
+pub use rss::*;
+pub use shared::*;
+pub use stats::*;
 
 use dpdk_sys;
 use libc::{
@@ -25,6 +49,7 @@ use log;
 use std::{
 	ffi::{CString, NulError},
 	os::raw,
+	time::Duration,
 };
 use thiserror::Error;
 
@@ -46,6 +71,19 @@ impl WrappedCString for String {
 	}
 }
 
+/// Whether a handle to a shared memory object may free it
+///
+/// The process that creates a mempool, ring or memzone owns it and frees it on drop.
+/// Handles got by looking the object up, typically in a secondary process, are attached:
+/// dropping them leaves the object to its owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ownership {
+	/// Created by this handle; freed when it is dropped
+	Owned,
+	/// Looked up; never freed through this handle
+	Attached,
+}
+
 // NOTE: An absolutely horrible thing to to do
 // converting a string conversion error to a memory error
 impl From<NulError> for MemoryError {
@@ -72,6 +110,8 @@ pub enum MemoryError {
 	NoBuf,
 	#[error("not enough entries to dequeue")]
 	NoEntries,
+	#[error("shared memory layout mismatch: {}", _0)]
+	Layout(String),
 	#[error("bad val")]
 	BadVal, // should never hit this
 }
@@ -120,6 +160,30 @@ pub enum BufError {
 	/// The struct exceeds the remaining buffer length
 	#[error("Struct size {} exceeds the remaining buffer length {}", _0, _1)]
 	OutOfBuffer(usize, usize),
+
+	/// The data does not fit before the first segment's data
+	#[error("{} bytes do not fit in the {} bytes of headroom left", _0, _1)]
+	NoHeadroom(usize, usize),
+
+	/// The data does not fit after the segment's data
+	#[error("{} bytes do not fit in the {} bytes of tailroom left", _0, _1)]
+	NoTailroom(usize, usize),
+
+	/// A packet cannot be chained from more segments
+	#[error("a packet cannot have more than {} segments", dpdk_sys::RTE_MBUF_MAX_NB_SEGS)]
+	TooManySegments,
+
+	/// The packet would be longer than `pkt_len` can hold
+	#[error("packet would be longer than {} bytes", u32::MAX)]
+	TooLong,
+
+	/// No mbuf could be allocated for a new segment
+	#[error("no mbuf left for a new segment")]
+	NoMbuf,
+
+	/// The packet data is shared with a clone and must not be written
+	#[error("packet data is shared with another mbuf")]
+	Shared,
 }
 
 #[derive(Error, Debug)]
@@ -132,18 +196,33 @@ pub enum PortError {
 	NoSupport,
 	#[error("not enough memory")]
 	NoMem,
+	#[error("invalid queue mapping: {}", _0)]
+	BadQueueMap(String),
+	#[error("lcore {} has no tx queue", _0)]
+	NoTxQueue(u32),
+	#[error("rss key of {} bytes is shorter than the {} bytes needed", _0, _1)]
+	RssKeyLen(usize, usize),
+	#[error("link did not come up within {:?}", _0)]
+	LinkTimeout(Duration),
+	#[error("device is already attached")]
+	Exists,
 	#[error("bad val")]
 	BadVal,
 }
 
 impl PortError {
 	pub fn new() -> Self {
-		let errno = unsafe { dpdk_sys::_rte_errno() };
+		Self::from_errno(unsafe { dpdk_sys::_rte_errno() })
+	}
+
+	/// Error for an errno returned directly, as negative values, by ethdev and hotplug calls
+	pub fn from_errno(errno: i32) -> Self {
 		match errno {
 			ENODEV => PortError::NoDevice,
 			EINVAL => PortError::Invalid,
 			ENOTSUP => PortError::NoSupport,
 			ENOMEM => PortError::NoMem,
+			EEXIST => PortError::Exists,
 			_ => PortError::BadVal,
 		}
 	}
@@ -157,6 +236,10 @@ pub enum EALErrors {
 	DuplicateCall,
 	#[error("invalid parameters were passed as argv/argc")]
 	InvalidArgs,
+	#[error("argument {} ({:?}) contains an interior nul byte", _0, _1)]
+	BadArg(usize, String),
+	#[error("argument {} ({:?}) was rejected: {}", _0, _1, _2)]
+	RejectedArg(usize, String, &'static str),
 	#[error("failure likely caused by an out-of-memory condition")]
 	NoMem,
 	#[error("memory setup issues")]
@@ -191,64 +274,63 @@ impl EALErrors {
 	}
 }
 
+#[derive(Error, Debug)]
+pub enum LcoreError {
+	#[error("lcore {} is not an enabled worker lcore", _0)]
+	NotAvailable(u32),
+	#[error("lcore {} is already running a function", _0)]
+	Busy(u32),
+	#[error("function launched on lcore {} panicked", _0)]
+	Panicked(u32),
+}
+
 /// Initializes the Environment Abstraction Layer (EAL)
-pub fn eal_init(args: Vec<String>) -> Result<(), EALErrors> {
+///
+/// `args` is the full argv, program name included; it is usually built with `EalArgs`.
+/// An argument that cannot be passed to C is reported by position, and so is one the EAL
+/// rejects when `EalArgs::find_rejected` can tell which; otherwise the full argv is logged.
+pub fn eal_init<A: Into<Vec<String>>>(args: A) -> Result<(), EALErrors> {
+	let args = args.into();
 	log::info!("Args: {:?}", &args);
 	let len = args.len() as raw::c_int;
-	// the panic is fine here since it's due to wrong arguments
-	// though this error can be handled better
-	let args = args
-		.into_iter()
-		.map(|s| CString::new(s).unwrap())
-		.collect::<Vec<_>>();
-	let mut ptrs = args
+	let cargs = args
+		.iter()
+		.enumerate()
+		.map(|(i, s)| CString::new(s.as_str()).map_err(|_| EALErrors::BadArg(i, s.clone())))
+		.collect::<Result<Vec<_>, _>>()?;
+	let mut ptrs = cargs
 		.iter()
 		.map(|s| s.as_ptr() as *mut raw::c_char)
 		.collect::<Vec<_>>();
 	match unsafe { dpdk_sys::rte_eal_init(len, ptrs.as_mut_ptr()) } {
 		-1 => {
-			log::error!("failed to initialize eal");
-			Err(EALErrors::new())
+			let e = match EALErrors::new() {
+				EALErrors::InvalidArgs => match EalArgs::find_rejected(&args) {
+					Some((i, reason)) => EALErrors::RejectedArg(i, args[i].clone(), reason),
+					None => EALErrors::InvalidArgs,
+				},
+				e => e,
+			};
+			log::error!("failed to initialize eal with {:?}: {}", &args, e);
+			Err(e)
 		}
 		_ => Ok(()),
 	}
 }
 
 /// Cleans up the Environment Abstraction Layer (EAL).
-pub fn eal_cleanup(mempool: &Mempool) -> Result<(), EALErrors> {
-	unsafe {
-		dpdk_sys::rte_mempool_free(mempool.get_ptr());
-		match dpdk_sys::rte_eal_cleanup() {
-			0 => Ok(()),
-			_ => Err(EALErrors::Fault),
-		}
+///
+/// Drops `mempools` first, which frees the pools this process owns; attached pools are
+/// left to their owner. No handle to them may be used afterwards.
+pub fn eal_cleanup(mempools: MempoolSet) -> Result<(), EALErrors> {
+	drop(mempools);
+	match unsafe { dpdk_sys::rte_eal_cleanup() } {
+		0 => Ok(()),
+		_ => Err(EALErrors::Fault),
 	}
 }
 
 /// Frees the `rte_mbuf` in bulk.
 pub(crate) fn mbuf_free_bulk(mbufs: Vec<*mut dpdk_sys::rte_mbuf>) {
-	assert!(!mbufs.is_empty());
-
-	let mut to_free = Vec::with_capacity(mbufs.len());
-	let pool = unsafe { (*mbufs[0]).pool };
-
-	for mbuf in mbufs.into_iter() {
-		if pool == unsafe { (*mbuf).pool } {
-			to_free.push(mbuf as *mut raw::c_void);
-		} else {
-			unsafe {
-				let len = to_free.len();
-				dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
-				to_free.set_len(0);
-			}
-
-			to_free.push(mbuf as *mut raw::c_void);
-		}
-	}
-
-	unsafe {
-		let len = to_free.len();
-		dpdk_sys::_rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
-		to_free.set_len(0);
-	}
+	Mbuf::mbuf_free_bulk(mbufs);
 }
@@ -0,0 +1,383 @@
+//! Rx and tx offload negotiation
+//!
+//! Ports request offloads by name from the configuration; `Port::configure`
+//! only enables those the PMD reports in `rx_offload_capa` and `tx_offload_capa`,
+//! and records the outcome in an `OffloadReport`.
+//!
+//! Checksum offloads the PMD lacks are done in software by the port:
+//! received packets get the `PKT_RX_*_CKSUM_*` flags the NIC would have set, and
+//! packets sent with `PKT_TX_*_CKSUM` flags get their checksums filled in before
+//! they reach the PMD. Other missing offloads are only reported.
+
+use serde::{de, Deserialize, Deserializer};
+use std::{fmt, slice, str::FromStr};
+
+use super::{FlagKind, Flags, Mbuf};
+use crate::net::{checksum, Checksum};
+
+/// Marker for the direction of a set of offloads;
+/// its `NAMES` are the `DEV_*_OFFLOAD_*` flags known by name
+pub trait OffloadDirection: FlagKind {
+	/// `rx` or `tx`, for messages
+	const PREFIX: &'static str;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rx {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tx {}
+
+impl FlagKind for Rx {
+	const NAMES: &'static [(&'static str, u64)] = &[
+		("vlan_strip", dpdk_sys::DEV_RX_OFFLOAD_VLAN_STRIP as u64),
+		("ipv4_cksum", dpdk_sys::DEV_RX_OFFLOAD_IPV4_CKSUM as u64),
+		("udp_cksum", dpdk_sys::DEV_RX_OFFLOAD_UDP_CKSUM as u64),
+		("tcp_cksum", dpdk_sys::DEV_RX_OFFLOAD_TCP_CKSUM as u64),
+		("tcp_lro", dpdk_sys::DEV_RX_OFFLOAD_TCP_LRO as u64),
+		("qinq_strip", dpdk_sys::DEV_RX_OFFLOAD_QINQ_STRIP as u64),
+		("outer_ipv4_cksum", dpdk_sys::DEV_RX_OFFLOAD_OUTER_IPV4_CKSUM as u64),
+		("vlan_filter", dpdk_sys::DEV_RX_OFFLOAD_VLAN_FILTER as u64),
+		("vlan_extend", dpdk_sys::DEV_RX_OFFLOAD_VLAN_EXTEND as u64),
+		("jumbo_frame", dpdk_sys::DEV_RX_OFFLOAD_JUMBO_FRAME as u64),
+		("scatter", dpdk_sys::DEV_RX_OFFLOAD_SCATTER as u64),
+		("timestamp", dpdk_sys::DEV_RX_OFFLOAD_TIMESTAMP as u64),
+		("keep_crc", dpdk_sys::DEV_RX_OFFLOAD_KEEP_CRC as u64),
+		("sctp_cksum", dpdk_sys::DEV_RX_OFFLOAD_SCTP_CKSUM as u64),
+		("outer_udp_cksum", dpdk_sys::DEV_RX_OFFLOAD_OUTER_UDP_CKSUM as u64),
+		("rss_hash", dpdk_sys::DEV_RX_OFFLOAD_RSS_HASH as u64),
+	];
+	const KIND: &'static str = "rx offloads";
+}
+
+impl OffloadDirection for Rx {
+	const PREFIX: &'static str = "rx";
+}
+
+impl FlagKind for Tx {
+	const NAMES: &'static [(&'static str, u64)] = &[
+		("vlan_insert", dpdk_sys::DEV_TX_OFFLOAD_VLAN_INSERT as u64),
+		("ipv4_cksum", dpdk_sys::DEV_TX_OFFLOAD_IPV4_CKSUM as u64),
+		("udp_cksum", dpdk_sys::DEV_TX_OFFLOAD_UDP_CKSUM as u64),
+		("tcp_cksum", dpdk_sys::DEV_TX_OFFLOAD_TCP_CKSUM as u64),
+		("sctp_cksum", dpdk_sys::DEV_TX_OFFLOAD_SCTP_CKSUM as u64),
+		("tcp_tso", dpdk_sys::DEV_TX_OFFLOAD_TCP_TSO as u64),
+		("udp_tso", dpdk_sys::DEV_TX_OFFLOAD_UDP_TSO as u64),
+		("outer_ipv4_cksum", dpdk_sys::DEV_TX_OFFLOAD_OUTER_IPV4_CKSUM as u64),
+		("qinq_insert", dpdk_sys::DEV_TX_OFFLOAD_QINQ_INSERT as u64),
+		("mt_lockfree", dpdk_sys::DEV_TX_OFFLOAD_MT_LOCKFREE as u64),
+		("multi_segs", dpdk_sys::DEV_TX_OFFLOAD_MULTI_SEGS as u64),
+		("mbuf_fast_free", dpdk_sys::DEV_TX_OFFLOAD_MBUF_FAST_FREE as u64),
+		("outer_udp_cksum", dpdk_sys::DEV_TX_OFFLOAD_OUTER_UDP_CKSUM as u64),
+	];
+	const KIND: &'static str = "tx offloads";
+}
+
+impl OffloadDirection for Tx {
+	const PREFIX: &'static str = "tx";
+}
+
+/// A set of `DEV_RX_OFFLOAD_*` or `DEV_TX_OFFLOAD_*` flags
+///
+/// In the configuration a set is a list of names, e.g. `["ipv4_cksum", "udp_cksum"]`.
+pub type Offloads<D> = Flags<D>;
+
+pub type RxOffloads = Offloads<Rx>;
+pub type TxOffloads = Offloads<Tx>;
+
+impl<D: OffloadDirection> Offloads<D> {
+	/// IPv4, UDP and TCP checksums
+	pub const fn checksum() -> Self {
+		// the rx and tx checksum flags share their values
+		Self::from_bits(
+			(dpdk_sys::DEV_RX_OFFLOAD_IPV4_CKSUM
+				| dpdk_sys::DEV_RX_OFFLOAD_UDP_CKSUM
+				| dpdk_sys::DEV_RX_OFFLOAD_TCP_CKSUM) as u64,
+		)
+	}
+}
+
+impl<D: OffloadDirection> FromStr for Offloads<D> {
+	type Err = String;
+
+	/// Parse a single offload name
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		D::NAMES
+			.iter()
+			.find(|(name, _)| *name == s)
+			.map(|&(_, flag)| Self::from_bits(flag))
+			.ok_or_else(|| format!("unknown {} offload {:?}", D::PREFIX, s))
+	}
+}
+
+impl<'de, D: OffloadDirection> Deserialize<'de> for Offloads<D> {
+	fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+		let names = Vec::<String>::deserialize(deserializer)?;
+		names.iter().try_fold(Self::empty(), |set, name| {
+			let offload = name.parse::<Self>().map_err(de::Error::custom)?;
+			Ok(set.union(offload))
+		})
+	}
+}
+
+/// Offloads a port asked for and offloads its PMD enabled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OffloadReport {
+	pub rx_requested: RxOffloads,
+	pub rx_enabled: RxOffloads,
+	pub tx_requested: TxOffloads,
+	pub tx_enabled: TxOffloads,
+}
+
+impl OffloadReport {
+	/// Requested rx offloads the PMD does not support
+	pub fn rx_missing(&self) -> RxOffloads {
+		self.rx_requested.difference(self.rx_enabled)
+	}
+
+	/// Requested tx offloads the PMD does not support
+	pub fn tx_missing(&self) -> TxOffloads {
+		self.tx_requested.difference(self.tx_enabled)
+	}
+}
+
+impl fmt::Display for OffloadReport {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "rx {} tx {}", self.rx_enabled, self.tx_enabled)?;
+		if !self.rx_missing().is_empty() || !self.tx_missing().is_empty() {
+			write!(
+				f,
+				", unsupported: rx {} tx {}",
+				self.rx_missing(),
+				self.tx_missing()
+			)?;
+		}
+		Ok(())
+	}
+}
+
+const ETHER_HDR_LEN: usize = 14;
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const ETHER_TYPE_IPV6: u16 = 0x86dd;
+const ETHER_TYPE_VLAN: u16 = 0x8100;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+/// Where the headers of a frame start
+struct Headers {
+	l3: usize,
+	/// L3 header length
+	l3_len: usize,
+	/// End of the IP payload
+	end: usize,
+	ipv4: bool,
+	proto: u8,
+	/// False for IPv4 fragments other than the first one
+	has_l4: bool,
+}
+
+impl Headers {
+	/// Parse the headers in `frame`, the start of a `pkt_len` byte packet
+	///
+	/// The IP header must be within `frame`; the rest of the packet may not be.
+	fn parse(frame: &[u8], pkt_len: usize) -> Option<Self> {
+		let mut l3 = ETHER_HDR_LEN;
+		let mut ether_type = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+		if ether_type == ETHER_TYPE_VLAN {
+			ether_type = u16::from_be_bytes([*frame.get(16)?, *frame.get(17)?]);
+			l3 += 4;
+		}
+		let ip = frame.get(l3..)?;
+		let ip_len = pkt_len.checked_sub(l3)?;
+		match ether_type {
+			ETHER_TYPE_IPV4 if ip.len() >= 20 => {
+				let l3_len = (ip[0] & 0x0f) as usize * 4;
+				let total = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+				if l3_len < 20 || l3_len > ip.len() || total < l3_len || total > ip_len {
+					return None;
+				}
+				Some(Self {
+					l3,
+					l3_len,
+					end: l3 + total,
+					ipv4: true,
+					proto: ip[9],
+					has_l4: u16::from_be_bytes([ip[6], ip[7]]) & 0x1fff == 0,
+				})
+			}
+			ETHER_TYPE_IPV6 if ip.len() >= 40 => {
+				let payload = u16::from_be_bytes([ip[4], ip[5]]) as usize;
+				if 40 + payload > ip_len {
+					return None;
+				}
+				Some(Self {
+					l3,
+					l3_len: 40,
+					end: l3 + 40 + payload,
+					ipv4: false,
+					proto: ip[6],
+					has_l4: true,
+				})
+			}
+			_ => None,
+		}
+	}
+
+	fn l4(&self) -> usize {
+		self.l3 + self.l3_len
+	}
+
+	/// Offset of the checksum field in the L4 header
+	fn l4_cksum_offset(&self) -> Option<usize> {
+		match self.proto {
+			IPPROTO_TCP if self.end - self.l4() >= 20 => Some(16),
+			IPPROTO_UDP if self.end - self.l4() >= 8 => Some(6),
+			_ => None,
+		}
+	}
+}
+
+/// One's complement sum of the pseudo header of the L4 segment
+fn pseudo_header_sum(frame: &[u8], hdr: &Headers) -> Checksum {
+	let ip = &frame[hdr.l3..];
+	let addrs = if hdr.ipv4 { &ip[12..20] } else { &ip[8..40] };
+	let mut sum = Checksum::new();
+	sum.add_bytes(addrs);
+	sum.add_u16(hdr.proto as u16);
+	sum.add_u32((hdr.end - hdr.l4()) as u32);
+	sum
+}
+
+/// One's complement sum of the L4 segment, all in `frame`, and its pseudo header
+fn l4_sum(frame: &[u8], hdr: &Headers) -> u16 {
+	let mut sum = pseudo_header_sum(frame, hdr);
+	sum.add_bytes(&frame[hdr.l4()..hdr.end]);
+	sum.sum()
+}
+
+/// The frame of a single segment packet as a mutable slice
+///
+/// # Safety
+///
+/// The buffer must not be referenced elsewhere for the lifetime of the slice
+unsafe fn frame_mut(pkt: &mut Mbuf) -> &mut [u8] {
+	slice::from_raw_parts_mut(pkt.data_address(0), pkt.data_len())
+}
+
+/// Verify the checksums `missing` covers and set the rx flags the NIC would have set
+pub(super) fn sw_rx_cksum(pkts: &mut [Mbuf], missing: RxOffloads) {
+	let ip = missing.contains(RxOffloads::from_bits(dpdk_sys::DEV_RX_OFFLOAD_IPV4_CKSUM as u64));
+	let tcp = missing.contains(RxOffloads::from_bits(dpdk_sys::DEV_RX_OFFLOAD_TCP_CKSUM as u64));
+	let udp = missing.contains(RxOffloads::from_bits(dpdk_sys::DEV_RX_OFFLOAD_UDP_CKSUM as u64));
+
+	for pkt in pkts.iter_mut() {
+		let (flags, check_l4) = match rx_cksum_flags(pkt, ip, tcp, udp) {
+			Some(found) => found,
+			None => continue,
+		};
+		let raw = pkt.raw_mut();
+		if ip {
+			raw.ol_flags &= !(dpdk_sys::PKT_RX_IP_CKSUM_MASK as u64);
+		}
+		if check_l4 {
+			raw.ol_flags &= !(dpdk_sys::PKT_RX_L4_CKSUM_MASK as u64);
+		}
+		raw.ol_flags |= flags;
+	}
+}
+
+/// The rx checksum flags of `pkt` for the checksums asked for, and whether it has a checked L4 protocol
+///
+/// The headers up to IP are read from the first segment; the L4 checksum is summed over
+/// every segment, so scattered packets are checked without being copied.
+fn rx_cksum_flags(pkt: &Mbuf, ip: bool, tcp: bool, udp: bool) -> Option<(u64, bool)> {
+	let frame = unsafe { pkt.read_data_slice::<u8>(0, pkt.data_len()).ok()?.as_ref() };
+	let hdr = Headers::parse(frame, pkt.pkt_len())?;
+	let mut flags = 0u64;
+	if ip && hdr.ipv4 {
+		flags |= if checksum(&frame[hdr.l3..hdr.l4()]) == 0 {
+			dpdk_sys::PKT_RX_IP_CKSUM_GOOD
+		} else {
+			dpdk_sys::PKT_RX_IP_CKSUM_BAD
+		} as u64;
+	}
+	let check_l4 = match hdr.proto {
+		IPPROTO_TCP => tcp,
+		IPPROTO_UDP => udp,
+		_ => false,
+	};
+	if check_l4 && hdr.has_l4 {
+		if let Some(offset) = hdr.l4_cksum_offset() {
+			let mut stored = [0u8; 2];
+			pkt.read_bytes(hdr.l4() + offset, &mut stored).ok()?;
+			let mut sum = pseudo_header_sum(frame, &hdr);
+			sum.add_mbuf(pkt, hdr.l4(), hdr.end - hdr.l4()).ok()?;
+			// a zero UDP checksum over IPv4 means the sender did not compute one
+			let good = (hdr.proto == IPPROTO_UDP && hdr.ipv4 && stored == [0, 0]) || sum.sum() == 0xffff;
+			flags |= if good {
+				dpdk_sys::PKT_RX_L4_CKSUM_GOOD
+			} else {
+				dpdk_sys::PKT_RX_L4_CKSUM_BAD
+			} as u64;
+		}
+	}
+	Some((flags, check_l4))
+}
+
+/// Fill in the checksums packets ask the NIC for when `missing` says it cannot
+///
+/// Packets whose data is shared with a clone are sent as they are: writing the
+/// checksums in place would change every clone too.
+pub(super) fn sw_tx_cksum(pkts: &mut [Mbuf], missing: TxOffloads) {
+	let ip = missing.contains(TxOffloads::from_bits(dpdk_sys::DEV_TX_OFFLOAD_IPV4_CKSUM as u64));
+	let tcp = missing.contains(TxOffloads::from_bits(dpdk_sys::DEV_TX_OFFLOAD_TCP_CKSUM as u64));
+	let udp = missing.contains(TxOffloads::from_bits(dpdk_sys::DEV_TX_OFFLOAD_UDP_CKSUM as u64));
+
+	for pkt in pkts.iter_mut() {
+		let ol_flags = pkt.raw().ol_flags;
+		let want_ip = ip && ol_flags & dpdk_sys::PKT_TX_IP_CKSUM != 0;
+		let l4_flag = ol_flags & dpdk_sys::PKT_TX_L4_MASK;
+		let want_l4 = (tcp && l4_flag == dpdk_sys::PKT_TX_TCP_CKSUM)
+			|| (udp && l4_flag == dpdk_sys::PKT_TX_UDP_CKSUM);
+		if !want_ip && !want_l4 {
+			continue;
+		}
+
+		if pkt.is_shared() {
+			log::debug!("sw_tx_cksum: {} byte shared packet left without checksums", pkt.pkt_len());
+			continue;
+		}
+		if pkt.linearize().is_err() {
+			log::debug!("sw_tx_cksum: {} byte chained packet left without checksums", pkt.pkt_len());
+			continue;
+		}
+		let frame = unsafe { frame_mut(pkt) };
+		if let Some(hdr) = Headers::parse(frame, frame.len()) {
+			if want_ip && hdr.ipv4 {
+				let field = hdr.l3 + 10;
+				frame[field..field + 2].copy_from_slice(&[0, 0]);
+				let sum = checksum(&frame[hdr.l3..hdr.l4()]);
+				frame[field..field + 2].copy_from_slice(&sum.to_be_bytes());
+			}
+			if want_l4 && hdr.has_l4 {
+				if let Some(offset) = hdr.l4_cksum_offset() {
+					let field = hdr.l4() + offset;
+					frame[field..field + 2].copy_from_slice(&[0, 0]);
+					let mut sum = !l4_sum(frame, &hdr);
+					if sum == 0 && hdr.proto == IPPROTO_UDP {
+						sum = 0xffff;
+					}
+					frame[field..field + 2].copy_from_slice(&sum.to_be_bytes());
+				}
+			}
+		}
+
+		let raw = pkt.raw_mut();
+		if want_ip {
+			raw.ol_flags &= !dpdk_sys::PKT_TX_IP_CKSUM;
+		}
+		if want_l4 {
+			raw.ol_flags &= !dpdk_sys::PKT_TX_L4_MASK;
+		}
+	}
+}
@@ -0,0 +1,161 @@
+//! Per-packet metadata kept in the private area of every mbuf
+//!
+//! A mempool created with `priv_size` of at least `PacketMeta::PRIV_SIZE` leaves room
+//! for a `PacketMeta` right after each `rte_mbuf`. The area travels with the mbuf
+//! through rings, so what one stage learns about a packet (where it came from, which
+//! client it went to, where it goes next) reaches the later stages and the other
+//! processes without parsing or looking it up again.
+//!
+//! DPDK does not clear the private area when an mbuf is allocated: the engine resets
+//! the metadata of every packet it receives, and mbufs allocated elsewhere should
+//! `reset` theirs before use.
+
+use std::{fmt, mem};
+
+use super::{DropReason, Mbuf};
+
+/// What the engine knows about a packet besides its data
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PacketMeta {
+	rx_tsc: u64,
+	next_hop: u32,
+	ingress_port: u16,
+	client_id: u16,
+	// 1 + the index of the reason in `DropReason::ALL`, 0 if not dropped
+	drop_reason: u8,
+	_reserved: [u8; 7],
+}
+
+impl PacketMeta {
+	/// Bytes of mbuf private area a `PacketMeta` needs
+	pub const PRIV_SIZE: u16 = mem::size_of::<PacketMeta>() as u16;
+
+	const NO_NEXT_HOP: u32 = u32::MAX;
+	const NO_CLIENT: u16 = u16::MAX;
+
+	/// Metadata of a packet received on `port` at TSC cycle `rx_tsc`
+	pub fn new(ingress_port: u16, rx_tsc: u64) -> Self {
+		Self {
+			rx_tsc,
+			next_hop: Self::NO_NEXT_HOP,
+			ingress_port,
+			client_id: Self::NO_CLIENT,
+			drop_reason: 0,
+			_reserved: [0; 7],
+		}
+	}
+
+	/// Forget everything about the previous packet and start over for one received on `port` now
+	#[inline]
+	pub fn reset(&mut self, ingress_port: u16) {
+		*self = Self::new(ingress_port, unsafe { dpdk_sys::_rte_get_tsc_cycles() });
+	}
+
+	/// Port the packet arrived on
+	#[inline]
+	pub fn ingress_port(&self) -> u16 {
+		self.ingress_port
+	}
+
+	#[inline]
+	pub fn set_ingress_port(&mut self, port: u16) {
+		self.ingress_port = port;
+	}
+
+	/// TSC cycle the packet was received at; see `rte_get_tsc_hz`
+	#[inline]
+	pub fn rx_tsc(&self) -> u64 {
+		self.rx_tsc
+	}
+
+	#[inline]
+	pub fn set_rx_tsc(&mut self, tsc: u64) {
+		self.rx_tsc = tsc;
+	}
+
+	/// Client the packet was handed to, if any
+	#[inline]
+	pub fn client_id(&self) -> Option<u16> {
+		if self.client_id == Self::NO_CLIENT {
+			None
+		} else {
+			Some(self.client_id)
+		}
+	}
+
+	#[inline]
+	pub fn set_client_id(&mut self, client_id: u16) {
+		self.client_id = client_id;
+	}
+
+	/// Why the packet is to be dropped, if it is
+	#[inline]
+	pub fn drop_reason(&self) -> Option<DropReason> {
+		match self.drop_reason {
+			0 => None,
+			n => DropReason::ALL.get(n as usize - 1).copied(),
+		}
+	}
+
+	/// Marks the packet to be dropped, or clears the mark with `None`
+	#[inline]
+	pub fn set_drop_reason(&mut self, reason: Option<DropReason>) {
+		self.drop_reason = match reason {
+			Some(reason) => reason as u8 + 1,
+			None => 0,
+		};
+	}
+
+	/// Index of the next hop the routing stage picked, if it did
+	#[inline]
+	pub fn next_hop(&self) -> Option<u32> {
+		if self.next_hop == Self::NO_NEXT_HOP {
+			None
+		} else {
+			Some(self.next_hop)
+		}
+	}
+
+	#[inline]
+	pub fn set_next_hop(&mut self, next_hop: Option<u32>) {
+		self.next_hop = next_hop.unwrap_or(Self::NO_NEXT_HOP);
+	}
+}
+
+impl fmt::Debug for PacketMeta {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("PacketMeta")
+			.field("ingress_port", &self.ingress_port())
+			.field("rx_tsc", &self.rx_tsc())
+			.field("client_id", &self.client_id())
+			.field("drop_reason", &self.drop_reason())
+			.field("next_hop", &self.next_hop())
+			.finish()
+	}
+}
+
+impl Mbuf {
+	/// Start of the private area of the mbuf, `rte_mbuf_to_priv` in Rust
+	#[inline]
+	fn meta_ptr(&self) -> Option<*mut PacketMeta> {
+		if self.raw().priv_size < PacketMeta::PRIV_SIZE {
+			return None;
+		}
+		// the area is `RTE_MBUF_PRIV_ALIGN` aligned, as much as a `PacketMeta` needs
+		let area = unsafe { (self.get_ptr() as *mut u8).add(mem::size_of::<dpdk_sys::rte_mbuf>()) };
+		Some(area as *mut PacketMeta)
+	}
+
+	/// Returns the metadata of the packet, if its mempool left room for it
+	#[inline]
+	pub fn meta(&self) -> Option<&PacketMeta> {
+		self.meta_ptr().map(|meta| unsafe { &*meta })
+	}
+
+	/// Returns the metadata of the packet for updating, if its mempool left room for it
+	#[inline]
+	pub fn meta_mut(&mut self) -> Option<&mut PacketMeta> {
+		self.meta_ptr().map(|meta| unsafe { &mut *meta })
+	}
+}
@@ -1,21 +1,210 @@
 //! The Port structure is a wrapper around physical NIC ports
-
-// TODO: It might be a good idea for Port to hold a mapping between core id and send and receive queues. This is currently done in `txbin` with `queue_id ^ 1` for the transmit queue and by ensuring that every Port always sets up an even number of queues.
+//!
+//! A Port owns the mapping between lcores and its queues.
+//! `receive` and `send` look the queue up for the calling lcore, so no two lcores ever share a queue.
 
 // DEVFLAGS: development flags - remove in production
 #![allow(dead_code)]
 
-// use crate::net::MacAddr;
-// use pnet::datalink::MacAddr;
-use std::marker::{Send, Sync};
+use crossbeam_queue::SegQueue;
+use libc::ENOTSUP;
+use serde::Deserialize;
+use std::{
+	fmt,
+	marker::{Send, Sync},
+	sync::Arc,
+	thread,
+	time::{Duration, Instant},
+};
+
+use super::{
+	current_lcore, offload, FlowRule, Mbuf, Mempool, MempoolSet, OffloadReport, PortError, Reta,
+	RxOffloads, TxOffloads, WrappedCString,
+};
+use crate::{config::PortConfig, net::MacAddr};
+
+/// Duplex mode of a link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Duplex {
+	Half,
+	Full,
+}
+
+/// State of a port's link as reported by the PMD
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkStatus {
+	pub up: bool,
+	/// Link speed in Mbps; 0 if unknown or down
+	pub speed_mbps: u32,
+	pub duplex: Duplex,
+	pub autoneg: bool,
+}
+
+impl LinkStatus {
+	fn from_raw(link: &dpdk_sys::rte_eth_link) -> Self {
+		Self {
+			up: link.link_status() as u32 != dpdk_sys::ETH_LINK_DOWN,
+			speed_mbps: link.link_speed,
+			duplex: match link.link_duplex() as u32 {
+				dpdk_sys::ETH_LINK_FULL_DUPLEX => Duplex::Full,
+				_ => Duplex::Half,
+			},
+			autoneg: link.link_autoneg() != 0,
+		}
+	}
+}
+
+impl fmt::Display for LinkStatus {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if !self.up {
+			return write!(f, "down");
+		}
+		let duplex = match self.duplex {
+			Duplex::Full => "full",
+			Duplex::Half => "half",
+		};
+		write!(f, "up {} Mbps {}-duplex", self.speed_mbps, duplex)
+	}
+}
+
+/// The queues an lcore polls on a port
+///
+/// An lcore may own an rx queue, a tx queue or both
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LcoreQueues {
+	pub lcore: u32,
+	#[serde(default)]
+	pub rx: Option<u16>,
+	#[serde(default)]
+	pub tx: Option<u16>,
+}
 
-use super::{Mbuf, Mempool, PortError};
+impl LcoreQueues {
+	/// The default mapping: rx queue `i` is polled by `rx_lcores[i]` for the first
+	/// `rx_queues` of them, and tx queue 0 is used by `tx_lcore`
+	pub fn spread(rx_lcores: &[u32], rx_queues: u16, tx_lcore: u32) -> Vec<Self> {
+		let mut map: Vec<Self> = rx_lcores
+			.iter()
+			.zip(0..rx_queues)
+			.map(|(&lcore, q)| Self {
+				lcore,
+				rx: Some(q),
+				tx: None,
+			})
+			.collect();
+		match map.iter_mut().find(|entry| entry.lcore == tx_lcore) {
+			Some(entry) => entry.tx = Some(0),
+			None => map.push(Self {
+				lcore: tx_lcore,
+				rx: None,
+				tx: Some(0),
+			}),
+		}
+		map
+	}
 
-#[derive(Clone, Copy)]
+	/// Check that a mapping is usable with `rx_queues` rx and `tx_queues` tx queues
+	///
+	/// Every queue must exist and be owned by at most one lcore,
+	/// every lcore must be listed once and every rx queue must be polled by someone.
+	pub fn validate(map: &[Self], rx_queues: u16, tx_queues: u16) -> Result<(), String> {
+		let mut lcores = Vec::with_capacity(map.len());
+		let mut rx_owner = vec![None; rx_queues as usize];
+		let mut tx_owner = vec![None; tx_queues as usize];
+
+		for entry in map {
+			if entry.lcore >= dpdk_sys::RTE_MAX_LCORE {
+				return Err(format!("lcore {} exceeds RTE_MAX_LCORE", entry.lcore));
+			}
+			if lcores.contains(&entry.lcore) {
+				return Err(format!("lcore {} is mapped more than once", entry.lcore));
+			}
+			lcores.push(entry.lcore);
+
+			Self::claim(&mut rx_owner, entry.rx, entry.lcore, "rx")?;
+			Self::claim(&mut tx_owner, entry.tx, entry.lcore, "tx")?;
+		}
+
+		if let Some(q) = rx_owner.iter().position(Option::is_none) {
+			return Err(format!("rx queue {} is not polled by any lcore", q));
+		}
+		Ok(())
+	}
+
+	/// Check that a mapping only uses the lcores that poll the ports
+	///
+	/// Rx queues must belong to one of `rx_lcores` and tx queues to `tx_lcore`, which must
+	/// own one; a queue mapped to any other lcore would never be polled.
+	pub fn check_lcores(map: &[Self], rx_lcores: &[u32], tx_lcore: u32) -> Result<(), String> {
+		for entry in map {
+			if let Some(q) = entry.rx.filter(|_| !rx_lcores.contains(&entry.lcore)) {
+				return Err(format!(
+					"rx queue {} is mapped to lcore {} but only lcores {:?} receive",
+					q, entry.lcore, rx_lcores
+				));
+			}
+			if let Some(q) = entry.tx.filter(|_| entry.lcore != tx_lcore) {
+				return Err(format!(
+					"tx queue {} is mapped to lcore {} but only lcore {} sends",
+					q, entry.lcore, tx_lcore
+				));
+			}
+		}
+		if !map.iter().any(|entry| entry.lcore == tx_lcore && entry.tx.is_some()) {
+			return Err(format!("lcore {} sends but has no tx queue", tx_lcore));
+		}
+		Ok(())
+	}
+
+	/// Record `lcore` as the owner of `queue` in `owners`
+	fn claim(
+		owners: &mut [Option<u32>],
+		queue: Option<u16>,
+		lcore: u32,
+		kind: &str,
+	) -> Result<(), String> {
+		let q = match queue {
+			Some(q) => q,
+			None => return Ok(()),
+		};
+		let len = owners.len();
+		match owners.get_mut(q as usize) {
+			None => Err(format!(
+				"lcore {} uses {} queue {} but only {} are set up",
+				lcore, kind, q, len
+			)),
+			Some(Some(other)) => Err(format!(
+				"{} queue {} is shared by lcores {} and {}",
+				kind, q, other, lcore
+			)),
+			Some(owner) => {
+				*owner = Some(lcore);
+				Ok(())
+			}
+		}
+	}
+}
+
+#[derive(Clone)]
 pub struct Port {
 	pub id: u16,
-	pub device: &'static str,
+	pub device: String,
 	pub dev_info: dpdk_sys::rte_eth_dev_info,
+	rx_burst: u16,
+	tx_burst: u16,
+	// rx and tx queue of every lcore, indexed by lcore id
+	lcore_rxq: Vec<Option<u16>>,
+	lcore_txq: Vec<Option<u16>>,
+	// flow rules the PMD rejected, by priority, with the id of their handle
+	pub(super) sw_flows: Vec<(u64, FlowRule)>,
+	pub(super) next_sw_flow: u64,
+	// packets software queue rules moved to another rx queue, by queue, until its lcore takes them
+	pub(super) steered: Arc<Vec<SegQueue<Mbuf>>>,
+	offloads: OffloadReport,
+	// checksum offloads requested but done in software
+	sw_rx_cksum: RxOffloads,
+	sw_tx_cksum: TxOffloads,
 }
 
 unsafe impl Sync for Port {}
@@ -31,50 +220,129 @@ impl Port {
 	const RX_BURST_MAX: u16 = 32;
 	const TX_BURST_MAX: u16 = 32;
 
-	const RTE_MP_RX_DESC_DEFAULT: u16 = 512;
-	const RTE_MP_TX_DESC_DEFAULT: u16 = 512;
-
-	const RSS_SYMMETRIC_KEY: [u8; 40] = [
+	/// RSS key programmed by `configure`; see `Toeplitz` for the matching software hash
+	pub const RSS_SYMMETRIC_KEY: [u8; 40] = [
 		0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d,
 		0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a,
 		0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a,
 	];
 
-	pub fn new(device: &'static str, id: u16) -> Result<Self, PortError> {
+	pub fn new(device: &str, id: u16) -> Result<Self, PortError> {
 		let mut dev_info = dpdk_sys::rte_eth_dev_info::default();
 		match unsafe { dpdk_sys::rte_eth_dev_info_get(id, &mut dev_info) } {
 			0 => Ok(Self {
 				id,
-				device,
+				device: String::from(device),
 				dev_info,
+				rx_burst: Self::RX_BURST_MAX,
+				tx_burst: Self::TX_BURST_MAX,
+				lcore_rxq: vec![None; dpdk_sys::RTE_MAX_LCORE as usize],
+				lcore_txq: vec![None; dpdk_sys::RTE_MAX_LCORE as usize],
+				sw_flows: Vec::new(),
+				next_sw_flow: 0,
+				steered: Arc::default(),
+				offloads: OffloadReport::default(),
+				sw_rx_cksum: RxOffloads::empty(),
+				sw_tx_cksum: TxOffloads::empty(),
 			}),
 			_ => Err(PortError::new()),
 		}
 	}
 
-	pub fn configure(&mut self, num_cores: u16, mempool: &Mempool) -> Result<(), PortError> {
+	/// Probe the device described by `devargs` at runtime and open its port
+	///
+	/// `devargs` is given as to `--vdev` or `-w`, e.g. `net_pcap1,iface=eth1` or `0000:03:00.1`;
+	/// the port is named after the part before the first comma.
+	/// The port still has to be configured and started.
+	pub fn attach(devargs: &str) -> Result<Self, PortError> {
+		let name = devargs.split(',').next().unwrap_or_default().trim();
+		let c_devargs = devargs.to_cstring().map_err(|_| PortError::Invalid)?;
+		match unsafe { dpdk_sys::rte_dev_probe(c_devargs.as_ptr()) } {
+			0 => {}
+			ret => {
+				let e = PortError::from_errno(-ret);
+				log::error!("could not probe device {}: {}", devargs, e);
+				return Err(e);
+			}
+		}
+		let c_name = name.to_cstring().map_err(|_| PortError::Invalid)?;
+		let mut id = 0;
+		match unsafe { dpdk_sys::rte_eth_dev_get_port_by_name(c_name.as_ptr(), &mut id) } {
+			0 => Self::new(name, id),
+			_ => {
+				log::error!("device {} was probed but has no ethdev port", name);
+				Err(PortError::NoDevice)
+			}
+		}
+	}
+
+	/// Set the maximum number of packets handled by a single `receive` and `send`
+	pub fn set_burst(&mut self, rx_burst: u16, tx_burst: u16) {
+		self.rx_burst = rx_burst;
+		self.tx_burst = tx_burst;
+	}
+
+	/// Configure the port and set its queues up
+	///
+	/// Rx queues receive into the pool of `mempools` on the port's NUMA socket.
+	pub fn configure(
+		&mut self,
+		port_conf: &PortConfig,
+		mempools: &MempoolSet,
+	) -> Result<(), PortError> {
 		let mut conf = dpdk_sys::rte_eth_conf::default();
+		let mempool = mempools.for_socket(self.socket_id());
+		if mempools.pools().len() > 1
+			&& self.socket_id() >= 0
+			&& mempool.socket_id() != self.socket_id()
+		{
+			log::warn!(
+				"port {}: no mempool on socket {}, using socket {}",
+				self.id,
+				self.socket_id(),
+				mempool.socket_id()
+			);
+		}
+
+		let frame_len = Self::frame_len(port_conf.mtu);
+		if frame_len > self.dev_info.max_rx_pktlen {
+			log::error!(
+				"port {}: mtu {} needs {} byte frames, the port takes at most {}",
+				self.id,
+				port_conf.mtu,
+				frame_len,
+				self.dev_info.max_rx_pktlen
+			);
+			return Err(PortError::Invalid);
+		}
+		let offloads = self.negotiate_offloads(port_conf, frame_len, mempool)?;
 
-		conf.rxmode.mq_mode = dpdk_sys::rte_eth_rx_mq_mode::ETH_MQ_RX_RSS;
-		conf.rxmode.max_rx_pkt_len = dpdk_sys::RTE_ETHER_MAX_LEN;
+		// without the jumbo frame offload the PMD caps frames at RTE_ETHER_MAX_LEN
+		conf.rxmode.max_rx_pkt_len = frame_len.max(dpdk_sys::RTE_ETHER_MAX_LEN);
 		conf.rxmode.split_hdr_size = 0;
-		conf.rxmode.offloads = dpdk_sys::DEV_RX_OFFLOAD_CHECKSUM as u64;
+		conf.rxmode.offloads = offloads.rx_enabled.bits();
 
 		conf.rx_adv_conf.rss_conf.rss_hf =
 			Self::DEFAULT_RSS_HF & self.dev_info.flow_type_rss_offloads;
+		// PMDs without RSS, e.g. net_ring, reject an RSS rx mode
+		conf.rxmode.mq_mode = if conf.rx_adv_conf.rss_conf.rss_hf != 0 {
+			dpdk_sys::rte_eth_rx_mq_mode::ETH_MQ_RX_RSS
+		} else {
+			dpdk_sys::rte_eth_rx_mq_mode::ETH_MQ_RX_NONE
+		};
 		let rss_symmetric_key = &Self::RSS_SYMMETRIC_KEY as *const u8 as *mut u8;
 		conf.rx_adv_conf.rss_conf.rss_key = rss_symmetric_key;
+		conf.rx_adv_conf.rss_conf.rss_key_len = Self::RSS_SYMMETRIC_KEY.len() as u8;
 
 		conf.txmode.mq_mode = 0;
-		conf.txmode.offloads = 0x00000002 | 0x00000004 | 0x00000008;
+		conf.txmode.offloads = offloads.tx_enabled.bits();
 
-		// turns on optimization for fast release of mbufs.
-		if self.dev_info.tx_offload_capa & dpdk_sys::DEV_TX_OFFLOAD_MBUF_FAST_FREE as u64 > 0 {
-			conf.txmode.offloads |= dpdk_sys::DEV_TX_OFFLOAD_MBUF_FAST_FREE as u64;
-		}
+		let rx_queues = port_conf.rx_queues;
+		let tx_queues = port_conf.tx_queues;
+		self.set_queue_map(&port_conf.queues, rx_queues, tx_queues)?;
 
 		// configure the device
-		match unsafe { dpdk_sys::rte_eth_dev_configure(self.id, num_cores, num_cores, &conf) } {
+		match unsafe { dpdk_sys::rte_eth_dev_configure(self.id, rx_queues, tx_queues, &conf) } {
 			0 => {}
 			_ => return Err(PortError::new()),
 		};
@@ -83,21 +351,13 @@ impl Port {
 		let rx_conf = &self.dev_info.default_rxconf;
 		let tx_conf = &self.dev_info.default_txconf;
 
-		let mut n_cores = num_cores;
-
-		if num_cores % 2 == 1 {
-			n_cores += 1;
-		} else {
-			n_cores = num_cores;
-		}
-
-		for i in 0..n_cores {
+		for i in 0..rx_queues {
 			unsafe {
 				match dpdk_sys::rte_eth_rx_queue_setup(
 					self.id,
 					i,
-					Self::RTE_MP_RX_DESC_DEFAULT,
-					dpdk_sys::rte_eth_dev_socket_id(self.id) as u32,
+					port_conf.rx_desc,
+					self.socket_id() as u32,
 					rx_conf,
 					mempool.get_ptr(),
 				) {
@@ -108,12 +368,16 @@ impl Port {
 						return Err(e);
 					}
 				}
+			}
+		}
 
+		for i in 0..tx_queues {
+			unsafe {
 				match dpdk_sys::rte_eth_tx_queue_setup(
 					self.id,
 					i,
-					Self::RTE_MP_TX_DESC_DEFAULT,
-					dpdk_sys::rte_eth_dev_socket_id(self.id) as u32,
+					port_conf.tx_desc,
+					self.socket_id() as u32,
 					tx_conf,
 				) {
 					0 => {}
@@ -126,14 +390,187 @@ impl Port {
 			}
 		}
 
+		// spread the redirection table over the rx queues so `Reta::round_robin` predicts it
+		if rx_queues > 1 && self.reta_size() > 0 {
+			match self.set_reta(&Reta::round_robin(self.reta_size(), rx_queues)) {
+				Ok(()) => {}
+				// the PMD keeps its own table; RSS still spreads the traffic
+				Err(PortError::NoSupport) => {
+					log::warn!("port {}: PMD cannot update its redirection table", self.id)
+				}
+				Err(e) => {
+					log::error!("port {}: couldn't set the redirection table: {}", self.id, e);
+					return Err(e);
+				}
+			}
+		}
+
+		match self.set_mtu(port_conf.mtu) {
+			Ok(()) => {}
+			// PMDs such as net_ring have no MTU; max_rx_pkt_len already bounds their frames
+			Err(PortError::NoSupport) => {
+				log::warn!("port {}: PMD cannot set mtu {}", self.id, port_conf.mtu)
+			}
+			Err(e) => {
+				log::error!("port {}: couldn't set mtu {}: {}", self.id, port_conf.mtu, e);
+				return Err(e);
+			}
+		}
+
 		// sets the port's promiscuous mode
 		match unsafe { dpdk_sys::rte_eth_promiscuous_enable(self.id) } {
 			0 => {}
+			ret if -ret == ENOTSUP => {
+				log::warn!("port {}: promiscuous mode is not supported", self.id)
+			}
 			_ => return Err(PortError::new()),
 		};
 		Ok(())
 	}
 
+	/// Largest frame, CRC included, a port with MTU `mtu` receives
+	#[inline]
+	fn frame_len(mtu: u16) -> u32 {
+		mtu as u32 + dpdk_sys::RTE_ETHER_HDR_LEN + dpdk_sys::RTE_ETHER_CRC_LEN
+	}
+
+	/// Intersect the offloads `port_conf` requests with the capabilities of the PMD
+	///
+	/// Frames longer than `RTE_ETHER_MAX_LEN` add the jumbo frame offload and frames that
+	/// do not fit a single mbuf of `mempool` add scattered rx; both are required.
+	/// Missing checksum offloads are taken over by `receive_on` and `send_on`.
+	fn negotiate_offloads(
+		&mut self,
+		port_conf: &PortConfig,
+		frame_len: u32,
+		mempool: &Mempool,
+	) -> Result<OffloadReport, PortError> {
+		let rx_capa = RxOffloads::from_bits(self.dev_info.rx_offload_capa);
+		let tx_capa = TxOffloads::from_bits(self.dev_info.tx_offload_capa);
+
+		let mut rx_required = RxOffloads::empty();
+		let mut tx_wanted = TxOffloads::empty();
+		if frame_len > dpdk_sys::RTE_ETHER_MAX_LEN {
+			rx_required = rx_required
+				.union(RxOffloads::from_bits(dpdk_sys::DEV_RX_OFFLOAD_JUMBO_FRAME as u64));
+		}
+		if frame_len > mempool.data_size() {
+			rx_required =
+				rx_required.union(RxOffloads::from_bits(dpdk_sys::DEV_RX_OFFLOAD_SCATTER as u64));
+			// frames received as mbuf chains go out as chains too
+			tx_wanted = TxOffloads::from_bits(dpdk_sys::DEV_TX_OFFLOAD_MULTI_SEGS as u64);
+		}
+		if !rx_capa.contains(rx_required) {
+			log::error!(
+				"port {}: mtu {} with {} byte mbufs needs rx offloads {} the PMD lacks",
+				self.id,
+				port_conf.mtu,
+				mempool.data_size(),
+				rx_required.difference(rx_capa)
+			);
+			return Err(PortError::NoSupport);
+		}
+
+		let rx_requested = port_conf.rx_offloads.union(rx_required);
+		let tx_requested = port_conf.tx_offloads.union(tx_wanted);
+		let report = OffloadReport {
+			rx_requested,
+			rx_enabled: rx_requested.intersection(rx_capa),
+			tx_requested,
+			tx_enabled: tx_requested.intersection(tx_capa),
+		};
+
+		self.sw_rx_cksum = report.rx_missing().intersection(RxOffloads::checksum());
+		self.sw_tx_cksum = report.tx_missing().intersection(TxOffloads::checksum());
+		self.offloads = report;
+
+		log::info!("port {}: offloads {}", self.id, report);
+		if !self.sw_rx_cksum.is_empty() || !self.sw_tx_cksum.is_empty() {
+			log::warn!(
+				"port {}: checksums done in software: rx {} tx {}",
+				self.id,
+				self.sw_rx_cksum,
+				self.sw_tx_cksum
+			);
+		}
+		Ok(report)
+	}
+
+	/// Get the MTU of the port
+	pub fn mtu(&self) -> Result<u16, PortError> {
+		let mut mtu = 0;
+		match unsafe { dpdk_sys::rte_eth_dev_get_mtu(self.id, &mut mtu) } {
+			0 => Ok(mtu),
+			_ => Err(PortError::new()),
+		}
+	}
+
+	/// Set the MTU of the port
+	///
+	/// Frames longer than the `max_rx_pkt_len` the port was configured with are still dropped.
+	pub fn set_mtu(&self, mtu: u16) -> Result<(), PortError> {
+		match unsafe { dpdk_sys::rte_eth_dev_set_mtu(self.id, mtu) } {
+			0 => Ok(()),
+			ret if -ret == ENOTSUP => Err(PortError::NoSupport),
+			_ => Err(PortError::new()),
+		}
+	}
+
+	/// Offloads requested for the port and offloads actually enabled by `configure`
+	pub fn offloads(&self) -> OffloadReport {
+		self.offloads
+	}
+
+	/// Replace the lcore to queue mapping
+	///
+	/// The mapping is rejected if it does not fit `rx_queues` and `tx_queues` (see `LcoreQueues::validate`)
+	pub fn set_queue_map(
+		&mut self,
+		map: &[LcoreQueues],
+		rx_queues: u16,
+		tx_queues: u16,
+	) -> Result<(), PortError> {
+		if let Err(e) = LcoreQueues::validate(map, rx_queues, tx_queues) {
+			log::error!("port {}: {}", self.id, e);
+			return Err(PortError::BadQueueMap(e));
+		}
+		self.lcore_rxq.iter_mut().for_each(|q| *q = None);
+		self.lcore_txq.iter_mut().for_each(|q| *q = None);
+		for entry in map {
+			self.lcore_rxq[entry.lcore as usize] = entry.rx;
+			self.lcore_txq[entry.lcore as usize] = entry.tx;
+		}
+		self.steered = Arc::new((0..rx_queues).map(|_| SegQueue::new()).collect());
+		Ok(())
+	}
+
+	/// The current lcore to queue mapping
+	pub fn queue_map(&self) -> Vec<LcoreQueues> {
+		self.lcore_rxq
+			.iter()
+			.zip(self.lcore_txq.iter())
+			.enumerate()
+			.filter(|(_, (rx, tx))| rx.is_some() || tx.is_some())
+			.map(|(lcore, (&rx, &tx))| LcoreQueues {
+				lcore: lcore as u32,
+				rx,
+				tx,
+			})
+			.collect()
+	}
+
+	/// The rx queue polled by `lcore`, if any
+	#[inline]
+	pub fn rx_queue(&self, lcore: u32) -> Option<u16> {
+		self.lcore_rxq.get(lcore as usize).copied().flatten()
+	}
+
+	/// The tx queue used by `lcore`, if any
+	#[inline]
+	pub fn tx_queue(&self, lcore: u32) -> Option<u16> {
+		self.lcore_txq.get(lcore as usize).copied().flatten()
+	}
+
 	/// Start the port
 	pub fn start(&self) -> Result<(), PortError> {
 		unsafe {
@@ -144,62 +581,157 @@ impl Port {
 		}
 	}
 
+	/// Stop the port; it can be reconfigured and started again
+	///
+	/// No lcore may be polling the port any more.
+	pub fn stop(&self) {
+		unsafe { dpdk_sys::rte_eth_dev_stop(self.id) };
+	}
+
+	/// Stop the port and release its queues; the port cannot be used afterwards
+	pub fn close(&self) {
+		unsafe {
+			dpdk_sys::rte_eth_dev_stop(self.id);
+			dpdk_sys::rte_eth_dev_close(self.id);
+		}
+	}
+
+	/// Close the port and remove its device, undoing `attach`
+	pub fn detach(self) -> Result<(), PortError> {
+		self.close();
+		match unsafe { dpdk_sys::rte_dev_remove(self.dev_info.device) } {
+			0 => Ok(()),
+			ret => {
+				let e = PortError::from_errno(-ret);
+				log::error!("port {}: could not remove device {}: {}", self.id, self.device, e);
+				Err(e)
+			}
+		}
+	}
+
+	/// NUMA socket the port is attached to; `SOCKET_ID_ANY` (-1) if unknown
+	#[inline]
+	pub fn socket_id(&self) -> i32 {
+		unsafe { dpdk_sys::rte_eth_dev_socket_id(self.id) }
+	}
+
 	/// Get user device in PCI notation
 	pub fn get_name(&self) -> &str {
-		self.device
+		&self.device
 	}
 
 	/// Get mac address for port
-	// pub fn mac_addr(&self) -> Result<MacAddr, PortError> {
-	// 	unsafe {
-	// 		let mac = dpdk_sys::rte_ether_addr::default();
-	// 		match dpdk_sys::rte_eth_macaddr_get(self.id, &mac as *const _ as *mut _) {
-	// 			// 0 => Ok(MacAddr::from_ether_addr(mac)),
-	// 			0 => Ok(MacAddr::new(
-	// 				mac.addr_bytes[0],
-	// 				mac.addr_bytes[1],
-	// 				mac.addr_bytes[2],
-	// 				mac.addr_bytes[3],
-	// 				mac.addr_bytes[4],
-	// 				mac.addr_bytes[5],
-	// 			)),
-	// 			_ => Err(PortError::new()),
-	// 		}
-	// 	}
-	// }
-
-	pub fn receive(&self, queue_id: u16) -> Vec<Mbuf> {
-		const RX_BURST_MAX: usize = 32;
-		let mut ptrs = Vec::with_capacity(RX_BURST_MAX);
+	pub fn mac_addr(&self) -> Result<MacAddr, PortError> {
+		let mut mac = dpdk_sys::rte_ether_addr::default();
+		match unsafe { dpdk_sys::rte_eth_macaddr_get(self.id, &mut mac) } {
+			0 => Ok(MacAddr::from_ether_addr(mac)),
+			_ => Err(PortError::new()),
+		}
+	}
+
+	/// Get the current link state without waiting for the PMD to settle
+	pub fn link(&self) -> Result<LinkStatus, PortError> {
+		let mut link = dpdk_sys::rte_eth_link::default();
+		match unsafe { dpdk_sys::rte_eth_link_get_nowait(self.id, &mut link) } {
+			0 => Ok(LinkStatus::from_raw(&link)),
+			_ => Err(PortError::new()),
+		}
+	}
+
+	/// Poll the link until it is up or `timeout` has passed
+	pub fn wait_for_link(&self, timeout: Duration) -> Result<LinkStatus, PortError> {
+		const POLL_INTERVAL: Duration = Duration::from_millis(100);
+		let deadline = Instant::now() + timeout;
+		loop {
+			let link = self.link()?;
+			if link.up {
+				return Ok(link);
+			}
+			if Instant::now() >= deadline {
+				return Err(PortError::LinkTimeout(timeout));
+			}
+			thread::sleep(POLL_INTERVAL);
+		}
+	}
+
+	/// Receive packets from the rx queue of the calling lcore
+	///
+	/// Returns nothing if the lcore has no rx queue on this port
+	#[inline]
+	pub fn receive(&self) -> Vec<Mbuf> {
+		match self.rx_queue(current_lcore()) {
+			Some(queue_id) => self.receive_on(queue_id),
+			None => Vec::new(),
+		}
+	}
+
+	/// Send packets out of the tx queue of the calling lcore
+	///
+	/// Returns how many the NIC took. If the lcore has no tx queue on this port
+	/// the packets are freed and `PortError::NoTxQueue` is returned
+	#[inline]
+	pub fn send(&self, pkts: Vec<Mbuf>) -> Result<usize, PortError> {
+		let lcore = current_lcore();
+		match self.tx_queue(lcore) {
+			Some(queue_id) => Ok(self.send_on(pkts, queue_id)),
+			None => {
+				Mbuf::free_bulk(pkts);
+				Err(PortError::NoTxQueue(lcore))
+			}
+		}
+	}
+
+	/// Receive packets from the rx queue `queue_id`
+	///
+	/// The caller must make sure no other lcore polls the same queue
+	pub fn receive_on(&self, queue_id: u16) -> Vec<Mbuf> {
+		let mut ptrs = Vec::with_capacity(self.rx_burst as usize);
 
 		let len = unsafe {
-			dpdk_sys::_rte_eth_rx_burst(self.id, queue_id, ptrs.as_mut_ptr(), RX_BURST_MAX as u16)
+			dpdk_sys::_rte_eth_rx_burst(self.id, queue_id, ptrs.as_mut_ptr(), self.rx_burst)
 		};
 
-		unsafe {
+		let mut pkts = unsafe {
 			ptrs.set_len(len as usize);
 			ptrs.into_iter()
 				.map(|ptr| Mbuf::from_ptr(ptr))
 				.collect::<Vec<_>>()
+		};
+		if !self.sw_rx_cksum.is_empty() {
+			offload::sw_rx_cksum(&mut pkts, self.sw_rx_cksum);
 		}
+		pkts
 	}
 
-	/// Send packets out of the port
-	pub fn send(&self, pkts: Vec<Mbuf>, queue_id: u16) -> usize {
+	/// Send packets out of the tx queue `queue_id`
+	///
+	/// Packets are handed to the NIC at most `tx_burst` at a time;
+	/// whatever the NIC does not accept is freed.
+	/// The caller must make sure no other lcore uses the same queue
+	pub fn send_on(&self, mut pkts: Vec<Mbuf>, queue_id: u16) -> usize {
+		if !self.sw_tx_cksum.is_empty() {
+			offload::sw_tx_cksum(&mut pkts, self.sw_tx_cksum);
+		}
 		let len = pkts.len();
 		let mut ptrs = pkts.into_iter().map(Mbuf::into_ptr).collect::<Vec<_>>();
 
-		let count = unsafe {
-			dpdk_sys::_rte_eth_tx_burst(
-				self.id,
-				queue_id,
-				// ptrs.as_ptr() as *mut *mut dpdk_sys::rte_mbuf,
-				ptrs.as_mut_ptr(),
-				ptrs.len() as u16,
-			) as usize
-		};
+		let mut count = 0;
+		for chunk in ptrs.chunks_mut(self.tx_burst as usize) {
+			let sent = unsafe {
+				dpdk_sys::_rte_eth_tx_burst(
+					self.id,
+					queue_id,
+					chunk.as_mut_ptr(),
+					chunk.len() as u16,
+				) as usize
+			};
+			count += sent;
+			if sent < chunk.len() {
+				break;
+			}
+		}
 		if count < len {
-			super::mbuf_free_bulk(ptrs);
+			super::mbuf_free_bulk(ptrs.split_off(count));
 		}
 		count
 	}
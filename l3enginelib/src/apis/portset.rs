@@ -0,0 +1,147 @@
+//! The set of ports the engine polls, changeable while the lcores run
+//!
+//! Every polling lcore holds a `PortSetReader` with its own copy of the set, so the
+//! fast path takes no lock; the copy is refreshed whenever the set's generation moves.
+//! `PortSet::remove` returns only once every reader has refreshed past the removal,
+//! after which no lcore touches the port any more and it can be stopped or detached.
+
+use std::{
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Mutex,
+	},
+	thread,
+	time::Duration,
+};
+
+use super::Port;
+
+struct Shared {
+	ports: Mutex<Vec<Port>>,
+	generation: AtomicU64,
+	// generation last seen by every live reader
+	readers: Mutex<Vec<Arc<AtomicU64>>>,
+}
+
+/// Ports shared between the polling lcores and the control plane
+///
+/// Clones refer to the same set.
+#[derive(Clone)]
+pub struct PortSet {
+	shared: Arc<Shared>,
+}
+
+impl PortSet {
+	/// How often `remove` checks whether the readers have caught up
+	const QUIESCE_POLL: Duration = Duration::from_micros(100);
+
+	pub fn new(ports: Vec<Port>) -> Self {
+		Self {
+			shared: Arc::new(Shared {
+				ports: Mutex::new(ports),
+				generation: AtomicU64::new(0),
+				readers: Mutex::new(Vec::new()),
+			}),
+		}
+	}
+
+	/// A reader for a polling lcore
+	pub fn reader(&self) -> PortSetReader {
+		let seen = Arc::new(AtomicU64::new(0));
+		self.shared.readers.lock().unwrap().push(seen.clone());
+		let mut reader = PortSetReader {
+			shared: self.shared.clone(),
+			generation: 0,
+			seen,
+			ports: Vec::new(),
+		};
+		reader.refresh();
+		reader
+	}
+
+	/// A copy of the ports currently in the set
+	pub fn ports(&self) -> Vec<Port> {
+		self.shared.ports.lock().unwrap().clone()
+	}
+
+	/// Is the port with id `id` in the set
+	pub fn contains(&self, id: u16) -> bool {
+		self.shared.ports.lock().unwrap().iter().any(|port| port.id == id)
+	}
+
+	/// Add `port`, replacing the port with the same id if there is one
+	///
+	/// Readers start polling the port on their next refresh.
+	pub fn insert(&self, port: Port) {
+		let mut ports = self.shared.ports.lock().unwrap();
+		match ports.iter_mut().find(|p| p.id == port.id) {
+			Some(p) => *p = port,
+			None => ports.push(port),
+		}
+		self.shared.generation.fetch_add(1, Ordering::AcqRel);
+	}
+
+	/// Take the port with id `id` out of the set
+	///
+	/// Blocks until every reader has dropped its copy of the port,
+	/// so a reader must refresh regularly for as long as it lives.
+	pub fn remove(&self, id: u16) -> Option<Port> {
+		let (port, generation) = {
+			let mut ports = self.shared.ports.lock().unwrap();
+			let i = ports.iter().position(|port| port.id == id)?;
+			let port = ports.remove(i);
+			(port, self.shared.generation.fetch_add(1, Ordering::AcqRel) + 1)
+		};
+		while self
+			.shared
+			.readers
+			.lock()
+			.unwrap()
+			.iter()
+			.any(|seen| seen.load(Ordering::Acquire) < generation)
+		{
+			thread::sleep(Self::QUIESCE_POLL);
+		}
+		Some(port)
+	}
+}
+
+/// A polling lcore's view of a `PortSet`
+pub struct PortSetReader {
+	shared: Arc<Shared>,
+	generation: u64,
+	seen: Arc<AtomicU64>,
+	ports: Vec<Port>,
+}
+
+impl PortSetReader {
+	/// The ports to poll, refreshed if the set has changed since the last call
+	///
+	/// Calling this also tells `PortSet::remove` that ports removed so far are no longer in use.
+	#[inline]
+	pub fn ports(&mut self) -> &[Port] {
+		if self.shared.generation.load(Ordering::Acquire) != self.generation {
+			self.refresh();
+		}
+		&self.ports
+	}
+
+	fn refresh(&mut self) {
+		{
+			let ports = self.shared.ports.lock().unwrap();
+			self.generation = self.shared.generation.load(Ordering::Acquire);
+			self.ports = ports.clone();
+		}
+		self.seen.store(self.generation, Ordering::Release);
+	}
+}
+
+impl Drop for PortSetReader {
+	fn drop(&mut self) {
+		self.shared
+			.readers
+			.lock()
+			.unwrap()
+			.retain(|seen| !Arc::ptr_eq(seen, &self.seen));
+	}
+}
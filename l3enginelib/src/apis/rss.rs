@@ -0,0 +1,259 @@
+//! Receive side scaling in software
+//!
+//! `Toeplitz` computes the same hash the NIC computes with the key programmed by
+//! `Port::configure`, and `Reta` maps that hash to an rx queue the same way the
+//! NIC's redirection table does. Together they tell software which queue, and
+//! hence which lcore, a flow is received on.
+//!
+//! With `Port::RSS_SYMMETRIC_KEY` both directions of a flow hash to the same value.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use super::{Port, PortError};
+
+/// Toeplitz hash keyed like the NIC
+#[derive(Debug, Clone)]
+pub struct Toeplitz {
+	key: Vec<u8>,
+}
+
+impl Toeplitz {
+	/// Longest input the `hash_*` functions hash, an IPv6 address pair and ports
+	pub const MAX_INPUT: usize = 36;
+	/// Shortest key that covers `MAX_INPUT` bytes of input
+	pub const MIN_KEY_LEN: usize = Self::MAX_INPUT + 4;
+
+	/// A hasher using `key`, which must be at least `MIN_KEY_LEN` bytes long
+	pub fn new(key: &[u8]) -> Result<Self, PortError> {
+		if key.len() < Self::MIN_KEY_LEN {
+			return Err(PortError::RssKeyLen(key.len(), Self::MIN_KEY_LEN));
+		}
+		Ok(Self { key: key.to_vec() })
+	}
+
+	/// A hasher using the key `Port::configure` programs
+	pub fn symmetric() -> Self {
+		// the key is MIN_KEY_LEN bytes long
+		Self {
+			key: Port::RSS_SYMMETRIC_KEY.to_vec(),
+		}
+	}
+
+	/// Hash `input`, given in network byte order
+	///
+	/// Key bits past the end of the key count as zero, so inputs longer than the key
+	/// less 4 bytes do not hash the way a NIC would.
+	pub fn hash(&self, input: &[u8]) -> u32 {
+		let key = |i: usize| self.key.get(i).copied().unwrap_or_default();
+		let mut hash = 0u32;
+		// the 32 key bits aligned with the current input bit
+		let mut window = u32::from_be_bytes([key(0), key(1), key(2), key(3)]);
+		for (i, byte) in input.iter().enumerate() {
+			let next = key(i + 4);
+			for bit in 0..8 {
+				if byte & (0x80 >> bit) != 0 {
+					hash ^= window;
+				}
+				window = (window << 1) | u32::from((next >> (7 - bit)) & 1);
+			}
+		}
+		hash
+	}
+
+	/// Hash of an IPv4 packet without usable L4 ports
+	pub fn hash_ipv4(&self, src: Ipv4Addr, dst: Ipv4Addr) -> u32 {
+		let mut input = [0u8; 8];
+		input[..4].copy_from_slice(&src.octets());
+		input[4..].copy_from_slice(&dst.octets());
+		self.hash(&input)
+	}
+
+	/// Hash of an IPv4 TCP or UDP packet
+	pub fn hash_ipv4_l4(&self, src: Ipv4Addr, dst: Ipv4Addr, sport: u16, dport: u16) -> u32 {
+		let mut input = [0u8; 12];
+		input[..4].copy_from_slice(&src.octets());
+		input[4..8].copy_from_slice(&dst.octets());
+		input[8..10].copy_from_slice(&sport.to_be_bytes());
+		input[10..].copy_from_slice(&dport.to_be_bytes());
+		self.hash(&input)
+	}
+
+	/// Hash of an IPv6 packet without usable L4 ports
+	pub fn hash_ipv6(&self, src: Ipv6Addr, dst: Ipv6Addr) -> u32 {
+		let mut input = [0u8; 32];
+		input[..16].copy_from_slice(&src.octets());
+		input[16..].copy_from_slice(&dst.octets());
+		self.hash(&input)
+	}
+
+	/// Hash of an IPv6 TCP or UDP packet
+	pub fn hash_ipv6_l4(&self, src: Ipv6Addr, dst: Ipv6Addr, sport: u16, dport: u16) -> u32 {
+		let mut input = [0u8; 36];
+		input[..16].copy_from_slice(&src.octets());
+		input[16..32].copy_from_slice(&dst.octets());
+		input[32..34].copy_from_slice(&sport.to_be_bytes());
+		input[34..].copy_from_slice(&dport.to_be_bytes());
+		self.hash(&input)
+	}
+}
+
+/// A redirection table: entry `hash % len` is the rx queue of a packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reta(pub Vec<u16>);
+
+impl Reta {
+	/// A table of `size` entries spreading hashes evenly over the first `rx_queues` queues
+	pub fn round_robin(size: u16, rx_queues: u16) -> Self {
+		Self((0..size).map(|i| i % rx_queues.max(1)).collect())
+	}
+
+	/// The rx queue a packet with RSS hash `hash` lands on
+	///
+	/// NICs index the table with the low bits of the hash;
+	/// for the power of two sizes they use that is the same as the remainder.
+	#[inline]
+	pub fn queue(&self, hash: u32) -> Option<u16> {
+		if self.0.is_empty() {
+			return None;
+		}
+		Some(self.0[hash as usize % self.0.len()])
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.0.len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+}
+
+impl Port {
+	/// Number of entries in the redirection table of the port; 0 if it has none
+	#[inline]
+	pub fn reta_size(&self) -> u16 {
+		self.dev_info.reta_size
+	}
+
+	/// Read the redirection table of the port
+	///
+	/// Fails with `PortError::NoSupport` if the port has no table.
+	pub fn reta(&self) -> Result<Reta, PortError> {
+		let size = self.reta_size();
+		if size == 0 {
+			return Err(PortError::NoSupport);
+		}
+		let mut conf = Self::reta_groups(size);
+		for group in conf.iter_mut() {
+			group.mask = u64::MAX;
+		}
+		// like the update, the query returns the errno
+		match unsafe { dpdk_sys::rte_eth_dev_rss_reta_query(self.id, conf.as_mut_ptr(), size) } {
+			0 => Ok(Reta(
+				conf.iter()
+					.flat_map(|group| group.reta.iter().copied())
+					.take(size as usize)
+					.collect(),
+			)),
+			ret => Err(PortError::from_errno(-ret)),
+		}
+	}
+
+	/// Program the redirection table of the port
+	///
+	/// The table must have exactly `reta_size` entries; fails with `PortError::NoSupport`
+	/// if the port has no table.
+	pub fn set_reta(&self, reta: &Reta) -> Result<(), PortError> {
+		let size = self.reta_size();
+		if size == 0 {
+			return Err(PortError::NoSupport);
+		}
+		if reta.len() != size as usize {
+			log::error!(
+				"port {}: redirection table has {} entries, the port needs {}",
+				self.id,
+				reta.len(),
+				size
+			);
+			return Err(PortError::Invalid);
+		}
+		let group_size = dpdk_sys::RTE_RETA_GROUP_SIZE as usize;
+		let mut conf = Self::reta_groups(size);
+		for (i, &queue) in reta.0.iter().enumerate() {
+			let group = &mut conf[i / group_size];
+			group.mask |= 1 << (i % group_size);
+			group.reta[i % group_size] = queue;
+		}
+		// the errno is returned, not left in rte_errno; ENOTSUP if the PMD has no RETA update
+		match unsafe { dpdk_sys::rte_eth_dev_rss_reta_update(self.id, conf.as_mut_ptr(), size) } {
+			0 => Ok(()),
+			ret => Err(PortError::from_errno(-ret)),
+		}
+	}
+
+	/// A hasher matching the RSS key the port is configured with
+	pub fn rss_hasher(&self) -> Toeplitz {
+		Toeplitz::symmetric()
+	}
+
+	/// Empty RETA groups covering `size` entries
+	fn reta_groups(size: u16) -> Vec<dpdk_sys::rte_eth_rss_reta_entry64> {
+		let group_size = dpdk_sys::RTE_RETA_GROUP_SIZE as usize;
+		let groups = (size as usize).div_ceil(group_size);
+		vec![
+			dpdk_sys::rte_eth_rss_reta_entry64 {
+				mask: 0,
+				reta: [0; 64],
+			};
+			groups
+		]
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The key of the Microsoft RSS verification suite
+	const MS_KEY: [u8; 40] = [
+		0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f,
+		0xb0, 0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30,
+		0xf2, 0x0c, 0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+	];
+
+	#[test]
+	fn matches_the_microsoft_verification_suite() {
+		let hasher = Toeplitz::new(&MS_KEY).unwrap();
+		let src = Ipv4Addr::new(66, 9, 149, 187);
+		let dst = Ipv4Addr::new(161, 142, 100, 80);
+		assert_eq!(hasher.hash_ipv4(src, dst), 0x323e8fc2);
+		assert_eq!(hasher.hash_ipv4_l4(src, dst, 2794, 1766), 0x51ccc178);
+	}
+
+	#[test]
+	fn rejects_a_key_too_short_for_ipv6() {
+		match Toeplitz::new(&MS_KEY[..16]) {
+			Err(PortError::RssKeyLen(16, Toeplitz::MIN_KEY_LEN)) => {}
+			other => panic!("16 byte key accepted: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn the_symmetric_key_hashes_both_directions_alike() {
+		let hasher = Toeplitz::symmetric();
+		let a = Ipv4Addr::new(10, 0, 0, 1);
+		let b = Ipv4Addr::new(192, 168, 1, 2);
+		assert_eq!(hasher.hash_ipv4(a, b), hasher.hash_ipv4(b, a));
+		assert_eq!(hasher.hash_ipv4_l4(a, b, 1234, 80), hasher.hash_ipv4_l4(b, a, 80, 1234));
+	}
+
+	#[test]
+	fn round_robin_spreads_hashes_over_the_queues() {
+		let reta = Reta::round_robin(8, 3);
+		assert_eq!(reta.0, vec![0, 1, 2, 0, 1, 2, 0, 1]);
+		assert_eq!(reta.queue(10), Some(2));
+		assert_eq!(Reta(Vec::new()).queue(10), None);
+	}
+}
@@ -0,0 +1,223 @@
+//! Typed objects in memory shared between DPDK processes
+//!
+//! A `SharedBox<T>` places a `T` in a memzone named after it, so the primary and the
+//! secondaries all reach the same value. The process that creates the box initialises
+//! the value and frees the memzone when the box is dropped; the others attach to it by
+//! name with `SharedBox::attach`, or with `SharedRef::attach` to only read it.
+//! Other processes may use the value at any time, so both only hand out `&T`;
+//! fields changed after creation are atomics.
+//!
+//! Attached handles must be dropped before the owner's box is, as the memory goes away with it.
+
+use std::{
+	any, fmt, mem,
+	ops::Deref,
+	ptr::{self, NonNull},
+	sync::atomic::{
+		AtomicBool, AtomicI16, AtomicI32, AtomicI64, AtomicI8, AtomicIsize, AtomicU16, AtomicU32,
+		AtomicU64, AtomicU8, AtomicUsize,
+	},
+};
+
+use super::{MemoryError, Memzone};
+
+/// Types that can be placed in shared memory
+///
+/// # Safety
+///
+/// A `Shareable` type must
+/// - hold no pointers or references, which would not be valid in another process,
+/// - need no `Drop`, as shared values are never dropped,
+/// - have the same layout in every process using it, so structs must be `#[repr(C)]`,
+/// - be valid when all of its bytes are zero.
+///
+/// Several processes use the value at once, so fields changed after creation should be atomics.
+pub unsafe trait Shareable: Sized + Sync + 'static {}
+
+macro_rules! shareable {
+	($($t:ty),*) => {
+		$(unsafe impl Shareable for $t {})*
+	};
+}
+
+shareable!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64, bool);
+shareable!(
+	AtomicU8,
+	AtomicU16,
+	AtomicU32,
+	AtomicU64,
+	AtomicUsize,
+	AtomicI8,
+	AtomicI16,
+	AtomicI32,
+	AtomicI64,
+	AtomicIsize,
+	AtomicBool
+);
+
+unsafe impl<T: Shareable, const N: usize> Shareable for [T; N] {}
+
+/// Check that `memzone` can hold a `T` and return where it is
+fn place<T: Shareable>(memzone: &Memzone) -> Result<NonNull<T>, MemoryError> {
+	let name = memzone.name();
+	if memzone.size() < mem::size_of::<T>() {
+		return Err(MemoryError::Layout(format!(
+			"memzone {} holds {} bytes, {} needs {}",
+			name,
+			memzone.size(),
+			any::type_name::<T>(),
+			mem::size_of::<T>()
+		)));
+	}
+	let addr = memzone.addr();
+	if addr as usize & (mem::align_of::<T>() - 1) != 0 {
+		return Err(MemoryError::Layout(format!(
+			"memzone {} at {:p} is not aligned to {} bytes for {}",
+			name,
+			addr,
+			mem::align_of::<T>(),
+			any::type_name::<T>()
+		)));
+	}
+	NonNull::new(addr as *mut T)
+		.ok_or_else(|| MemoryError::Layout(format!("memzone {} has no address", name)))
+}
+
+/// A handle to a `T` in shared memory that can create, attach to and free it
+pub struct SharedBox<T: Shareable> {
+	memzone: Memzone,
+	ptr: NonNull<T>,
+}
+
+impl<T: Shareable> SharedBox<T> {
+	/// Place `value` in a new memzone named `name` on the calling lcore's socket
+	pub fn new(name: &str, value: T) -> Result<Self, MemoryError> {
+		Self::new_on_socket(name, value, unsafe { dpdk_sys::rte_socket_id() } as i32)
+	}
+
+	/// Place `value` in a new memzone named `name` on `socket`
+	pub fn new_on_socket(name: &str, value: T, socket: i32) -> Result<Self, MemoryError> {
+		let shared = Self::reserve(name, socket)?;
+		unsafe { ptr::write(shared.ptr.as_ptr(), value) };
+		Ok(shared)
+	}
+
+	/// A zeroed `T` in a new memzone named `name` on the calling lcore's socket
+	///
+	/// Unlike `new`, the value is built in place, so large tables never go through the stack.
+	pub fn zeroed(name: &str) -> Result<Self, MemoryError> {
+		Self::zeroed_on_socket(name, unsafe { dpdk_sys::rte_socket_id() } as i32)
+	}
+
+	/// A zeroed `T` in a new memzone named `name` on `socket`
+	pub fn zeroed_on_socket(name: &str, socket: i32) -> Result<Self, MemoryError> {
+		let shared = Self::reserve(name, socket)?;
+		unsafe { ptr::write_bytes(shared.ptr.as_ptr(), 0, 1) };
+		Ok(shared)
+	}
+
+	/// Attach to the `T` another process placed under `name`
+	///
+	/// The memzone is left to its owner when the handle is dropped.
+	pub fn attach(name: &str) -> Result<Self, MemoryError> {
+		let memzone = Memzone::lookup(name)?;
+		let ptr = place(&memzone)?;
+		Ok(Self { memzone, ptr })
+	}
+
+	fn reserve(name: &str, socket: i32) -> Result<Self, MemoryError> {
+		// a zero length would reserve the largest free area
+		let len = mem::size_of::<T>().max(1);
+		let align = mem::align_of::<T>().max(dpdk_sys::RTE_CACHE_LINE_SIZE as usize);
+		let memzone = Memzone::reserve_aligned(name, len, socket, align as u32)?;
+		let ptr = place(&memzone)?;
+		Ok(Self { memzone, ptr })
+	}
+
+	/// Whether this handle created the value and frees it when dropped
+	#[inline]
+	pub fn is_owner(&self) -> bool {
+		self.memzone.is_owner()
+	}
+
+	/// The memzone holding the value
+	#[inline]
+	pub fn memzone(&self) -> &Memzone {
+		&self.memzone
+	}
+
+	/// Mutable access to the value, e.g. to fill in plain fields before publishing it
+	///
+	/// # Safety
+	///
+	/// No other handle to the value, in this process or another, may use it while the
+	/// reference lives.
+	#[inline]
+	pub unsafe fn get_mut(&mut self) -> &mut T {
+		self.ptr.as_mut()
+	}
+}
+
+impl<T: Shareable> Deref for SharedBox<T> {
+	type Target = T;
+
+	#[inline]
+	fn deref(&self) -> &T {
+		unsafe { self.ptr.as_ref() }
+	}
+}
+
+unsafe impl<T: Shareable> Send for SharedBox<T> {}
+unsafe impl<T: Shareable> Sync for SharedBox<T> {}
+
+impl<T: Shareable + fmt::Debug> fmt::Debug for SharedBox<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("SharedBox")
+			.field("name", &self.memzone.name())
+			.field("owner", &self.is_owner())
+			.field("value", &**self)
+			.finish()
+	}
+}
+
+/// A read-only handle to a `T` another process placed in shared memory
+pub struct SharedRef<T: Shareable> {
+	memzone: Memzone,
+	ptr: NonNull<T>,
+}
+
+impl<T: Shareable> SharedRef<T> {
+	/// Attach to the `T` placed under `name`
+	pub fn attach(name: &str) -> Result<Self, MemoryError> {
+		let memzone = Memzone::lookup(name)?;
+		let ptr = place(&memzone)?;
+		Ok(Self { memzone, ptr })
+	}
+
+	/// The memzone holding the value
+	#[inline]
+	pub fn memzone(&self) -> &Memzone {
+		&self.memzone
+	}
+}
+
+impl<T: Shareable> Deref for SharedRef<T> {
+	type Target = T;
+
+	#[inline]
+	fn deref(&self) -> &T {
+		unsafe { self.ptr.as_ref() }
+	}
+}
+
+unsafe impl<T: Shareable> Send for SharedRef<T> {}
+unsafe impl<T: Shareable> Sync for SharedRef<T> {}
+
+impl<T: Shareable + fmt::Debug> fmt::Debug for SharedRef<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("SharedRef")
+			.field("name", &self.memzone.name())
+			.field("value", &**self)
+			.finish()
+	}
+}
@@ -0,0 +1,141 @@
+//! Port statistics
+//!
+//! `Port::stats` takes a snapshot of the basic ethdev counters together with the
+//! extended statistics (xstats) of the PMD. Every PMD reports the generic xstats
+//! (`rx_good_packets`, `rx_missed_errors`, ...), so vdevs such as `net_ring` and
+//! `net_pcap` are covered as well as physical NICs.
+
+use std::{ffi::CStr, fmt, os::raw, ptr};
+
+use super::{Port, PortError};
+
+/// A named extended statistic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XStat {
+	pub name: String,
+	pub value: u64,
+}
+
+/// Counters of a port at one point in time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortStats {
+	pub port_id: u16,
+	/// Packets received successfully
+	pub ipackets: u64,
+	/// Packets sent successfully
+	pub opackets: u64,
+	pub ibytes: u64,
+	pub obytes: u64,
+	/// Packets dropped by the NIC because no rx descriptor was free
+	pub imissed: u64,
+	/// Erroneous received packets
+	pub ierrors: u64,
+	/// Failed transmissions
+	pub oerrors: u64,
+	/// Receive failures because the mempool ran out of mbufs
+	pub rx_nombuf: u64,
+	/// Extended statistics, in the order the PMD reports them
+	pub xstats: Vec<XStat>,
+}
+
+impl PortStats {
+	/// Packets the port dropped before our rx path saw them
+	pub fn rx_drops(&self) -> u64 {
+		self.imissed + self.rx_nombuf
+	}
+
+	/// Value of the extended statistic `name`
+	pub fn xstat(&self, name: &str) -> Option<u64> {
+		self.xstats.iter().find(|x| x.name == name).map(|x| x.value)
+	}
+}
+
+impl fmt::Display for PortStats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"port {}: rx {} pkts ({} B), tx {} pkts ({} B), missed {}, rx errors {}, tx errors {}, rx no mbuf {}",
+			self.port_id,
+			self.ipackets,
+			self.ibytes,
+			self.opackets,
+			self.obytes,
+			self.imissed,
+			self.ierrors,
+			self.oerrors,
+			self.rx_nombuf
+		)
+	}
+}
+
+impl Port {
+	/// Take a snapshot of the basic and extended statistics of the port
+	pub fn stats(&self) -> Result<PortStats, PortError> {
+		let mut stats = dpdk_sys::rte_eth_stats::default();
+		if unsafe { dpdk_sys::rte_eth_stats_get(self.id, &mut stats) } != 0 {
+			return Err(PortError::new());
+		}
+		Ok(PortStats {
+			port_id: self.id,
+			ipackets: stats.ipackets,
+			opackets: stats.opackets,
+			ibytes: stats.ibytes,
+			obytes: stats.obytes,
+			imissed: stats.imissed,
+			ierrors: stats.ierrors,
+			oerrors: stats.oerrors,
+			rx_nombuf: stats.rx_nombuf,
+			xstats: self.xstats()?,
+		})
+	}
+
+	/// Get the extended statistics of the port
+	pub fn xstats(&self) -> Result<Vec<XStat>, PortError> {
+		// the number of xstats is only known by asking for it
+		let count = unsafe { dpdk_sys::rte_eth_xstats_get_names(self.id, ptr::null_mut(), 0) };
+		if count < 0 {
+			return Err(PortError::new());
+		}
+		let count = count as usize;
+
+		let mut names = vec![dpdk_sys::rte_eth_xstat_name { name: [0; 64] }; count];
+		let mut values = vec![dpdk_sys::rte_eth_xstat::default(); count];
+		let ret = unsafe {
+			dpdk_sys::rte_eth_xstats_get_names(self.id, names.as_mut_ptr(), count as raw::c_uint)
+		};
+		if ret < 0 || ret as usize > count {
+			return Err(PortError::new());
+		}
+		let ret = unsafe {
+			dpdk_sys::rte_eth_xstats_get(self.id, values.as_mut_ptr(), count as raw::c_uint)
+		};
+		if ret < 0 || ret as usize > count {
+			return Err(PortError::new());
+		}
+		values.truncate(ret as usize);
+
+		Ok(values
+			.iter()
+			.filter_map(|xstat| {
+				let name = names.get(xstat.id as usize)?;
+				let name = unsafe { CStr::from_ptr(name.name.as_ptr()) };
+				Some(XStat {
+					name: name.to_string_lossy().into_owned(),
+					value: xstat.value,
+				})
+			})
+			.collect())
+	}
+
+	/// Reset the basic and extended statistics of the port to zero
+	pub fn reset_stats(&self) -> Result<(), PortError> {
+		// PMDs with their own xstats do not always clear the basic counters with them
+		if unsafe { dpdk_sys::rte_eth_stats_reset(self.id) } != 0 {
+			return Err(PortError::new());
+		}
+		match unsafe { dpdk_sys::rte_eth_xstats_reset(self.id) } {
+			0 => Ok(()),
+			_ => Err(PortError::new()),
+		}
+	}
+}
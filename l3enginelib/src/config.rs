@@ -0,0 +1,814 @@
+//! Declarative configuration shared by the engine and the packetiser
+//!
+//! Both binaries load the same TOML file at startup. The file describes the EAL
+//! arguments, the lcores each process runs on, the ports and their queues, mempool and
+//! ring sizing, burst sizes and the control endpoints used to synchronise the two processes.
+//!
+//! Every section has defaults that match the values the engine used to hardcode,
+//! so a file only needs to list what differs from them.
+//! `EngineConfig::validate` is run on load and reports the first offending field.
+
+use crate::apis::{EalArgs, FlowRule, LcoreQueues, MempoolConfig, PacketMeta, ProcType, RxOffloads, TxOffloads};
+use serde::Deserialize;
+use std::{collections::BTreeSet, fs, io, path::Path};
+use thiserror::Error;
+
+/// Config file used when no path is passed on the command line
+pub const DEFAULT_CONFIG_PATH: &str = "engine.toml";
+
+/// Sent by the packetiser on `control.packetiser_connect` once it is ready
+pub const READY_MSG: &str = "Hello";
+/// The engine's reply to `READY_MSG`, sent when the engine shuts down
+pub const SHUTDOWN_MSG: &str = "shutdown";
+/// Sent by the packetiser once it no longer touches the shared rings and mempool
+pub const STOPPED_MSG: &str = "stopped";
+
+/// Errors raised while loading or validating the configuration
+#[derive(Error, Debug)]
+pub enum ConfigError {
+	#[error("could not read config file {}: {}", _0, _1)]
+	Io(String, io::Error),
+	#[error("could not parse config file: {}", _0)]
+	Parse(#[from] toml::de::Error),
+	#[error("invalid value for `{}`: {}", _0, _1)]
+	Invalid(String, String),
+}
+
+impl ConfigError {
+	fn invalid<F: Into<String>, R: Into<String>>(field: F, reason: R) -> Self {
+		ConfigError::Invalid(field.into(), reason.into())
+	}
+}
+
+/// Arguments passed to the EAL by both processes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EalConfig {
+	/// Number of memory channels (`-n`)
+	pub memory_channels: u32,
+	/// Base virtual address for the primary (`--base-virtaddr`)
+	pub base_virtaddr: Option<String>,
+	/// Any further EAL arguments, passed through verbatim
+	pub extra_args: Vec<String>,
+}
+
+impl Default for EalConfig {
+	fn default() -> Self {
+		Self {
+			memory_channels: 4,
+			base_virtaddr: Some(String::from("0x7f000000000")),
+			extra_args: Vec::new(),
+		}
+	}
+}
+
+/// Settings for the primary engine process
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineSection {
+	/// Lcores used by the engine, in EAL list notation (e.g. `0-1` or `0,2,4-5`);
+	/// see `LcoreRoles` for the work each of them does
+	pub lcores: String,
+	/// Maximum number of packets received from a port queue at once
+	pub rx_burst: u16,
+	/// Maximum number of packets sent to a port queue at once
+	pub tx_burst: u16,
+	/// How long to wait for each port's link to come up at startup, in milliseconds; 0 skips the wait
+	pub link_timeout_ms: u64,
+	/// How long to wait for the packetiser to stop on shutdown, in milliseconds
+	pub shutdown_timeout_ms: u64,
+}
+
+impl Default for EngineSection {
+	fn default() -> Self {
+		Self {
+			lcores: String::from("0-1"),
+			rx_burst: 32,
+			tx_burst: 32,
+			link_timeout_ms: 9000,
+			shutdown_timeout_ms: 2000,
+		}
+	}
+}
+
+/// The work each engine lcore does, derived from `engine.lcores`
+///
+/// The lowest lcore is the main lcore. Without workers it runs both paths;
+/// with one worker the worker receives and the main lcore sends; with more
+/// the last worker sends, every other worker receives and the main lcore only waits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LcoreRoles {
+	/// The lcore `main` runs on
+	pub main: u32,
+	/// Lcores polling the rx queues; each polls its own queue of every port
+	pub rx: Vec<u32>,
+	/// The lcore sending on every port
+	pub tx: u32,
+}
+
+impl LcoreRoles {
+	/// Split `lcores`, sorted as `parse_lcores` returns them, into their roles
+	pub fn new(lcores: &[u32]) -> Self {
+		let main = lcores.first().copied().unwrap_or_default();
+		let workers = lcores.get(1..).unwrap_or_default();
+		let (rx, tx) = match workers {
+			[] => (vec![main], main),
+			[worker] => (vec![*worker], main),
+			[rx @ .., tx] => (rx.to_vec(), *tx),
+		};
+		Self { main, rx, tx }
+	}
+}
+
+/// Settings for the packetiser secondary process
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PacketiserSection {
+	/// Lcores used by the packetiser, in EAL list notation
+	pub lcores: String,
+	/// Client id of the channel between the engine and the packetiser
+	pub client_id: u16,
+	/// Maximum number of packets moved between the packetiser and the engine at once
+	pub burst: usize,
+}
+
+impl Default for PacketiserSection {
+	fn default() -> Self {
+		Self {
+			lcores: String::from("2-3"),
+			client_id: 1,
+			burst: 512,
+		}
+	}
+}
+
+/// Sizing of the shared packet mempool
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MempoolSection {
+	/// Name the secondaries use to look the mempool up
+	pub name: String,
+	/// Number of mbufs in the pool
+	pub capacity: u32,
+	/// Size of the per-lcore cache
+	pub cache_size: u32,
+	/// Bytes of packet data per mbuf, headroom excluded; frames of ports with a
+	/// larger MTU are received as chains of mbufs
+	pub data_size: u32,
+	/// Bytes of application private area per mbuf; a multiple of 8. Packets carry a
+	/// `PacketMeta` there if it holds one; 0 leaves them without
+	pub priv_size: u16,
+	/// NUMA socket of the pool; the engine's main lcore's socket if unset
+	pub socket: Option<i32>,
+	/// Mempool ops backend (e.g. `ring_mp_mc` or `stack`); the platform default if unset
+	pub ops: Option<String>,
+	/// Create a pool named `<name>_<socket>` on every NUMA socket instead of a single pool;
+	/// each port then receives into the pool on its own socket
+	pub per_socket: bool,
+	/// Percentage of the pool left available below which an alarm is logged; 0 disables the alarm
+	pub low_water_pct: u8,
+	/// How often the engine checks the pools against the low-water mark
+	pub check_interval_ms: u64,
+}
+
+impl MempoolSection {
+	/// Layout of the pools described by the section
+	pub fn pool_config(&self) -> MempoolConfig {
+		MempoolConfig {
+			capacity: self.capacity,
+			cache_size: self.cache_size,
+			data_size: self.data_size,
+			priv_size: self.priv_size,
+			socket: self.socket,
+			ops: self.ops.clone(),
+		}
+	}
+}
+
+impl Default for MempoolSection {
+	fn default() -> Self {
+		Self {
+			name: String::from("GLOBAL_MEMPOOL"),
+			capacity: 32767,
+			cache_size: 512,
+			data_size: 2048,
+			priv_size: PacketMeta::PRIV_SIZE,
+			socket: None,
+			ops: None,
+			per_socket: false,
+			low_water_pct: 10,
+			check_interval_ms: 1000,
+		}
+	}
+}
+
+/// Sizing of the rings between the engine and its clients
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RingSection {
+	/// Number of slots in every client ring; must be a power of two
+	pub size: u32,
+}
+
+impl Default for RingSection {
+	fn default() -> Self {
+		Self { size: 512 }
+	}
+}
+
+/// Endpoints used on the control plane
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlSection {
+	/// Endpoint the engine binds to wait for the packetiser
+	pub packetiser_bind: String,
+	/// Endpoint the packetiser connects to once it is ready
+	pub packetiser_connect: String,
+	/// Endpoint the engine binds to take port hotplug commands on; empty disables hotplug
+	pub hotplug_bind: String,
+}
+
+impl Default for ControlSection {
+	fn default() -> Self {
+		Self {
+			packetiser_bind: String::from("tcp://*:5555"),
+			packetiser_connect: String::from("tcp://localhost:5555"),
+			hotplug_bind: String::from("tcp://127.0.0.1:5556"),
+		}
+	}
+}
+
+/// A single ethernet port driven by the engine
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortConfig {
+	/// Device name, only used for logging
+	pub name: String,
+	/// DPDK port id
+	pub id: u16,
+	/// Number of RX queues; 0 means as many as `queues` uses, or one per rx lcore without `queues`
+	#[serde(default)]
+	pub rx_queues: u16,
+	/// Number of TX queues; 0 means as many as `queues` uses, or 1 without `queues`
+	#[serde(default)]
+	pub tx_queues: u16,
+	/// Number of descriptors per RX queue
+	#[serde(default = "PortConfig::default_desc")]
+	pub rx_desc: u16,
+	/// Number of descriptors per TX queue
+	#[serde(default = "PortConfig::default_desc")]
+	pub tx_desc: u16,
+	/// MTU in bytes, L2 header excluded; above 1500 the port receives jumbo frames
+	#[serde(default = "PortConfig::default_mtu")]
+	pub mtu: u16,
+	/// Queues owned by each engine lcore (see `LcoreRoles`); rx queues may only go to
+	/// rx lcores and tx queues to the tx lcore. When empty, rx queue `i` is polled by
+	/// the `i`th rx lcore and the tx lcore uses tx queue 0
+	#[serde(default)]
+	pub queues: Vec<LcoreQueues>,
+	/// Rx offloads to enable, by name (e.g. `ipv4_cksum`); those the PMD lacks are
+	/// done in software when they are checksums and otherwise only reported
+	#[serde(default = "RxOffloads::checksum")]
+	pub rx_offloads: RxOffloads,
+	/// Tx offloads to enable, by name; handled like `rx_offloads`.
+	/// `mbuf_fast_free` is only safe when every packet sent on the port comes from one
+	/// mempool and is never cloned or otherwise referenced more than once
+	#[serde(default = "TxOffloads::checksum")]
+	pub tx_offloads: TxOffloads,
+	/// Flow steering rules installed when the port starts
+	#[serde(default)]
+	pub flows: Vec<FlowRule>,
+}
+
+impl PortConfig {
+	/// A port with every setting at its default
+	pub fn new(name: &str, id: u16) -> Self {
+		Self {
+			name: String::from(name),
+			id,
+			rx_queues: 0,
+			tx_queues: 0,
+			rx_desc: Self::default_desc(),
+			tx_desc: Self::default_desc(),
+			mtu: Self::default_mtu(),
+			queues: Vec::new(),
+			rx_offloads: RxOffloads::checksum(),
+			tx_offloads: TxOffloads::checksum(),
+			flows: Vec::new(),
+		}
+	}
+
+	/// Map the queues to the lcores in `roles` if the port has no mapping, then give
+	/// it as many queues as its mapping uses unless it has an explicit queue count
+	pub fn resolve_queues(&mut self, roles: &LcoreRoles) {
+		if self.queues.is_empty() {
+			let rx_queues = match self.rx_queues {
+				0 => roles.rx.len() as u16,
+				n => n,
+			};
+			self.queues = LcoreQueues::spread(&roles.rx, rx_queues, roles.tx);
+		}
+		let rx_used = self.queues.iter().filter_map(|entry| entry.rx).max();
+		let tx_used = self.queues.iter().filter_map(|entry| entry.tx).max();
+		if self.rx_queues == 0 {
+			self.rx_queues = rx_used.map_or(1, |q| q + 1);
+		}
+		if self.tx_queues == 0 {
+			self.tx_queues = tx_used.map_or(1, |q| q + 1);
+		}
+	}
+
+	fn default_desc() -> u16 {
+		512
+	}
+
+	fn default_mtu() -> u16 {
+		dpdk_sys::RTE_ETHER_MTU as u16
+	}
+}
+
+/// The whole configuration file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EngineConfig {
+	#[serde(default)]
+	pub eal: EalConfig,
+	#[serde(default)]
+	pub engine: EngineSection,
+	#[serde(default)]
+	pub packetiser: PacketiserSection,
+	#[serde(default)]
+	pub mempool: MempoolSection,
+	#[serde(default)]
+	pub rings: RingSection,
+	#[serde(default)]
+	pub control: ControlSection,
+	pub ports: Vec<PortConfig>,
+}
+
+impl EngineConfig {
+	/// Maximum size of a mempool per-lcore cache (`RTE_MEMPOOL_CACHE_MAX_SIZE`)
+	const MEMPOOL_CACHE_MAX: u32 = 512;
+	/// Smallest MTU an IPv4 host must support
+	const MIN_MTU: u16 = 68;
+
+	/// Load and validate the configuration at `path`
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+		let path = path.as_ref();
+		let text = fs::read_to_string(path)
+			.map_err(|e| ConfigError::Io(path.display().to_string(), e))?;
+		Self::from_toml(&text)
+	}
+
+	/// Parse and validate a configuration held in memory
+	pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+		let mut conf: Self = toml::from_str(text)?;
+		conf.resolve_queues();
+		conf.validate()?;
+		Ok(conf)
+	}
+
+	/// Check the configuration for values DPDK would reject later on
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		let engine_lcores = parse_lcores(&self.engine.lcores)
+			.map_err(|e| ConfigError::invalid("engine.lcores", e))?;
+		let packetiser_lcores = parse_lcores(&self.packetiser.lcores)
+			.map_err(|e| ConfigError::invalid("packetiser.lcores", e))?;
+		if let Some(lcore) = engine_lcores
+			.iter()
+			.find(|lcore| packetiser_lcores.contains(lcore))
+		{
+			return Err(ConfigError::invalid(
+				"packetiser.lcores",
+				format!("lcore {} is already used by the engine", lcore),
+			));
+		}
+
+		if self.eal.memory_channels == 0 {
+			return Err(ConfigError::invalid("eal.memory_channels", "must be at least 1"));
+		}
+		if let Some(addr) = &self.eal.base_virtaddr {
+			if parse_hex(addr).is_none() {
+				return Err(ConfigError::invalid(
+					"eal.base_virtaddr",
+					format!("`{}` is not a hexadecimal address", addr),
+				));
+			}
+		}
+
+		if self.engine.rx_burst == 0 {
+			return Err(ConfigError::invalid("engine.rx_burst", "must be at least 1"));
+		}
+		if self.engine.tx_burst == 0 {
+			return Err(ConfigError::invalid("engine.tx_burst", "must be at least 1"));
+		}
+		if self.packetiser.burst == 0 {
+			return Err(ConfigError::invalid("packetiser.burst", "must be at least 1"));
+		}
+
+		if self.mempool.name.is_empty() {
+			return Err(ConfigError::invalid("mempool.name", "must not be empty"));
+		}
+		if self.mempool.capacity == 0 {
+			return Err(ConfigError::invalid("mempool.capacity", "must be at least 1"));
+		}
+		if self.mempool.cache_size > Self::MEMPOOL_CACHE_MAX
+			|| self.mempool.cache_size as f64 * 1.5 > self.mempool.capacity as f64
+		{
+			return Err(ConfigError::invalid(
+				"mempool.cache_size",
+				format!(
+					"must be at most {} and at most capacity / 1.5",
+					Self::MEMPOOL_CACHE_MAX
+				),
+			));
+		}
+
+		let max_data_size = u16::MAX as u32 - dpdk_sys::RTE_PKTMBUF_HEADROOM;
+		if self.mempool.data_size == 0 || self.mempool.data_size > max_data_size {
+			return Err(ConfigError::invalid(
+				"mempool.data_size",
+				format!("must be between 1 and {}", max_data_size),
+			));
+		}
+		if self.mempool.priv_size as u32 & (dpdk_sys::RTE_MBUF_PRIV_ALIGN - 1) != 0 {
+			return Err(ConfigError::invalid(
+				"mempool.priv_size",
+				format!("must be a multiple of {}", dpdk_sys::RTE_MBUF_PRIV_ALIGN),
+			));
+		}
+		if self.mempool.priv_size != 0 && self.mempool.priv_size < PacketMeta::PRIV_SIZE {
+			return Err(ConfigError::invalid(
+				"mempool.priv_size",
+				format!("must be 0 or at least {} to hold the packet metadata", PacketMeta::PRIV_SIZE),
+			));
+		}
+		if self.mempool.per_socket && self.mempool.socket.is_some() {
+			return Err(ConfigError::invalid(
+				"mempool.socket",
+				"cannot be set together with mempool.per_socket",
+			));
+		}
+		if self.mempool.ops.as_deref() == Some("") {
+			return Err(ConfigError::invalid("mempool.ops", "must not be empty"));
+		}
+		if self.mempool.low_water_pct > 100 {
+			return Err(ConfigError::invalid("mempool.low_water_pct", "must be at most 100"));
+		}
+		if self.mempool.low_water_pct != 0 && self.mempool.check_interval_ms == 0 {
+			return Err(ConfigError::invalid("mempool.check_interval_ms", "must be at least 1"));
+		}
+
+		if !self.rings.size.is_power_of_two() {
+			return Err(ConfigError::invalid("rings.size", "must be a power of two"));
+		}
+		if (self.packetiser.burst as u32) > self.rings.size {
+			return Err(ConfigError::invalid(
+				"packetiser.burst",
+				format!("must not exceed rings.size ({})", self.rings.size),
+			));
+		}
+
+		if self.control.packetiser_bind.is_empty() {
+			return Err(ConfigError::invalid("control.packetiser_bind", "must not be empty"));
+		}
+		if self.control.packetiser_connect.is_empty() {
+			return Err(ConfigError::invalid("control.packetiser_connect", "must not be empty"));
+		}
+		if self.control.hotplug_bind == self.control.packetiser_bind {
+			return Err(ConfigError::invalid(
+				"control.hotplug_bind",
+				"must differ from control.packetiser_bind",
+			));
+		}
+
+		if self.ports.is_empty() {
+			return Err(ConfigError::invalid("ports", "at least one port is required"));
+		}
+		let mut ids = BTreeSet::new();
+		for (i, port) in self.ports.iter().enumerate() {
+			if !ids.insert(port.id) {
+				return Err(ConfigError::invalid(
+					format!("ports[{}].id", i),
+					format!("port {} is listed twice", port.id),
+				));
+			}
+			self.validate_port(&format!("ports[{}]", i), port)?;
+		}
+		if let Some(lcore) = self.lcore_roles().rx.into_iter().find(|&lcore| {
+			!self
+				.ports
+				.iter()
+				.flat_map(|port| &port.queues)
+				.any(|entry| entry.lcore == lcore && entry.rx.is_some())
+		}) {
+			return Err(ConfigError::invalid(
+				"engine.lcores",
+				format!("lcore {} receives but polls no rx queue of any port", lcore),
+			));
+		}
+
+		Ok(())
+	}
+
+	/// Check a single port against the rest of the configuration;
+	/// errors name its fields below `field`, e.g. `ports[0]`
+	pub fn validate_port(&self, field: &str, port: &PortConfig) -> Result<(), ConfigError> {
+		if !port.rx_desc.is_power_of_two() || port.rx_desc < self.engine.rx_burst {
+			return Err(ConfigError::invalid(
+				format!("{}.rx_desc", field),
+				"must be a power of two and at least engine.rx_burst",
+			));
+		}
+		if !port.tx_desc.is_power_of_two() || port.tx_desc < self.engine.tx_burst {
+			return Err(ConfigError::invalid(
+				format!("{}.tx_desc", field),
+				"must be a power of two and at least engine.tx_burst",
+			));
+		}
+		let max_mtu = dpdk_sys::RTE_ETHER_MAX_JUMBO_FRAME_LEN
+			- dpdk_sys::RTE_ETHER_HDR_LEN
+			- dpdk_sys::RTE_ETHER_CRC_LEN;
+		if port.mtu < Self::MIN_MTU || port.mtu as u32 > max_mtu {
+			return Err(ConfigError::invalid(
+				format!("{}.mtu", field),
+				format!("must be between {} and {}", Self::MIN_MTU, max_mtu),
+			));
+		}
+		for (j, rule) in port.flows.iter().enumerate() {
+			rule.validate(port.rx_queues)
+				.map_err(|e| ConfigError::invalid(format!("{}.flows[{}]", field, j), e))?;
+		}
+		if port.queues.is_empty() {
+			return Err(ConfigError::invalid(
+				format!("{}.queues", field),
+				"no lcore mapping, `PortConfig::resolve_queues` makes the default one",
+			));
+		}
+		let engine_lcores = self.engine_lcores();
+		if let Some(entry) = port
+			.queues
+			.iter()
+			.find(|entry| !engine_lcores.contains(&entry.lcore))
+		{
+			return Err(ConfigError::invalid(
+				format!("{}.queues", field),
+				format!("lcore {} is not an engine lcore", entry.lcore),
+			));
+		}
+		LcoreQueues::validate(&port.queues, port.rx_queues, port.tx_queues)
+			.map_err(|e| ConfigError::invalid(format!("{}.queues", field), e))?;
+		let roles = self.lcore_roles();
+		LcoreQueues::check_lcores(&port.queues, &roles.rx, roles.tx)
+			.map_err(|e| ConfigError::invalid(format!("{}.queues", field), e))?;
+		Ok(())
+	}
+
+	/// Lcores the engine runs on
+	pub fn engine_lcores(&self) -> Vec<u32> {
+		// validated on load
+		parse_lcores(&self.engine.lcores).unwrap_or_default()
+	}
+
+	/// The work each engine lcore does
+	pub fn lcore_roles(&self) -> LcoreRoles {
+		LcoreRoles::new(&self.engine_lcores())
+	}
+
+	/// Lcores the packetiser runs on
+	pub fn packetiser_lcores(&self) -> Vec<u32> {
+		// validated on load
+		parse_lcores(&self.packetiser.lcores).unwrap_or_default()
+	}
+
+	/// Map the queues of every port without a mapping to the engine lcores and
+	/// give every port without an explicit queue count as many queues as its mapping uses
+	fn resolve_queues(&mut self) {
+		// a bad lcore list is reported by `validate`
+		if parse_lcores(&self.engine.lcores).is_err() {
+			return;
+		}
+		let roles = self.lcore_roles();
+		for port in &mut self.ports {
+			port.resolve_queues(&roles);
+		}
+	}
+
+	/// EAL arguments for the primary engine process
+	pub fn engine_eal_args(&self) -> EalArgs {
+		let mut args = EalArgs::new("l3enginebin")
+			.lcores(&self.engine_lcores())
+			.memory_channels(self.eal.memory_channels)
+			.proc_type(ProcType::Primary);
+		if let Some(addr) = self.eal.base_virtaddr.as_deref().and_then(parse_hex) {
+			args = args.base_virtaddr(addr);
+		}
+		self.eal
+			.extra_args
+			.iter()
+			.fold(args, |args, arg| args.arg(arg))
+	}
+
+	/// EAL arguments for the packetiser secondary process
+	pub fn packetiser_eal_args(&self) -> EalArgs {
+		let args = EalArgs::new("l3enginepacketiser")
+			.lcores(&self.packetiser_lcores())
+			.memory_channels(self.eal.memory_channels)
+			.proc_type(ProcType::Secondary);
+		self.eal
+			.extra_args
+			.iter()
+			.fold(args, |args, arg| args.arg(arg))
+	}
+}
+
+/// Parse a hexadecimal number with an optional `0x` prefix
+fn parse_hex(val: &str) -> Option<u64> {
+	let digits = val.trim_start_matches("0x").trim_start_matches("0X");
+	u64::from_str_radix(digits, 16).ok()
+}
+
+/// Parse an EAL style lcore list such as `0-1` or `0,2,4-5`
+pub fn parse_lcores(list: &str) -> Result<Vec<u32>, String> {
+	let mut lcores = BTreeSet::new();
+	for part in list.split(',').map(str::trim) {
+		let mut bounds = part.splitn(2, '-').map(str::trim);
+		let lo = bounds.next().unwrap_or_default();
+		let lo = lo
+			.parse::<u32>()
+			.map_err(|_| format!("`{}` is not an lcore id", lo))?;
+		let hi = match bounds.next() {
+			Some(hi) => hi
+				.parse::<u32>()
+				.map_err(|_| format!("`{}` is not an lcore id", hi))?,
+			None => lo,
+		};
+		if hi < lo {
+			return Err(format!("range `{}` is reversed", part));
+		}
+		if hi >= dpdk_sys::RTE_MAX_LCORE {
+			return Err(format!(
+				"lcore {} exceeds RTE_MAX_LCORE ({})",
+				hi,
+				dpdk_sys::RTE_MAX_LCORE
+			));
+		}
+		lcores.extend(lo..=hi);
+	}
+	Ok(lcores.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A minimal valid config with `extra` appended
+	fn load(extra: &str) -> Result<EngineConfig, ConfigError> {
+		EngineConfig::from_toml(&format!("[[ports]]\nname = \"port0\"\nid = 0\n{}", extra))
+	}
+
+	/// The field `extra` makes the config fail on
+	fn invalid_field(extra: &str) -> String {
+		match load(extra) {
+			Err(ConfigError::Invalid(field, _)) => field,
+			Err(e) => panic!("{:?} failed otherwise: {}", extra, e),
+			Ok(_) => panic!("{:?} was accepted", extra),
+		}
+	}
+
+	#[test]
+	fn parses_lcore_lists() {
+		assert_eq!(parse_lcores("0-1"), Ok(vec![0, 1]));
+		assert_eq!(parse_lcores("4-5, 0,2"), Ok(vec![0, 2, 4, 5]));
+		assert_eq!(parse_lcores("1,1-2"), Ok(vec![1, 2]));
+		assert!(parse_lcores("").is_err());
+		assert!(parse_lcores("a").is_err());
+		assert!(parse_lcores("3-1").is_err());
+		assert!(parse_lcores(&dpdk_sys::RTE_MAX_LCORE.to_string()).is_err());
+	}
+
+	#[test]
+	fn defaults_are_valid() {
+		let conf = load("").unwrap();
+		assert_eq!(conf.engine_lcores(), vec![0, 1]);
+		assert_eq!(conf.packetiser_lcores(), vec![2, 3]);
+		assert_eq!(conf.ports[0].rx_queues, 1);
+		assert_eq!(conf.ports[0].tx_queues, 1);
+	}
+
+	#[test]
+	fn splits_lcores_into_roles() {
+		let roles = |lcores: &[u32]| {
+			let roles = LcoreRoles::new(lcores);
+			(roles.main, roles.rx, roles.tx)
+		};
+		assert_eq!(roles(&[0]), (0, vec![0], 0));
+		assert_eq!(roles(&[0, 1]), (0, vec![1], 0));
+		assert_eq!(roles(&[0, 1, 2]), (0, vec![1], 2));
+		assert_eq!(roles(&[0, 2, 4, 6]), (0, vec![2, 4], 6));
+	}
+
+	#[test]
+	fn names_the_bad_field() {
+		let cases = [
+			("[engine]\nlcores = \"1-0\"", "engine.lcores"),
+			("[engine]\nrx_burst = 0", "engine.rx_burst"),
+			("[packetiser]\nlcores = \"1-2\"", "packetiser.lcores"),
+			("[packetiser]\nburst = 1024", "packetiser.burst"),
+			("[eal]\nmemory_channels = 0", "eal.memory_channels"),
+			("[eal]\nbase_virtaddr = \"0xzz\"", "eal.base_virtaddr"),
+			("[mempool]\ncache_size = 1024", "mempool.cache_size"),
+			("[mempool]\npriv_size = 12", "mempool.priv_size"),
+			("[mempool]\nper_socket = true\nsocket = 0", "mempool.socket"),
+			("[rings]\nsize = 1000", "rings.size"),
+			("[control]\nhotplug_bind = \"tcp://*:5555\"", "control.hotplug_bind"),
+			("rx_desc = 500", "ports[0].rx_desc"),
+			("mtu = 60", "ports[0].mtu"),
+			("[[ports]]\nname = \"port1\"\nid = 0", "ports[1].id"),
+		];
+		for (extra, field) in cases.iter() {
+			assert_eq!(invalid_field(extra), *field, "for {:?}", extra);
+		}
+	}
+
+	#[test]
+	fn requires_a_port() {
+		match EngineConfig::from_toml("ports = []") {
+			Err(ConfigError::Invalid(field, _)) => assert_eq!(field, "ports"),
+			other => panic!("no ports accepted: {:?}", other.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn reports_parse_errors() {
+		assert!(matches!(load("mtu = \"big\""), Err(ConfigError::Parse(_))));
+		assert!(matches!(load("no_such_field = 1"), Err(ConfigError::Parse(_))));
+	}
+
+	#[test]
+	fn gives_each_rx_lcore_a_queue_by_default() {
+		let conf = load("[engine]\nlcores = \"0-3\"\n[packetiser]\nlcores = \"4-5\"").unwrap();
+		let port = &conf.ports[0];
+		assert_eq!(port.rx_queues, 2);
+		assert_eq!(port.tx_queues, 1);
+		assert_eq!(
+			port.queues,
+			vec![
+				LcoreQueues {
+					lcore: 1,
+					rx: Some(0),
+					tx: None,
+				},
+				LcoreQueues {
+					lcore: 2,
+					rx: Some(1),
+					tx: None,
+				},
+				LcoreQueues {
+					lcore: 3,
+					rx: None,
+					tx: Some(0),
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn sizes_the_queues_after_the_map() {
+		let conf = load(
+			"queues = [{ lcore = 1, rx = 0 }, { lcore = 2, rx = 1 }, { lcore = 3, tx = 2 }]\n\
+			 [engine]\nlcores = \"0-3\"\n[packetiser]\nlcores = \"4-5\"",
+		)
+		.unwrap();
+		assert_eq!(conf.ports[0].rx_queues, 2);
+		assert_eq!(conf.ports[0].tx_queues, 3);
+	}
+
+	#[test]
+	fn checks_queue_maps_against_the_lcore_roles() {
+		let cases = [
+			// more rx queues than rx lcores
+			"rx_queues = 2",
+			// rx queue on the tx lcore
+			"queues = [{ lcore = 0, rx = 0, tx = 0 }]",
+			// tx lcore without a tx queue
+			"queues = [{ lcore = 1, rx = 0 }]",
+			// lcore outside engine.lcores
+			"queues = [{ lcore = 5, rx = 0 }, { lcore = 0, tx = 0 }]",
+			// rx queue 1 is polled by nobody
+			"rx_queues = 2\nqueues = [{ lcore = 1, rx = 0 }, { lcore = 0, tx = 0 }]",
+		];
+		for extra in cases.iter() {
+			assert_eq!(invalid_field(extra), "ports[0].queues", "for {:?}", extra);
+		}
+	}
+
+	#[test]
+	fn rejects_rx_lcores_polling_nothing() {
+		let field = invalid_field(
+			"queues = [{ lcore = 1, rx = 0 }, { lcore = 3, tx = 0 }]\n\
+			 [engine]\nlcores = \"0-3\"\n[packetiser]\nlcores = \"4-5\"",
+		);
+		assert_eq!(field, "engine.lcores");
+	}
+}
@@ -0,0 +1,277 @@
+//! This module lets ports be attached, detached and reconfigured while the engine runs
+//!
+//! A control thread takes commands on the `control.hotplug_bind` endpoint (ZMQ REP).
+//! The first line of a command is one of
+//! - `list`: the ports the engine drives and whether they are polled
+//! - `attach <devargs>`: probe a device (e.g. `net_pcap1,iface=eth1`) and poll it
+//! - `detach <port id>`: stop polling a port, close it and remove its device
+//! - `stop <port id>`: stop polling a port and stop it
+//! - `start <port id>`: start a stopped port and poll it again
+//! - `reconfigure <port id>`: stop a port, configure it again and restart it
+//!
+//! `attach` and `reconfigure` read the port settings from the lines after the command,
+//! written like a `[[ports]]` entry of the config file without `name` and `id`;
+//! settings left out take their defaults.
+//! Replies are `ok <port id>`, `ok <count>` followed by one line per port for `list`,
+//! or `error: <reason>`.
+
+use crate::{CONFIG, MEMPOOL};
+use l3enginelib::{
+	apis::{Port, PortError, PortSet, StopSignal},
+	config::PortConfig,
+};
+use std::{collections::BTreeMap, thread};
+
+/// Configure `port` with the shared mempool, start it and install its flow rules
+pub(crate) fn start_port(port: &mut Port, port_conf: &PortConfig) -> Result<(), PortError> {
+	let config = CONFIG.get();
+	port.set_burst(config.engine.rx_burst, config.engine.tx_burst);
+	port.configure(port_conf, MEMPOOL.get())?;
+	port.start()?;
+	for rule in &port_conf.flows {
+		if let Err(e) = port.add_flow(rule.clone()) {
+			port.stop();
+			return Err(e);
+		}
+	}
+	Ok(())
+}
+
+/// A port known to the control thread
+struct Managed {
+	port: Port,
+	conf: PortConfig,
+	// in the polling set
+	polled: bool,
+}
+
+/// State of the hotplug control thread
+pub(crate) struct Hotplug {
+	set: PortSet,
+	ports: BTreeMap<u16, Managed>,
+}
+
+impl Hotplug {
+	/// How long a poll of the socket waits before the stop signal is checked again
+	const POLL_TIMEOUT_MS: i64 = 100;
+
+	/// `ports` are the started ports in `set` along with the configuration they were started with
+	pub(crate) fn new(set: PortSet, ports: Vec<(Port, PortConfig)>) -> Self {
+		Self {
+			set,
+			ports: ports
+				.into_iter()
+				.map(|(port, conf)| {
+					let managed = Managed {
+						port,
+						conf,
+						polled: true,
+					};
+					(managed.port.id, managed)
+				})
+				.collect(),
+		}
+	}
+
+	/// Serve commands on `endpoint` from a new thread until `stop` is signalled
+	pub(crate) fn spawn(self, endpoint: String, stop: StopSignal) -> thread::JoinHandle<()> {
+		thread::Builder::new()
+			.name(String::from("hotplug"))
+			.spawn(move || self.serve(&endpoint, &stop))
+			.expect("could not spawn the hotplug thread")
+	}
+
+	fn serve(mut self, endpoint: &str, stop: &StopSignal) {
+		let context = zmq::Context::new();
+		let socket = match context.socket(zmq::REP) {
+			Ok(socket) => socket,
+			Err(e) => {
+				log::error!("hotplug: cannot create socket: {}", e);
+				return;
+			}
+		};
+		if let Err(e) = socket.bind(endpoint) {
+			log::error!("hotplug: cannot bind to {}: {}", endpoint, e);
+			return;
+		}
+		log::info!("hotplug: taking commands on {}", endpoint);
+
+		while stop.is_running() {
+			match socket.poll(zmq::POLLIN, Self::POLL_TIMEOUT_MS) {
+				Ok(0) => continue,
+				Ok(_) => {}
+				Err(e) => {
+					log::error!("hotplug: poll failed: {}", e);
+					break;
+				}
+			}
+			let reply = match socket.recv_string(0) {
+				Ok(Ok(command)) => self.handle(&command),
+				Ok(Err(_)) => String::from("error: command is not valid utf-8"),
+				Err(e) => {
+					log::error!("hotplug: receive failed: {}", e);
+					break;
+				}
+			};
+			if let Err(e) = socket.send(reply.as_str(), 0) {
+				log::error!("hotplug: reply failed: {}", e);
+				break;
+			}
+		}
+	}
+
+	/// Run a single command and build its reply
+	fn handle(&mut self, command: &str) -> String {
+		let (line, settings) = match command.find('\n') {
+			Some(i) => (&command[..i], &command[i + 1..]),
+			None => (command, ""),
+		};
+		let mut words = line.split_whitespace();
+		let result = match (words.next(), words.next(), words.next()) {
+			(Some("list"), None, None) => Ok(self.list()),
+			(Some("attach"), Some(devargs), None) => self.attach(devargs, settings),
+			(Some(op @ ("detach" | "stop" | "start" | "reconfigure")), Some(id), None) => {
+				match id.parse::<u16>() {
+					Ok(id) if !self.ports.contains_key(&id) => {
+						Err(format!("port {} is not driven by the engine", id))
+					}
+					Ok(id) => match op {
+						"detach" => self.detach(id),
+						"stop" => self.stop(id),
+						"start" => self.start(id),
+						_ => self.reconfigure(id, settings),
+					},
+					Err(_) => Err(format!("`{}` is not a port id", id)),
+				}
+			}
+			_ => Err(format!("unknown command `{}`", line.trim())),
+		};
+		match result {
+			Ok(reply) => format!("ok {}", reply),
+			Err(e) => {
+				log::warn!("hotplug: `{}` failed: {}", line.trim(), e);
+				format!("error: {}", e)
+			}
+		}
+	}
+
+	fn list(&self) -> String {
+		let mut reply = self.ports.len().to_string();
+		for managed in self.ports.values() {
+			reply.push_str(&format!(
+				"\n{} {} {} mtu {}",
+				managed.port.id,
+				managed.port.device,
+				if managed.polled { "running" } else { "stopped" },
+				managed.conf.mtu
+			));
+		}
+		reply
+	}
+
+	fn attach(&mut self, devargs: &str, settings: &str) -> Result<String, String> {
+		let mut port = Port::attach(devargs).map_err(|e| format!("cannot attach {}: {}", devargs, e))?;
+		let id = port.id;
+		let conf = match self.port_conf(&port, settings).and_then(|conf| {
+			start_port(&mut port, &conf).map_err(|e| format!("port {}: {}", id, e))?;
+			Ok(conf)
+		}) {
+			Ok(conf) => conf,
+			Err(e) => {
+				if let Err(detach_err) = port.detach() {
+					log::warn!("hotplug: port {} is left attached: {}", id, detach_err);
+				}
+				return Err(e);
+			}
+		};
+		match port.mac_addr() {
+			Ok(mac) => log::info!("hotplug: attached {} as port {}, mac {}", devargs, id, mac),
+			Err(_) => log::info!("hotplug: attached {} as port {}", devargs, id),
+		}
+		self.set.insert(port.clone());
+		self.ports.insert(
+			id,
+			Managed {
+				port,
+				conf,
+				polled: true,
+			},
+		);
+		Ok(id.to_string())
+	}
+
+	fn detach(&mut self, id: u16) -> Result<String, String> {
+		let managed = self.ports.remove(&id).ok_or("port vanished")?;
+		self.set.remove(id);
+		managed
+			.port
+			.detach()
+			.map_err(|e| format!("port {}: {}", id, e))?;
+		log::info!("hotplug: detached port {}", id);
+		Ok(id.to_string())
+	}
+
+	fn stop(&mut self, id: u16) -> Result<String, String> {
+		let managed = self.ports.get_mut(&id).ok_or("port vanished")?;
+		if !managed.polled {
+			return Err(format!("port {} is already stopped", id));
+		}
+		self.set.remove(id);
+		managed.port.stop();
+		managed.polled = false;
+		log::info!("hotplug: stopped port {}", id);
+		Ok(id.to_string())
+	}
+
+	fn start(&mut self, id: u16) -> Result<String, String> {
+		let managed = self.ports.get_mut(&id).ok_or("port vanished")?;
+		if managed.polled {
+			return Err(format!("port {} is already running", id));
+		}
+		managed
+			.port
+			.start()
+			.map_err(|e| format!("port {}: {}", id, e))?;
+		self.set.insert(managed.port.clone());
+		managed.polled = true;
+		log::info!("hotplug: started port {}", id);
+		Ok(id.to_string())
+	}
+
+	/// Stop the port, configure it with `settings` and start it again
+	///
+	/// The settings are checked before the port is touched; if the port then
+	/// fails to come back up it is left stopped.
+	fn reconfigure(&mut self, id: u16, settings: &str) -> Result<String, String> {
+		let conf = {
+			let managed = self.ports.get(&id).ok_or("port vanished")?;
+			self.port_conf(&managed.port, settings)?
+		};
+		let managed = self.ports.get_mut(&id).ok_or("port vanished")?;
+		if managed.polled {
+			self.set.remove(id);
+			managed.polled = false;
+		}
+		if let Err(e) = managed.port.flush_flows() {
+			log::warn!("hotplug: port {}: cannot flush flow rules: {}", id, e);
+		}
+		managed.port.stop();
+		start_port(&mut managed.port, &conf).map_err(|e| format!("port {} left stopped: {}", id, e))?;
+		managed.conf = conf;
+		self.set.insert(managed.port.clone());
+		managed.polled = true;
+		log::info!("hotplug: reconfigured port {}", id);
+		Ok(id.to_string())
+	}
+
+	/// Parse and validate port settings given with a command
+	fn port_conf(&self, port: &Port, settings: &str) -> Result<PortConfig, String> {
+		let text = format!("name = {:?}\nid = {}\n{}", port.device, port.id, settings);
+		let mut conf: PortConfig =
+			toml::from_str(&text).map_err(|e| format!("bad port settings: {}", e))?;
+		let config = CONFIG.get();
+		conf.resolve_queues(&config.lcore_roles());
+		config.validate_port("port", &conf).map_err(|e| e.to_string())?;
+		Ok(conf)
+	}
+}
@@ -1,9 +1,14 @@
 //! This is the library module that provides structures like
 //! Mbuf, Mempool, Ring, Channel, RingClientMap, Port and associated functions
+//! as well as the configuration shared by the engine and its secondaries
 //!
 //! These structures and functions enable the user to interact with DPDK in a safe manner
 //! and without also having to manually figure out certain interaction semantics
 
+// lets `#[derive(SizeOf)]` name the trait as `::l3enginelib::apis::SizeOf` inside the crate too
+extern crate self as l3enginelib;
+
 pub mod apis;
+pub mod config;
 pub mod net;
 // pub mod server;
@@ -16,48 +16,63 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 
+mod hotplug;
 mod rxbin;
 mod txbin;
 
 use crossbeam_queue::SegQueue;
-use l3enginelib::apis::{eal_cleanup, eal_init, Mbuf, Mempool, Memzone, Port, RingClientMap};
+use l3enginelib::{
+	apis::{
+		current_lcore, eal_cleanup, eal_init, launch, EngineCounters, LcoreHandle, Mbuf, Mempool,
+		MempoolSet, Memzone, Port, PortSet, PortSetReader, RingClientMap, SharedBox, Stage,
+		StopSignal,
+	},
+	config::{EngineConfig, DEFAULT_CONFIG_PATH, SHUTDOWN_MSG},
+};
+use hotplug::{start_port, Hotplug};
 use libc::{IFF_BROADCAST, IFF_ECHO, IFF_PROMISC, IFF_UP};
 use log;
 // use pnet::ipnetwork::{IpNetwork, Ipv4Network};
 use rxbin::{get_external_pkts, get_from_packetiser};
+use signal_hook::{
+	consts::{SIGINT, SIGTERM},
+	iterator::Signals,
+};
 // use smoltcp::wire::Ipv4Address;
 use state::Storage;
 use std::{
 	cell::Cell,
+	env,
 	mem,
 	// net::Ipv4Addr,
+	process,
 	ptr::NonNull,
 	sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError},
 	sync::{
 		atomic::{AtomicBool, Ordering},
 		Arc,
 	},
+	thread,
 	time::Duration,
 	vec,
 };
 use txbin::{send_pkts_out, send_to_packetiser};
 use zmq::Context;
 
-// These three values need to be the same here and in the `l3packetiser` crate
-const PACKETISER_ID: u16 = 1;
-const PACKETISER_BURST: usize = 512;
-const G_MEMPOOL_NAME: &str = "GLOBAL_MEMPOOL";
+/// Configuration the engine was started with
+pub static CONFIG: Storage<EngineConfig> = Storage::new();
 
-const TX_QUEUE_CACHE_SZ: usize = 512;
-const RX_BURST_MAX: usize = 32;
-const TX_BURST_MAX: usize = 32;
-
-const PACKETISER_ZMQ_PORT: &str = "tcp://*:5555";
+/// A central mempool for all cores, or one per NUMA socket with `mempool.per_socket`.
+///
+/// DPDK mempool is based on DPDK's lockless ring and thus thread-safe.
+/// The pools are attached: `main` owns them and frees them in `eal_cleanup`
+pub static MEMPOOL: Storage<MempoolSet> = Storage::new();
 
-/// A central mempool for all cores.
+/// Counters of the ports, pipeline stages and clients
 ///
-/// DPDK mempool is based on DPDK's lockless ring and thus thread-safe
-pub static MEMPOOL: Storage<Mempool> = Storage::new();
+/// Kept in the memzone `EngineCounters::MEMZONE`, where other processes can read them.
+/// Like `MEMPOOL` this is an attached handle: `main` owns the memory and frees it on shutdown
+pub static COUNTERS: Storage<SharedBox<EngineCounters>> = Storage::new();
 
 /// Send/Receive packets to/fro the processing core
 pub(crate) static PROC_CHANNEL: Storage<RingClientMap> = Storage::new();
@@ -71,41 +86,108 @@ pub static FROM_PACKETISER: Storage<SegQueue<Mbuf>> = Storage::new();
 
 pub const NUM_RX_THREADS: usize = 1;
 pub const NUM_TX_THREADS: usize = 1;
-const PROCESSOR_THREAD: u16 = 1; // ID of the process that will process the packets
 
-fn handle_signal(kr: Arc<AtomicBool>) {
-	ctrlc::set_handler(move || {
-		kr.store(false, Ordering::SeqCst);
-	})
-	.expect("Error setting Ctrl-C handler");
+/// Stop the engine on SIGINT or SIGTERM; a second signal exits without cleaning up
+fn handle_signal(stop: StopSignal) {
+	let mut signals = Signals::new([SIGINT, SIGTERM]).expect("Error setting signal handlers");
+	thread::spawn(move || {
+		for signal in signals.forever() {
+			if !stop.is_running() {
+				log::warn!("main: signal {} while shutting down, exiting now", signal);
+				process::exit(1);
+			}
+			log::info!("main: signal {}, shutting down", signal);
+			stop.stop();
+		}
+	});
+}
+
+/// Check the mempools against their low-water mark every `interval` until `stop` is signalled
+fn monitor_mempools(stop: StopSignal, interval: Duration) -> thread::JoinHandle<()> {
+	thread::Builder::new()
+		.name(String::from("mempool monitor"))
+		.spawn(move || {
+			while stop.is_running() {
+				MEMPOOL.get().check_low_water();
+				thread::sleep(interval);
+			}
+		})
+		.expect("could not spawn the mempool monitor thread")
+}
+
+/// Free every packet still queued in the engine or sent to it by a client
+///
+/// The lcores moving packets must have stopped. The rings towards the clients are left
+/// alone: the clients dequeue from them until they stop.
+fn drain_queues(ports: &[Port]) -> usize {
+	let mut count: usize = ports.iter().map(Port::drain_steered).sum();
+	for queue in &[OUT_PKTS.get(), TO_PACKETISER.get(), FROM_PACKETISER.get()] {
+		while let Some(pkt) = queue.pop() {
+			drop(pkt);
+			count += 1;
+		}
+	}
+	count + PROC_CHANNEL.get().drain_from_clients()
+}
+
+/// Tell the packetiser the engine is shutting down and wait for it to stop
+///
+/// `responder` still owes the packetiser the reply to its ready message; the shutdown notice is that reply.
+/// Returns whether the packetiser confirmed it stopped.
+fn notify_packetiser(responder: &zmq::Socket, timeout: Duration) -> bool {
+	if let Err(e) = responder.send(SHUTDOWN_MSG, 0) {
+		log::warn!("main: cannot notify the packetiser: {}", e);
+		return false;
+	}
+	match responder.poll(zmq::POLLIN, timeout.as_millis() as i64) {
+		Ok(n) if n > 0 => {
+			let _ = responder.recv_bytes(0);
+			log::info!("main: packetiser stopped");
+			true
+		}
+		Ok(_) => {
+			log::warn!("main: packetiser did not stop within {:?}", timeout);
+			false
+		}
+		Err(e) => {
+			log::warn!("main: waiting for the packetiser failed: {}", e);
+			false
+		}
+	}
 }
 
-// pub fn print_mac_addrs(ports: &Vec<Port>) {
-// 	for port in ports {
-// 		println!("Port {}: {:?}", port.id, port.mac_addr().unwrap());
-// 	}
-// }
+pub fn print_mac_addrs(ports: &[Port]) {
+	for port in ports {
+		println!("Port {}: {}", port.id, port.mac_addr().unwrap());
+	}
+}
 
-fn rx_thread_main(kr: Arc<AtomicBool>, ports: Vec<Port>) {
-	while kr.load(Ordering::SeqCst) {
+/// Poll the rx queues of the calling lcore; with `feed_packetiser` also pass the
+/// packets every rx lcore received on to the packetiser
+///
+/// The ring towards the packetiser has a single producer, so only one lcore may feed it.
+fn rx_thread_main(stop: StopSignal, mut ports: PortSetReader, feed_packetiser: bool) {
+	while stop.is_running() {
 		// get packets from outside
-		let _rx_sz = get_external_pkts(&ports);
+		let _rx_sz = get_external_pkts(ports.ports());
 		#[cfg(feature = "debug")]
 		if _rx_sz > 0 {
 			println!("received: {} pkt(s)", _rx_sz);
 		}
 		// send packets to the packetiser
-		send_to_packetiser();
+		if feed_packetiser {
+			send_to_packetiser();
+		}
 	}
 }
 
-fn tx_thread_main(kr: Arc<AtomicBool>, ports: Vec<Port>) {
-	while kr.load(Ordering::SeqCst) {
+fn tx_thread_main(stop: StopSignal, mut ports: PortSetReader) {
+	while stop.is_running() {
 		// get packets from packetiser
 		get_from_packetiser();
 
 		// send all outgoing packets
-		let _tx_sz = send_pkts_out(&ports);
+		let _tx_sz = send_pkts_out(ports.ports());
 		#[cfg(feature = "debug")]
 		if _tx_sz > 0 {
 			println!("sent: {} pkt(s)", _tx_sz);
@@ -113,68 +195,133 @@ fn tx_thread_main(kr: Arc<AtomicBool>, ports: Vec<Port>) {
 	}
 }
 
+/// Run both the rx and the tx path on the calling lcore
+fn rx_tx_thread_main(stop: StopSignal, mut ports: PortSetReader) {
+	while stop.is_running() {
+		// get packets from outside
+		let _rx_sz = get_external_pkts(ports.ports());
+		#[cfg(feature = "debug")]
+		if _rx_sz > 0 {
+			println!("received: {} pkts", _rx_sz);
+		}
+		// send packets to the packetiser
+		send_to_packetiser();
+		// get packets from packetiser
+		get_from_packetiser();
+
+		// send all outgoing packets
+		let _tx_sz = send_pkts_out(ports.ports());
+		#[cfg(feature = "debug")]
+		if _tx_sz > 0 {
+			println!("sent: {} pkts", _tx_sz);
+		}
+	}
+}
+
+/// Launch `func` on `lcore`, stopping the engine if it cannot be launched
+fn launch_or_stop<F>(lcore: u32, stop: &StopSignal, func: F) -> Option<LcoreHandle<()>>
+where
+	F: FnOnce() + Send + 'static,
+{
+	match launch(lcore, func) {
+		Ok(handle) => Some(handle),
+		Err(e) => {
+			log::error!("main: failed to launch on lcore {}: {}", lcore, e);
+			stop.stop();
+			None
+		}
+	}
+}
+
 #[allow(while_true)]
 fn main() {
+	let conf_path = env::args()
+		.nth(1)
+		.unwrap_or_else(|| String::from(DEFAULT_CONFIG_PATH));
+	let config = match EngineConfig::load(&conf_path) {
+		Ok(config) => config,
+		Err(e) => {
+			eprintln!("main: bad configuration: {}", e);
+			process::exit(1);
+		}
+	};
+	CONFIG.set(config);
+	let config = CONFIG.get();
+
 	log::info!("Initializing DPDK env ...");
-	// NOTE: hardcoded for now
-	// later get a python script scan the system and populate a config file
-	let args = vec![
-		String::from("-l 0-1"),
-		String::from("-n 4"),
-		String::from("--proc-type=primary"),
-		String::from("--base-virtaddr=0x7f000000000"),
-		String::from("--"),
-		String::from("-p 3"),
-		String::from("-n 2"),
-	];
-	println!("main process args: {:?}", &args); // debug
-	#[cfg(debug)]
+	let args = config.engine_eal_args();
+	#[cfg(feature = "debug")]
 	println!("main process args: {:?}", &args);
 	eal_init(args).unwrap();
 
 	#[cfg(feature = "debug")]
 	println!("environment initialised"); // debug
 
-	// NOTE: once again hardcoded
-	let cores = (0..2).collect::<Vec<u32>>();
-	let cur_core = unsafe { dpdk_sys::_rte_lcore_id() };
+	let roles = config.lcore_roles();
+	let cur_core = current_lcore();
+	if cur_core != roles.main {
+		eprintln!(
+			"main: bad configuration: engine.lcores: the EAL runs main on lcore {}, not on lcore {}",
+			cur_core, roles.main
+		);
+		process::exit(1);
+	}
+
+	// the statics get attached handles, which never free what they point to;
+	// main keeps the owners until everything else has stopped
+	let counters = match SharedBox::<EngineCounters>::zeroed(EngineCounters::MEMZONE) {
+		Ok(counters) => counters,
+		Err(e) => panic!("Failed to reserve the counters: {}", e),
+	};
+	COUNTERS.set(SharedBox::attach(EngineCounters::MEMZONE).unwrap());
 
 	log::info!("setup mempool");
 	let mempool;
-	match Mempool::new(G_MEMPOOL_NAME) {
+	let pool_config = config.mempool.pool_config();
+	let pools = if config.mempool.per_socket {
+		MempoolSet::per_socket(&config.mempool.name, &pool_config)
+	} else {
+		Mempool::with_config(&config.mempool.name, &pool_config).map(MempoolSet::single)
+	};
+	match pools {
 		Ok(mp) => {
 			#[cfg(feature = "debug")]
-			println!("mempool address: {:p}", mp.get_ptr());
+			println!("mempools: {:?}", mp);
 			mempool = mp;
 		}
 		Err(e) => panic!("Failed to initialize mempool: {}", e),
 	}
-	MEMPOOL.set(mempool);
+	MEMPOOL.set(MempoolSet::lookup(&config.mempool.name).unwrap());
+	MEMPOOL.get().set_low_water_pct(config.mempool.low_water_pct);
 	#[cfg(feature = "debug")]
 	println!("mempool set");
 
+	log::info!("lcores: main {}, rx {:?}, tx {}", roles.main, roles.rx, roles.tx);
 	log::info!("setup ports");
-	// let eth_devs = vec!["port0", "port1"];
-	let eth_devs = vec!["port0"];
 	let mut ports: Vec<Port> = Vec::new();
+	let mut port_confs = Vec::new();
 	{
-		let mempool = MEMPOOL.get();
-		let mut p = Port::new(eth_devs[0], 0u16).unwrap();
-		p.configure(cores.len() as u16, mempool).unwrap();
-		p.start().unwrap();
-		ports.push(p);
-		// for id in 0..=1 {
-		// 	let mut p = Port::new(eth_devs[id], id as u16).unwrap();
-		// 	p.configure(cores.len() as u16, mempool).unwrap();
-		// 	p.start().unwrap();
-		// 	ports.push(p);
-		// }
-	} // lock on MEMPOOL released
+		// the queue maps were resolved and checked against the lcore roles on load
+		for port_conf in config.ports.iter().cloned() {
+			let mut p = Port::new(&port_conf.name, port_conf.id).unwrap();
+			start_port(&mut p, &port_conf).unwrap();
+			log::info!("port {} ({}): mac {}", p.id, p.device, p.mac_addr().unwrap());
+			if config.engine.link_timeout_ms > 0 {
+				match p.wait_for_link(Duration::from_millis(config.engine.link_timeout_ms)) {
+					Ok(link) => log::info!("port {}: link {}", p.id, link),
+					Err(e) => log::warn!("port {}: {}", p.id, e),
+				}
+			}
+			ports.push(p);
+			port_confs.push(port_conf);
+		}
+	}
+	let port_set = PortSet::new(ports.clone());
 
 	#[cfg(feature = "debug")]
 	{
 		println!("ports set");
-		// print_mac_addrs(&ports);
+		print_mac_addrs(&ports);
 	}
 
 	// let mut server = Server::new();
@@ -209,10 +356,10 @@ fn main() {
 		println!("Test memzone addr: {}", memzone.virt_addr());
 	}
 
-	let ringmap = RingClientMap::new();
+	let ringmap = RingClientMap::with_ring_size(config.rings.size as usize);
 	#[cfg(feature = "debug")]
 	println!("main process: created ringmap");
-	ringmap.add_client(PACKETISER_ID).unwrap(); // fatal error
+	ringmap.add_client(config.packetiser.client_id).unwrap(); // fatal error
 	#[cfg(debug)]
 	println!("main process: added packetiser to ringmap");
 
@@ -221,7 +368,7 @@ fn main() {
 	println!("main: waiting for secondary");
 	let context = Context::new();
 	let responder = context.socket(zmq::REP).unwrap(); // fatal error
-	assert!(responder.bind(PACKETISER_ZMQ_PORT).is_ok());
+	assert!(responder.bind(&config.control.packetiser_bind).is_ok());
 	let mut msg = zmq::Message::new();
 	responder.recv(&mut msg, 0).unwrap();
 
@@ -234,47 +381,124 @@ fn main() {
 	// packets to be received from the packetiser
 	FROM_PACKETISER.set(SegQueue::new());
 
-	// handling Ctrl+C
-	let keep_running = Arc::new(AtomicBool::new(true));
-	let kr = keep_running.clone();
-	handle_signal(keep_running.clone());
+	// handling Ctrl+C and SIGTERM
+	let stop = StopSignal::new();
+	handle_signal(stop.clone());
+
+	let monitor = if config.mempool.low_water_pct == 0 {
+		None
+	} else {
+		Some(monitor_mempools(
+			stop.clone(),
+			Duration::from_millis(config.mempool.check_interval_ms),
+		))
+	};
+
+	// ports can be attached and reconfigured from now on
+	let hotplug = if config.control.hotplug_bind.is_empty() {
+		None
+	} else {
+		let hotplug = Hotplug::new(port_set.clone(), ports.into_iter().zip(port_confs).collect());
+		Some(hotplug.spawn(config.control.hotplug_bind.clone(), stop.clone()))
+	};
 
 	#[cfg(feature = "debug")]
 	println!("main: secondary started");
 	// secondary has started up; start processing packets
-	while kr.load(Ordering::SeqCst) {
-		// get packets from outside
-		let _rx_sz = get_external_pkts(&ports);
-		#[cfg(feature = "debug")]
-		if _rx_sz > 0 {
-			println!("received: {} pkts", _rx_sz);
+	let mut handles = Vec::new();
+	if roles.rx == [roles.tx] {
+		rx_tx_thread_main(stop.clone(), port_set.reader());
+	} else {
+		for (i, &lcore) in roles.rx.iter().enumerate() {
+			let (s, p) = (stop.clone(), port_set.reader());
+			handles.extend(launch_or_stop(lcore, &stop, move || rx_thread_main(s, p, i == 0)));
 		}
-		// send packets to the packetiser
-		send_to_packetiser();
-		// get packets from packetiser
-		get_from_packetiser();
-
-		// send all outgoing packets
-		let _tx_sz = send_pkts_out(&ports);
-		#[cfg(feature = "debug")]
-		if _tx_sz > 0 {
-			println!("sent: {} pkts", _tx_sz);
+		if roles.tx == cur_core {
+			tx_thread_main(stop.clone(), port_set.reader());
+		} else {
+			let (s, p) = (stop.clone(), port_set.reader());
+			handles.extend(launch_or_stop(roles.tx, &stop, move || tx_thread_main(s, p)));
+		}
+	}
+	for handle in handles {
+		let lcore = handle.lcore();
+		if let Err(e) = handle.join() {
+			log::error!("main: lcore {} did not stop cleanly: {}", lcore, e);
+		}
+	}
+	if let Some(hotplug) = hotplug {
+		if hotplug.join().is_err() {
+			log::error!("main: hotplug thread panicked");
+		}
+	}
+	if let Some(monitor) = monitor {
+		if monitor.join().is_err() {
+			log::error!("main: mempool monitor thread panicked");
 		}
 	}
 
-	// dpdk_sys::rte_eal_remote_launch(
-	// 	Some(rx_thread_main),
-	// 	&[kr.clone(), &ports, &server],
-	// 	cur_core,
-	// );
-
+	// rx and tx have stopped; nothing moves packets any more
 	#[cfg(feature = "debug")]
 	println!("main: stopping");
+	let drained = drain_queues(&port_set.ports());
+	log::info!("main: freed {} queued pkt(s)", drained);
+	// only clients known to have stopped have their rings drained and freed;
+	// the packetiser is the only client with a control channel to tell it to stop
+	let clients = PROC_CHANNEL.get().client_ids();
+	let mut stopped = Vec::new();
+	for &client_id in &clients {
+		if client_id != config.packetiser.client_id {
+			log::warn!("main: client {} cannot be told to stop, its rings are kept", client_id);
+			continue;
+		}
+		let timeout = Duration::from_millis(config.engine.shutdown_timeout_ms);
+		if notify_packetiser(&responder, timeout) {
+			stopped.push(client_id);
+		}
+	}
+	for &client_id in &stopped {
+		// the client no longer dequeues, and may have queued more before it stopped
+		if let Ok(drained) = PROC_CHANNEL.get().drain_client(client_id) {
+			log::info!("main: freed {} pkt(s) left in the rings of client {}", drained, client_id);
+		}
+	}
+
+	for port in &port_set.ports() {
+		match port.stats() {
+			Ok(stats) => log::info!("{}", stats),
+			Err(e) => log::warn!("port {}: cannot read stats: {}", port.id, e),
+		}
+	}
+	for stats in MEMPOOL.get().stats() {
+		log::info!("{}", stats);
+	}
+	for stage in Stage::ALL.iter() {
+		log::info!("{}: {}", stage.name(), counters.stage(*stage).snapshot());
+	}
+	for port in &port_set.ports() {
+		if let Some(port_counters) = counters.port(port.id) {
+			log::info!("port {} counters: {}", port.id, port_counters.snapshot());
+		}
+	}
+	for &client_id in &clients {
+		if let Some(client) = counters.client(client_id) {
+			log::info!("client {}: {}", client_id, client.snapshot());
+		}
+	}
 	unsafe { dpdk_sys::_pkt_stop_and_close_ports() };
 	#[cfg(feature = "debug")]
 	println!("main: ports closed");
-	let mempool = MEMPOOL.get();
+
+	// a client that did not stop may still be using its rings
+	for client_id in stopped {
+		PROC_CHANNEL.get().remove_client(client_id);
+	}
+	drop(memzone);
+	// the counters memzone must go before the EAL is torn down
+	drop(counters);
 	#[cfg(feature = "debug")]
 	println!("main: mempool cleaned");
-	eal_cleanup(mempool).unwrap();
+	if let Err(e) = eal_cleanup(mempool) {
+		log::error!("main: eal cleanup failed: {}", e);
+	}
 }
@@ -0,0 +1,298 @@
+//! ARP for IPv4 over Ethernet
+//!
+//! Other hardware and protocol types are rejected when the layer is parsed, so the
+//! addresses always have the sizes of a MAC and an IPv4 address.
+
+use crate::apis::SizeOf;
+use std::{fmt, mem, net::Ipv4Addr, ptr::NonNull};
+
+use super::{Datalink, EtherType, MacAddr, Packet, ParseError};
+
+/// `htype` of Ethernet
+const ARP_HRD_ETHER: u16 = 1;
+
+/// ARP packet as it is on the wire
+#[repr(C, packed)]
+#[derive(Clone, Copy, SizeOf)]
+pub struct ArpHeader {
+	// big endian
+	htype: u16,
+	// big endian
+	ptype: u16,
+	hlen: u8,
+	plen: u8,
+	// big endian
+	oper: u16,
+	sha: MacAddr,
+	spa: [u8; 4],
+	tha: MacAddr,
+	tpa: [u8; 4],
+}
+
+/// What an ARP packet asks or tells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpOp {
+	Request,
+	Reply,
+	Other(u16),
+}
+
+impl ArpOp {
+	fn from_u16(op: u16) -> Self {
+		match op {
+			1 => ArpOp::Request,
+			2 => ArpOp::Reply,
+			other => ArpOp::Other(other),
+		}
+	}
+
+	fn to_u16(self) -> u16 {
+		match self {
+			ArpOp::Request => 1,
+			ArpOp::Reply => 2,
+			ArpOp::Other(other) => other,
+		}
+	}
+}
+
+/// The ARP packet of a frame
+pub struct Arp<E: Datalink> {
+	envelope: E,
+	header: NonNull<ArpHeader>,
+	offset: usize,
+}
+
+impl<E: Datalink> Arp<E> {
+	#[inline]
+	fn header(&self) -> &ArpHeader {
+		unsafe { self.header.as_ref() }
+	}
+
+	#[inline]
+	fn header_mut(&mut self) -> &mut ArpHeader {
+		unsafe { self.header.as_mut() }
+	}
+
+	#[inline]
+	pub fn operation(&self) -> ArpOp {
+		ArpOp::from_u16(u16::from_be(self.header().oper))
+	}
+
+	#[inline]
+	pub fn set_operation(&mut self, op: ArpOp) {
+		self.header_mut().oper = op.to_u16().to_be();
+	}
+
+	#[inline]
+	pub fn sender_hw_addr(&self) -> MacAddr {
+		self.header().sha
+	}
+
+	#[inline]
+	pub fn set_sender_hw_addr(&mut self, mac: MacAddr) {
+		self.header_mut().sha = mac;
+	}
+
+	#[inline]
+	pub fn sender_proto_addr(&self) -> Ipv4Addr {
+		Ipv4Addr::from(self.header().spa)
+	}
+
+	#[inline]
+	pub fn set_sender_proto_addr(&mut self, ip: Ipv4Addr) {
+		self.header_mut().spa = ip.octets();
+	}
+
+	#[inline]
+	pub fn target_hw_addr(&self) -> MacAddr {
+		self.header().tha
+	}
+
+	#[inline]
+	pub fn set_target_hw_addr(&mut self, mac: MacAddr) {
+		self.header_mut().tha = mac;
+	}
+
+	#[inline]
+	pub fn target_proto_addr(&self) -> Ipv4Addr {
+		Ipv4Addr::from(self.header().tpa)
+	}
+
+	#[inline]
+	pub fn set_target_proto_addr(&mut self, ip: Ipv4Addr) {
+		self.header_mut().tpa = ip.octets();
+	}
+
+	/// Turns a request into the reply announcing that `mac` has the address asked for
+	///
+	/// Only the ARP packet is rewritten; the frame around it still has to be addressed
+	/// back to the sender.
+	pub fn make_reply(&mut self, mac: MacAddr) {
+		let header = self.header_mut();
+		header.oper = ArpOp::Reply.to_u16().to_be();
+		header.tha = header.sha;
+		header.sha = mac;
+		// byte arrays, so the packed fields can be borrowed
+		mem::swap(&mut header.spa, &mut header.tpa);
+	}
+}
+
+impl<E: Datalink> Packet for Arp<E> {
+	type Envelope = E;
+
+	#[inline]
+	fn envelope(&self) -> &Self::Envelope {
+		&self.envelope
+	}
+
+	#[inline]
+	fn envelope_mut(&mut self) -> &mut Self::Envelope {
+		&mut self.envelope
+	}
+
+	#[inline]
+	fn offset(&self) -> usize {
+		self.offset
+	}
+
+	#[inline]
+	fn header_len(&self) -> usize {
+		ArpHeader::size_of()
+	}
+
+	fn try_parse(envelope: Self::Envelope) -> Result<Self, ParseError> {
+		envelope.expect_ether_type(EtherType::ARP, "ARP")?;
+		let offset = envelope.payload_offset();
+		let header = envelope.mbuf().read_data::<ArpHeader>(offset)?;
+		let arp = unsafe { header.as_ref() };
+		if u16::from_be(arp.htype) != ARP_HRD_ETHER
+			|| EtherType(u16::from_be(arp.ptype)) != EtherType::IPV4
+			|| arp.hlen as usize != MacAddr::size_of()
+			|| arp.plen != 4
+		{
+			return Err(ParseError::Invalid(
+				"ARP",
+				format!(
+					"not IPv4 over Ethernet (htype {}, ptype {:#06x})",
+					u16::from_be(arp.htype),
+					u16::from_be(arp.ptype)
+				),
+			));
+		}
+		Ok(Self {
+			envelope,
+			header,
+			offset,
+		})
+	}
+
+	#[inline]
+	fn deparse(self) -> Self::Envelope {
+		self.envelope
+	}
+}
+
+impl<E: Datalink> fmt::Debug for Arp<E> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Arp")
+			.field("operation", &self.operation())
+			.field("sender_hw_addr", &self.sender_hw_addr())
+			.field("sender_proto_addr", &self.sender_proto_addr())
+			.field("target_hw_addr", &self.target_hw_addr())
+			.field("target_proto_addr", &self.target_proto_addr())
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::apis::{BufError, Mbuf};
+	use crate::net::{testing, Ethernet, Vlan};
+
+	/// A broadcast request from 02:00:00:00:00:02 at 10.0.0.1 asking for 10.0.0.2,
+	/// behind `tags` (ether type and TCI of each VLAN tag)
+	fn request_frame(tags: &[(u16, u16)]) -> Vec<u8> {
+		let mut frame = vec![0xff; 6];
+		frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x02]);
+		for (ether_type, tci) in tags {
+			frame.extend_from_slice(&ether_type.to_be_bytes());
+			frame.extend_from_slice(&tci.to_be_bytes());
+		}
+		frame.extend_from_slice(&[
+			0x08, 0x06, // ether type
+			0x00, 0x01, 0x08, 0x00, 6, 4, 0x00, 0x01, // IPv4 over Ethernet, request
+			0x02, 0, 0, 0, 0, 0x02, 10, 0, 0, 1, // sender
+			0, 0, 0, 0, 0, 0, 10, 0, 0, 2, // target
+		]);
+		frame
+	}
+
+	fn parse(mbuf: Mbuf) -> Arp<Ethernet> {
+		Ethernet::parse(mbuf).unwrap().parse::<Arp<Ethernet>>().unwrap()
+	}
+
+	#[test]
+	fn parses_a_request() {
+		let arp = parse(testing::mbuf(&request_frame(&[])));
+		assert_eq!(arp.operation(), ArpOp::Request);
+		assert_eq!(arp.sender_hw_addr(), MacAddr::new(2, 0, 0, 0, 0, 2));
+		assert_eq!(arp.sender_proto_addr(), Ipv4Addr::new(10, 0, 0, 1));
+		assert_eq!(arp.target_hw_addr(), MacAddr::default());
+		assert_eq!(arp.target_proto_addr(), Ipv4Addr::new(10, 0, 0, 2));
+		assert_eq!((arp.offset(), arp.payload_len()), (14, 0));
+		testing::release(arp.reset());
+	}
+
+	#[test]
+	fn a_reply_survives_a_reparse() {
+		let mac = MacAddr::new(2, 0, 0, 0, 0, 0xaa);
+		let mut arp = parse(testing::mbuf(&request_frame(&[])));
+		arp.make_reply(mac);
+		let arp = parse(arp.reset());
+		assert_eq!(arp.operation(), ArpOp::Reply);
+		assert_eq!(arp.sender_hw_addr(), mac);
+		assert_eq!(arp.sender_proto_addr(), Ipv4Addr::new(10, 0, 0, 2));
+		assert_eq!(arp.target_hw_addr(), MacAddr::new(2, 0, 0, 0, 0, 2));
+		assert_eq!(arp.target_proto_addr(), Ipv4Addr::new(10, 0, 0, 1));
+		testing::release(arp.reset());
+	}
+
+	#[test]
+	fn parses_a_request_behind_qinq_tags() {
+		let frame = request_frame(&[(0x88a8, 100), (0x8100, 200)]);
+		let ether = Ethernet::parse(testing::mbuf(&frame)).unwrap();
+		let inner = ether.parse::<Vlan<Ethernet>>().unwrap().parse::<Vlan<Vlan<Ethernet>>>().unwrap();
+		let arp = inner.parse::<Arp<Vlan<Vlan<Ethernet>>>>().unwrap();
+		assert_eq!(arp.offset(), 22);
+		assert_eq!(arp.target_proto_addr(), Ipv4Addr::new(10, 0, 0, 2));
+		assert_eq!(arp.envelope().vid(), 200);
+		testing::release(arp.reset());
+	}
+
+	#[test]
+	fn a_short_packet_is_a_parse_error() {
+		let frame = request_frame(&[]);
+		let ether = Ethernet::parse(testing::mbuf(&frame[..frame.len() - 1])).unwrap();
+		match ether.parse::<Arp<Ethernet>>() {
+			Err(ParseError::Buf(BufError::OutOfBuffer(28, 27))) => {}
+			other => panic!("27 byte packet: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn other_protocols_are_parse_errors() {
+		let mut frame = request_frame(&[]);
+		frame[14..16].copy_from_slice(&6u16.to_be_bytes());
+		match Ethernet::parse(testing::mbuf(&frame)).unwrap().parse::<Arp<Ethernet>>() {
+			Err(ParseError::Invalid("ARP", _)) => {}
+			other => panic!("token ring: {:?}", other),
+		}
+		let mut frame = request_frame(&[]);
+		frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+		match Ethernet::parse(testing::mbuf(&frame)).unwrap().parse::<Arp<Ethernet>>() {
+			Err(ParseError::EtherType("ARP", EtherType::IPV4)) => {}
+			other => panic!("ipv4 ether type: {:?}", other),
+		}
+	}
+}
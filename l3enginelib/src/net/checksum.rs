@@ -0,0 +1,211 @@
+//! The Internet checksum (RFC 1071) and its incremental update (RFC 1624)
+//!
+//! IPv4, TCP, UDP and ICMP all protect their headers with the one's complement of the
+//! one's complement sum of 16 bit words. A full computation sums every byte again;
+//! when a router or a NAT only rewrites a field, `update_u16` and `update_u32` fix the
+//! checksum from the old and new values of the field alone.
+
+use crate::apis::{BufError, Mbuf};
+
+/// A running one's complement sum
+///
+/// Bytes can be added in pieces of any length, e.g. one per mbuf segment;
+/// an odd byte left at the end of a piece is paired with the first one of the next.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Checksum {
+	sum: u64,
+	// high byte of a word whose low byte has not been added yet
+	odd: Option<u8>,
+}
+
+impl Checksum {
+	#[inline]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add a big endian 16 bit word
+	#[inline]
+	pub fn add_u16(&mut self, word: u16) {
+		self.add_bytes(&word.to_be_bytes());
+	}
+
+	/// Add a big endian 32 bit word, e.g. an IPv4 address
+	#[inline]
+	pub fn add_u32(&mut self, word: u32) {
+		self.add_bytes(&word.to_be_bytes());
+	}
+
+	pub fn add_bytes(&mut self, mut data: &[u8]) {
+		if let Some(high) = self.odd {
+			match data.split_first() {
+				Some((&low, rest)) => {
+					self.sum += u16::from_be_bytes([high, low]) as u64;
+					self.odd = None;
+					data = rest;
+				}
+				None => return,
+			}
+		}
+		let mut words = data.chunks_exact(2);
+		for word in &mut words {
+			self.sum += u16::from_be_bytes([word[0], word[1]]) as u64;
+		}
+		if let [last] = words.remainder() {
+			self.odd = Some(*last);
+		}
+	}
+
+	/// Add `len` bytes of `mbuf` from `offset`, over as many segments as they span
+	pub fn add_mbuf(&mut self, mbuf: &Mbuf, offset: usize, len: usize) -> Result<(), BufError> {
+		if offset + len > mbuf.pkt_len() {
+			return Err(BufError::OutOfBuffer(len, mbuf.pkt_len().saturating_sub(offset)));
+		}
+		let mut skip = offset;
+		let mut left = len;
+		for seg in mbuf.segments() {
+			if left == 0 {
+				break;
+			}
+			if skip >= seg.len() {
+				skip -= seg.len();
+				continue;
+			}
+			let take = (seg.len() - skip).min(left);
+			self.add_bytes(&seg[skip..skip + take]);
+			skip = 0;
+			left -= take;
+		}
+		Ok(())
+	}
+
+	/// The sum folded to 16 bits; `0xffff` over data that holds a valid checksum
+	#[inline]
+	pub fn sum(&self) -> u16 {
+		let mut sum = self.sum;
+		if let Some(high) = self.odd {
+			// an odd length is padded with a zero byte
+			sum += (high as u64) << 8;
+		}
+		while sum > 0xffff {
+			sum = (sum & 0xffff) + (sum >> 16);
+		}
+		sum as u16
+	}
+
+	/// The checksum to store in a header
+	#[inline]
+	pub fn finish(&self) -> u16 {
+		!self.sum()
+	}
+}
+
+/// The Internet checksum of `data`
+#[inline]
+pub fn checksum(data: &[u8]) -> u16 {
+	let mut sum = Checksum::new();
+	sum.add_bytes(data);
+	sum.finish()
+}
+
+/// The checksum `cksum` once a 16 bit word covered by it changes from `old` to `new`
+///
+/// RFC 1624, eqn. 3: `HC' = ~(~HC + ~m + m')`
+#[inline]
+pub fn update_u16(cksum: u16, old: u16, new: u16) -> u16 {
+	let mut sum = (!cksum) as u32 + (!old) as u32 + new as u32;
+	while sum > 0xffff {
+		sum = (sum & 0xffff) + (sum >> 16);
+	}
+	!(sum as u16)
+}
+
+/// The checksum `cksum` once a 32 bit word covered by it changes from `old` to `new`
+#[inline]
+pub fn update_u32(cksum: u16, old: u32, new: u32) -> u16 {
+	let cksum = update_u16(cksum, (old >> 16) as u16, (new >> 16) as u16);
+	update_u16(cksum, old as u16, new as u16)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Deterministic bytes that are never all zero
+	fn sample(len: usize, seed: u32) -> Vec<u8> {
+		let mut state = seed | 1;
+		(0..len)
+			.map(|_| {
+				state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+				(state >> 16) as u8
+			})
+			.collect()
+	}
+
+	fn word(data: &[u8], at: usize) -> u16 {
+		u16::from_be_bytes([data[at], data[at + 1]])
+	}
+
+	#[test]
+	fn rfc1071_example() {
+		let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+		let mut sum = Checksum::new();
+		sum.add_bytes(&data);
+		assert_eq!(sum.sum(), 0xddf2);
+		assert_eq!(checksum(&data), 0x220d);
+	}
+
+	#[test]
+	fn pieces_of_any_length_add_up() {
+		let data = sample(101, 7);
+		for split in [0, 1, 2, 3, 50, 51, 100, 101].iter().copied() {
+			let mut sum = Checksum::new();
+			sum.add_bytes(&data[..split]);
+			sum.add_bytes(&data[split..]);
+			assert_eq!(sum.finish(), checksum(&data), "split at {}", split);
+		}
+	}
+
+	#[test]
+	fn valid_data_sums_to_ones() {
+		let mut data = sample(40, 3);
+		data[10..12].copy_from_slice(&[0, 0]);
+		let cksum = checksum(&data);
+		data[10..12].copy_from_slice(&cksum.to_be_bytes());
+		assert_eq!(checksum(&data), 0);
+	}
+
+	#[test]
+	fn update_u16_matches_recompute() {
+		for seed in 0..64 {
+			let mut data = sample(60, seed);
+			let at = (seed as usize * 2) % data.len();
+			let cksum = checksum(&data);
+			let old = word(&data, at);
+			let new = sample(2, seed + 100);
+			data[at..at + 2].copy_from_slice(&new);
+			assert_eq!(update_u16(cksum, old, word(&data, at)), checksum(&data), "seed {}", seed);
+		}
+	}
+
+	#[test]
+	fn update_u16_to_the_same_value_keeps_the_checksum() {
+		let data = sample(20, 11);
+		let cksum = checksum(&data);
+		assert_eq!(update_u16(cksum, word(&data, 4), word(&data, 4)), cksum);
+	}
+
+	#[test]
+	fn update_u32_matches_recompute() {
+		for seed in 0..64 {
+			let mut data = sample(60, seed);
+			let at = (seed as usize * 4) % data.len();
+			let cksum = checksum(&data);
+			let old = u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+			let new = sample(4, seed + 200);
+			data[at..at + 4].copy_from_slice(&new);
+			let new = u32::from_be_bytes([new[0], new[1], new[2], new[3]]);
+			assert_eq!(update_u32(cksum, old, new), checksum(&data), "seed {}", seed);
+		}
+	}
+}
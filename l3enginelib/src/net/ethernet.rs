@@ -0,0 +1,248 @@
+//! Ethernet II frames
+//!
+//! `Ethernet` is the first layer parsed from an mbuf. Layers that carry an ether type
+//! for the next layer implement `Datalink`, so the layers above (ARP, VLAN, IP) can be
+//! parsed from an untagged frame and from a VLAN tag alike.
+
+use crate::apis::{Mbuf, SizeOf};
+use std::{fmt, mem, ptr::NonNull};
+
+use super::{MacAddr, Packet, ParseError};
+
+/// The protocol a link layer frame carries
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EtherType(pub u16);
+
+impl EtherType {
+	pub const IPV4: EtherType = EtherType(dpdk_sys::RTE_ETHER_TYPE_IPV4 as u16);
+	pub const IPV6: EtherType = EtherType(dpdk_sys::RTE_ETHER_TYPE_IPV6 as u16);
+	pub const ARP: EtherType = EtherType(dpdk_sys::RTE_ETHER_TYPE_ARP as u16);
+	/// An 802.1Q VLAN tag
+	pub const VLAN: EtherType = EtherType(dpdk_sys::RTE_ETHER_TYPE_VLAN as u16);
+	/// An 802.1ad service VLAN tag
+	pub const QINQ: EtherType = EtherType(dpdk_sys::RTE_ETHER_TYPE_QINQ as u16);
+
+	/// Is this the type of a VLAN tag
+	#[inline]
+	pub fn is_vlan(self) -> bool {
+		self == Self::VLAN || self == Self::QINQ
+	}
+}
+
+impl fmt::Display for EtherType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match *self {
+			EtherType::IPV4 => write!(f, "IPv4"),
+			EtherType::IPV6 => write!(f, "IPv6"),
+			EtherType::ARP => write!(f, "ARP"),
+			EtherType::VLAN => write!(f, "VLAN"),
+			EtherType::QINQ => write!(f, "QinQ"),
+			EtherType(other) => write!(f, "{:#06x}", other),
+		}
+	}
+}
+
+impl fmt::Debug for EtherType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "EtherType({})", self)
+	}
+}
+
+/// A layer that names the protocol of its payload with an ether type
+pub trait Datalink: Packet {
+	/// The protocol of the payload
+	fn next_ether_type(&self) -> EtherType;
+
+	/// Check that the payload is `expected` before a layer is parsed from it
+	#[inline]
+	fn expect_ether_type(&self, expected: EtherType, layer: &'static str) -> Result<(), ParseError> {
+		let found = self.next_ether_type();
+		if found == expected {
+			Ok(())
+		} else {
+			Err(ParseError::EtherType(layer, found))
+		}
+	}
+}
+
+/// Ethernet header as it is on the wire
+#[repr(C, packed)]
+#[derive(Clone, Copy, SizeOf)]
+pub struct EthernetHeader {
+	dst: MacAddr,
+	src: MacAddr,
+	// big endian
+	ether_type: u16,
+}
+
+/// The Ethernet layer of a packet
+pub struct Ethernet {
+	envelope: Mbuf,
+	header: NonNull<EthernetHeader>,
+}
+
+impl Ethernet {
+	/// Parses the Ethernet header at the start of `mbuf`
+	#[inline]
+	pub fn parse(mbuf: Mbuf) -> Result<Self, ParseError> {
+		Self::try_parse(mbuf)
+	}
+
+	#[inline]
+	fn header(&self) -> &EthernetHeader {
+		unsafe { self.header.as_ref() }
+	}
+
+	#[inline]
+	fn header_mut(&mut self) -> &mut EthernetHeader {
+		unsafe { self.header.as_mut() }
+	}
+
+	#[inline]
+	pub fn src(&self) -> MacAddr {
+		self.header().src
+	}
+
+	#[inline]
+	pub fn set_src(&mut self, mac: MacAddr) {
+		self.header_mut().src = mac;
+	}
+
+	#[inline]
+	pub fn dst(&self) -> MacAddr {
+		self.header().dst
+	}
+
+	#[inline]
+	pub fn set_dst(&mut self, mac: MacAddr) {
+		self.header_mut().dst = mac;
+	}
+
+	/// The ether type of the frame, `EtherType::VLAN` for a tagged one
+	#[inline]
+	pub fn ether_type(&self) -> EtherType {
+		EtherType(u16::from_be(self.header().ether_type))
+	}
+
+	#[inline]
+	pub fn set_ether_type(&mut self, ether_type: EtherType) {
+		self.header_mut().ether_type = ether_type.0.to_be();
+	}
+
+	/// Swaps the source and destination addresses, e.g. to send a reply back
+	#[inline]
+	pub fn swap_addresses(&mut self) {
+		let header = self.header_mut();
+		// `MacAddr` is a byte array, so the packed fields can be borrowed
+		mem::swap(&mut header.src, &mut header.dst);
+	}
+}
+
+impl Packet for Ethernet {
+	type Envelope = Mbuf;
+
+	#[inline]
+	fn envelope(&self) -> &Self::Envelope {
+		&self.envelope
+	}
+
+	#[inline]
+	fn envelope_mut(&mut self) -> &mut Self::Envelope {
+		&mut self.envelope
+	}
+
+	#[inline]
+	fn offset(&self) -> usize {
+		0
+	}
+
+	#[inline]
+	fn header_len(&self) -> usize {
+		EthernetHeader::size_of()
+	}
+
+	fn try_parse(envelope: Self::Envelope) -> Result<Self, ParseError> {
+		let header = envelope.read_data::<EthernetHeader>(0)?;
+		Ok(Self { envelope, header })
+	}
+
+	#[inline]
+	fn deparse(self) -> Self::Envelope {
+		self.envelope
+	}
+}
+
+impl Datalink for Ethernet {
+	#[inline]
+	fn next_ether_type(&self) -> EtherType {
+		self.ether_type()
+	}
+}
+
+impl fmt::Debug for Ethernet {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Ethernet")
+			.field("src", &self.src())
+			.field("dst", &self.dst())
+			.field("ether_type", &self.ether_type())
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::apis::BufError;
+	use crate::net::{testing, IpProtocol};
+
+	#[test]
+	fn parses_the_header() {
+		let ether = Ethernet::parse(testing::mbuf(&testing::ipv4_frame(IpProtocol::UDP, &[]))).unwrap();
+		assert_eq!(ether.dst(), MacAddr::new(2, 0, 0, 0, 0, 1));
+		assert_eq!(ether.src(), MacAddr::new(2, 0, 0, 0, 0, 2));
+		assert_eq!(ether.ether_type(), EtherType::IPV4);
+		assert_eq!(ether.payload_offset(), 14);
+		assert_eq!(ether.payload_len(), 20);
+		testing::release(ether.reset());
+	}
+
+	#[test]
+	fn changes_are_kept_in_the_deparsed_frame() {
+		let frame = testing::ipv4_frame(IpProtocol::UDP, &[]);
+		let mut ether = Ethernet::parse(testing::mbuf(&frame)).unwrap();
+		ether.swap_addresses();
+		ether.set_src(MacAddr::new(2, 0, 0, 0, 0, 0xaa));
+		ether.set_ether_type(EtherType::ARP);
+		let mbuf = ether.deparse();
+		assert_eq!(mbuf.pkt_len(), frame.len());
+
+		let ether = Ethernet::parse(mbuf).unwrap();
+		assert_eq!(ether.dst(), MacAddr::new(2, 0, 0, 0, 0, 2));
+		assert_eq!(ether.src(), MacAddr::new(2, 0, 0, 0, 0, 0xaa));
+		assert_eq!(ether.next_ether_type(), EtherType::ARP);
+		testing::release(ether.reset());
+	}
+
+	#[test]
+	fn a_short_frame_is_a_parse_error() {
+		let frame = testing::ipv4_frame(IpProtocol::UDP, &[]);
+		match Ethernet::parse(testing::mbuf(&frame[..13])) {
+			Err(ParseError::Buf(BufError::OutOfBuffer(14, 13))) => {}
+			other => panic!("13 byte frame: {:?}", other),
+		}
+		match Ethernet::parse(testing::mbuf(&[])) {
+			Err(ParseError::Buf(BufError::BadOffset(0, 0))) => {}
+			other => panic!("empty frame: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn another_ether_type_is_a_parse_error() {
+		let ether = Ethernet::parse(testing::mbuf(&testing::ipv4_frame(IpProtocol::UDP, &[]))).unwrap();
+		match ether.expect_ether_type(EtherType::ARP, "ARP") {
+			Err(ParseError::EtherType("ARP", EtherType::IPV4)) => {}
+			other => panic!("{:?}", other),
+		}
+		testing::release(ether.reset());
+	}
+}
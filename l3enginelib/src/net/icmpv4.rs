@@ -0,0 +1,311 @@
+//! ICMP for IPv4
+//!
+//! The checksum covers the whole message and no pseudo header. Setters keep it valid
+//! with incremental updates; `make_echo_reply` answers a ping in place.
+
+use crate::apis::SizeOf;
+use std::{fmt, ptr::NonNull};
+
+use super::{header_word, update_u16, Checksum, Datalink, IpPacket, IpProtocol, Ipv4, Packet, ParseError};
+
+/// The type of an ICMP message
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IcmpType(pub u8);
+
+impl IcmpType {
+	pub const ECHO_REPLY: IcmpType = IcmpType(0);
+	pub const DEST_UNREACHABLE: IcmpType = IcmpType(3);
+	pub const REDIRECT: IcmpType = IcmpType(5);
+	pub const ECHO_REQUEST: IcmpType = IcmpType(8);
+	pub const TIME_EXCEEDED: IcmpType = IcmpType(11);
+	pub const PARAMETER_PROBLEM: IcmpType = IcmpType(12);
+}
+
+impl fmt::Display for IcmpType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match *self {
+			IcmpType::ECHO_REPLY => write!(f, "echo reply"),
+			IcmpType::DEST_UNREACHABLE => write!(f, "destination unreachable"),
+			IcmpType::REDIRECT => write!(f, "redirect"),
+			IcmpType::ECHO_REQUEST => write!(f, "echo request"),
+			IcmpType::TIME_EXCEEDED => write!(f, "time exceeded"),
+			IcmpType::PARAMETER_PROBLEM => write!(f, "parameter problem"),
+			IcmpType(other) => write!(f, "{}", other),
+		}
+	}
+}
+
+impl fmt::Debug for IcmpType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "IcmpType({})", self)
+	}
+}
+
+/// ICMP header as it is on the wire
+#[repr(C, packed)]
+#[derive(Clone, Copy, SizeOf)]
+pub struct IcmpHeader {
+	icmp_type: u8,
+	code: u8,
+	// big endian, as every multi-byte field
+	checksum: u16,
+	// identifier and sequence number of echo messages
+	rest: [u8; 4],
+}
+
+/// The ICMP layer of an IPv4 packet
+pub struct Icmpv4<E: Datalink> {
+	envelope: Ipv4<E>,
+	header: NonNull<IcmpHeader>,
+	offset: usize,
+}
+
+impl<E: Datalink> Icmpv4<E> {
+	#[inline]
+	fn header(&self) -> &IcmpHeader {
+		unsafe { self.header.as_ref() }
+	}
+
+	#[inline]
+	fn header_mut(&mut self) -> &mut IcmpHeader {
+		unsafe { self.header.as_mut() }
+	}
+
+	/// Apply `f` to the header and fix the checksum for the word at byte `at` it changed
+	#[inline]
+	fn set_word<F: FnOnce(&mut IcmpHeader)>(&mut self, at: usize, f: F) {
+		let old = unsafe { header_word(self.header, at) };
+		f(self.header_mut());
+		let new = unsafe { header_word(self.header, at) };
+		let cksum = update_u16(self.checksum(), old, new);
+		self.header_mut().checksum = cksum.to_be();
+	}
+
+	#[inline]
+	pub fn icmp_type(&self) -> IcmpType {
+		IcmpType(self.header().icmp_type)
+	}
+
+	#[inline]
+	pub fn set_icmp_type(&mut self, icmp_type: IcmpType) {
+		self.set_word(0, |h| h.icmp_type = icmp_type.0);
+	}
+
+	#[inline]
+	pub fn code(&self) -> u8 {
+		self.header().code
+	}
+
+	#[inline]
+	pub fn set_code(&mut self, code: u8) {
+		self.set_word(0, |h| h.code = code);
+	}
+
+	#[inline]
+	pub fn checksum(&self) -> u16 {
+		u16::from_be(self.header().checksum)
+	}
+
+	/// Sets the checksum as is; see `compute_checksum` to fill in the right one
+	#[inline]
+	pub fn set_checksum(&mut self, cksum: u16) {
+		self.header_mut().checksum = cksum.to_be();
+	}
+
+	/// The last four bytes of the header, whose meaning depends on the type
+	#[inline]
+	pub fn rest_of_header(&self) -> u32 {
+		u32::from_be_bytes(self.header().rest)
+	}
+
+	#[inline]
+	pub fn set_rest_of_header(&mut self, rest: u32) {
+		let bytes = rest.to_be_bytes();
+		self.set_word(4, |h| h.rest[..2].copy_from_slice(&bytes[..2]));
+		self.set_word(6, |h| h.rest[2..].copy_from_slice(&bytes[2..]));
+	}
+
+	/// Identifier of an echo request or reply
+	#[inline]
+	pub fn identifier(&self) -> u16 {
+		unsafe { header_word(self.header, 4) }
+	}
+
+	#[inline]
+	pub fn set_identifier(&mut self, id: u16) {
+		self.set_word(4, |h| h.rest[..2].copy_from_slice(&id.to_be_bytes()));
+	}
+
+	/// Sequence number of an echo request or reply
+	#[inline]
+	pub fn sequence(&self) -> u16 {
+		unsafe { header_word(self.header, 6) }
+	}
+
+	#[inline]
+	pub fn set_sequence(&mut self, seq: u16) {
+		self.set_word(6, |h| h.rest[2..].copy_from_slice(&seq.to_be_bytes()));
+	}
+
+	/// Turns an echo request into its reply, addresses included; false for other messages
+	///
+	/// The payload is sent back as it came. The link layer still has to be addressed
+	/// back to the sender.
+	pub fn make_echo_reply(&mut self) -> bool {
+		if self.icmp_type() != IcmpType::ECHO_REQUEST {
+			return false;
+		}
+		self.set_icmp_type(IcmpType::ECHO_REPLY);
+		self.set_code(0);
+		self.envelope.swap_addresses();
+		true
+	}
+
+	/// Computes the checksum over the whole message and stores it
+	pub fn compute_checksum(&mut self) -> Result<(), ParseError> {
+		self.set_checksum(0);
+		let cksum = self.sum()?;
+		self.set_checksum(!cksum);
+		Ok(())
+	}
+
+	/// Does the checksum match the message
+	pub fn is_checksum_valid(&self) -> Result<bool, ParseError> {
+		Ok(self.sum()? == 0xffff)
+	}
+
+	/// One's complement sum of the whole message
+	fn sum(&self) -> Result<u16, ParseError> {
+		let mut sum = Checksum::new();
+		sum.add_mbuf(self.mbuf(), self.offset, self.envelope.payload_len())?;
+		Ok(sum.sum())
+	}
+}
+
+impl<E: Datalink> Packet for Icmpv4<E> {
+	type Envelope = Ipv4<E>;
+
+	#[inline]
+	fn envelope(&self) -> &Self::Envelope {
+		&self.envelope
+	}
+
+	#[inline]
+	fn envelope_mut(&mut self) -> &mut Self::Envelope {
+		&mut self.envelope
+	}
+
+	#[inline]
+	fn offset(&self) -> usize {
+		self.offset
+	}
+
+	#[inline]
+	fn header_len(&self) -> usize {
+		IcmpHeader::size_of()
+	}
+
+	#[inline]
+	fn payload_len(&self) -> usize {
+		self.envelope.payload_len().saturating_sub(self.header_len())
+	}
+
+	fn try_parse(envelope: Self::Envelope) -> Result<Self, ParseError> {
+		envelope.expect_protocol(IpProtocol::ICMP, "ICMP")?;
+		let offset = envelope.payload_offset();
+		if envelope.payload_len() < IcmpHeader::size_of() {
+			return Err(ParseError::Invalid(
+				"ICMP",
+				format!("{} byte message", envelope.payload_len()),
+			));
+		}
+		let header = envelope.mbuf().read_data::<IcmpHeader>(offset)?;
+		Ok(Self {
+			envelope,
+			header,
+			offset,
+		})
+	}
+
+	#[inline]
+	fn deparse(self) -> Self::Envelope {
+		self.envelope
+	}
+}
+
+impl<E: Datalink> fmt::Debug for Icmpv4<E> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Icmpv4")
+			.field("type", &self.icmp_type())
+			.field("code", &self.code())
+			.field("checksum", &format_args!("{:#06x}", self.checksum()))
+			.field("rest_of_header", &format_args!("{:#010x}", self.rest_of_header()))
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::net::{testing, testing::assert_checksums, Ethernet};
+	use std::net::Ipv4Addr;
+
+	/// A ping from 10.0.0.1 to 10.0.0.2 with identifier 0x1234 and sequence 7, both checksums valid
+	fn echo_request() -> Icmpv4<Ethernet> {
+		let frame = testing::echo_request_frame(0x1234, 7, b"ping!");
+		let mut icmp = testing::ipv4(&frame).parse::<Icmpv4<Ethernet>>().unwrap();
+		icmp.compute_checksum().unwrap();
+		icmp
+	}
+
+	#[test]
+	fn parses_the_header() {
+		let icmp = echo_request();
+		assert_eq!(icmp.icmp_type(), IcmpType::ECHO_REQUEST);
+		assert_eq!(icmp.code(), 0);
+		assert_eq!(icmp.identifier(), 0x1234);
+		assert_eq!(icmp.sequence(), 7);
+		assert_eq!(icmp.rest_of_header(), 0x1234_0007);
+		assert_eq!(icmp.payload_len(), 5);
+		assert!(icmp.is_checksum_valid().unwrap());
+		testing::release(icmp.reset());
+	}
+
+	#[test]
+	fn a_changed_payload_invalidates_the_checksum() {
+		let mut icmp = echo_request();
+		let offset = icmp.payload_offset();
+		unsafe { icmp.mbuf_mut().data_address(offset).write(b'P') };
+		assert!(!icmp.is_checksum_valid().unwrap());
+		testing::release(icmp.reset());
+	}
+
+	#[test]
+	fn header_rewrites_keep_the_checksum_valid() {
+		let mut icmp = echo_request();
+		icmp.set_identifier(0xbeef);
+		assert_checksums(&mut icmp, "identifier");
+		icmp.set_sequence(0x0102);
+		assert_checksums(&mut icmp, "sequence");
+		icmp.set_rest_of_header(0xdead_0001);
+		assert_checksums(&mut icmp, "rest of header");
+		assert_eq!((icmp.identifier(), icmp.sequence()), (0xdead, 1));
+		icmp.set_icmp_type(IcmpType::DEST_UNREACHABLE);
+		icmp.set_code(3);
+		assert_checksums(&mut icmp, "type and code");
+		testing::release(icmp.reset());
+	}
+
+	#[test]
+	fn answers_an_echo_request_in_place() {
+		let mut icmp = echo_request();
+		assert!(icmp.make_echo_reply());
+		assert_checksums(&mut icmp, "echo reply");
+		assert_eq!(icmp.icmp_type(), IcmpType::ECHO_REPLY);
+		assert_eq!((icmp.identifier(), icmp.sequence()), (0x1234, 7));
+		assert_eq!(icmp.envelope().src(), Ipv4Addr::new(10, 0, 0, 2));
+		assert_eq!(icmp.envelope().dst(), Ipv4Addr::new(10, 0, 0, 1));
+		assert!(!icmp.make_echo_reply(), "a reply is not answered");
+		testing::release(icmp.reset());
+	}
+}
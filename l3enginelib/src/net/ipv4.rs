@@ -0,0 +1,519 @@
+//! IPv4 packets
+//!
+//! Every setter of `Ipv4` keeps the header checksum valid with an incremental update,
+//! so rewriting a field costs no pass over the header. Only `options_mut` leaves the
+//! checksum to the caller, to be fixed with `compute_checksum`.
+
+use crate::apis::SizeOf;
+use std::{fmt, mem, net::Ipv4Addr, ptr::NonNull, slice};
+
+use super::{checksum, header_word, update_u16, update_u32, Checksum, Datalink, EtherType, Packet, ParseError};
+
+/// The protocol an IP packet carries
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpProtocol(pub u8);
+
+impl IpProtocol {
+	pub const ICMP: IpProtocol = IpProtocol(1);
+	pub const TCP: IpProtocol = IpProtocol(6);
+	pub const UDP: IpProtocol = IpProtocol(17);
+}
+
+impl fmt::Display for IpProtocol {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match *self {
+			IpProtocol::ICMP => write!(f, "ICMP"),
+			IpProtocol::TCP => write!(f, "TCP"),
+			IpProtocol::UDP => write!(f, "UDP"),
+			IpProtocol(other) => write!(f, "{}", other),
+		}
+	}
+}
+
+impl fmt::Debug for IpProtocol {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "IpProtocol({})", self)
+	}
+}
+
+/// An IP layer, which transport layers are parsed from
+pub trait IpPacket: Packet {
+	/// The protocol of the payload
+	fn next_protocol(&self) -> IpProtocol;
+
+	/// Does the payload start with the transport header; false for later fragments
+	fn has_l4_header(&self) -> bool;
+
+	/// Sum of the pseudo header TCP and UDP checksums cover, for a transport layer of `len` bytes
+	fn pseudo_header_sum(&self, len: u16) -> Checksum;
+
+	/// Check that the payload is `expected` before a layer is parsed from it
+	#[inline]
+	fn expect_protocol(&self, expected: IpProtocol, layer: &'static str) -> Result<(), ParseError> {
+		let found = self.next_protocol();
+		if found != expected {
+			return Err(ParseError::IpProtocol(layer, found));
+		}
+		if !self.has_l4_header() {
+			return Err(ParseError::Invalid(layer, String::from("not in the first fragment")));
+		}
+		Ok(())
+	}
+}
+
+/// Fixed part of the IPv4 header as it is on the wire
+#[repr(C, packed)]
+#[derive(Clone, Copy, SizeOf)]
+pub struct Ipv4Header {
+	version_ihl: u8,
+	tos: u8,
+	// big endian, as every multi-byte field
+	total_length: u16,
+	identification: u16,
+	flags_fragment: u16,
+	ttl: u8,
+	protocol: u8,
+	checksum: u16,
+	src: [u8; 4],
+	dst: [u8; 4],
+}
+
+/// Byte offsets of the header words setters touch
+const TOS_WORD: usize = 0;
+const TOTAL_LENGTH_WORD: usize = 2;
+const ID_WORD: usize = 4;
+const FRAGMENT_WORD: usize = 6;
+const TTL_WORD: usize = 8;
+
+const DONT_FRAGMENT: u16 = 0x4000;
+const MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1fff;
+
+/// The IPv4 layer of a packet
+pub struct Ipv4<E: Datalink> {
+	envelope: E,
+	header: NonNull<Ipv4Header>,
+	offset: usize,
+}
+
+impl<E: Datalink> Ipv4<E> {
+	#[inline]
+	fn header(&self) -> &Ipv4Header {
+		unsafe { self.header.as_ref() }
+	}
+
+	#[inline]
+	fn header_mut(&mut self) -> &mut Ipv4Header {
+		unsafe { self.header.as_mut() }
+	}
+
+	/// Apply `f` to the header and fix the checksum for the word at byte `at` it changed
+	#[inline]
+	fn rewrite<F: FnOnce(&mut Ipv4Header)>(&mut self, at: usize, f: F) {
+		let old = unsafe { header_word(self.header, at) };
+		f(self.header_mut());
+		let new = unsafe { header_word(self.header, at) };
+		let cksum = update_u16(self.checksum(), old, new);
+		self.header_mut().checksum = cksum.to_be();
+	}
+
+	#[inline]
+	pub fn version(&self) -> u8 {
+		self.header().version_ihl >> 4
+	}
+
+	/// Header length in 32 bit words, options included
+	///
+	/// There is no setter: options cannot be added or removed in place.
+	#[inline]
+	pub fn ihl(&self) -> u8 {
+		self.header().version_ihl & 0x0f
+	}
+
+	/// Differentiated services code point
+	#[inline]
+	pub fn dscp(&self) -> u8 {
+		self.header().tos >> 2
+	}
+
+	#[inline]
+	pub fn set_dscp(&mut self, dscp: u8) {
+		self.rewrite(TOS_WORD, |h| h.tos = (h.tos & 0x03) | (dscp << 2));
+	}
+
+	/// Explicit congestion notification
+	#[inline]
+	pub fn ecn(&self) -> u8 {
+		self.header().tos & 0x03
+	}
+
+	#[inline]
+	pub fn set_ecn(&mut self, ecn: u8) {
+		self.rewrite(TOS_WORD, |h| h.tos = (h.tos & !0x03) | (ecn & 0x03));
+	}
+
+	/// Length of the packet, header included
+	#[inline]
+	pub fn total_length(&self) -> u16 {
+		u16::from_be(self.header().total_length)
+	}
+
+	#[inline]
+	pub fn set_total_length(&mut self, len: u16) {
+		self.rewrite(TOTAL_LENGTH_WORD, |h| h.total_length = len.to_be());
+	}
+
+	#[inline]
+	pub fn identification(&self) -> u16 {
+		u16::from_be(self.header().identification)
+	}
+
+	#[inline]
+	pub fn set_identification(&mut self, id: u16) {
+		self.rewrite(ID_WORD, |h| h.identification = id.to_be());
+	}
+
+	#[inline]
+	fn flags_fragment(&self) -> u16 {
+		u16::from_be(self.header().flags_fragment)
+	}
+
+	#[inline]
+	fn set_flags_fragment(&mut self, mask: u16, value: u16) {
+		let word = (self.flags_fragment() & !mask) | (value & mask);
+		self.rewrite(FRAGMENT_WORD, |h| h.flags_fragment = word.to_be());
+	}
+
+	#[inline]
+	pub fn dont_fragment(&self) -> bool {
+		self.flags_fragment() & DONT_FRAGMENT != 0
+	}
+
+	#[inline]
+	pub fn set_dont_fragment(&mut self, on: bool) {
+		self.set_flags_fragment(DONT_FRAGMENT, if on { DONT_FRAGMENT } else { 0 });
+	}
+
+	#[inline]
+	pub fn more_fragments(&self) -> bool {
+		self.flags_fragment() & MORE_FRAGMENTS != 0
+	}
+
+	#[inline]
+	pub fn set_more_fragments(&mut self, on: bool) {
+		self.set_flags_fragment(MORE_FRAGMENTS, if on { MORE_FRAGMENTS } else { 0 });
+	}
+
+	/// Offset of the fragment in the original payload, in units of 8 bytes
+	#[inline]
+	pub fn fragment_offset(&self) -> u16 {
+		self.flags_fragment() & FRAGMENT_OFFSET
+	}
+
+	#[inline]
+	pub fn set_fragment_offset(&mut self, offset: u16) {
+		self.set_flags_fragment(FRAGMENT_OFFSET, offset);
+	}
+
+	/// Is the packet a fragment of a larger one
+	#[inline]
+	pub fn is_fragment(&self) -> bool {
+		self.more_fragments() || self.fragment_offset() != 0
+	}
+
+	#[inline]
+	pub fn ttl(&self) -> u8 {
+		self.header().ttl
+	}
+
+	#[inline]
+	pub fn set_ttl(&mut self, ttl: u8) {
+		self.rewrite(TTL_WORD, |h| h.ttl = ttl);
+	}
+
+	/// Decrements the TTL and returns what is left, or `None` if it was already 0
+	///
+	/// A router forwards the packet only if the result is above 0.
+	#[inline]
+	pub fn decrement_ttl(&mut self) -> Option<u8> {
+		let ttl = self.ttl().checked_sub(1)?;
+		self.set_ttl(ttl);
+		Some(ttl)
+	}
+
+	#[inline]
+	pub fn protocol(&self) -> IpProtocol {
+		IpProtocol(self.header().protocol)
+	}
+
+	#[inline]
+	pub fn set_protocol(&mut self, protocol: IpProtocol) {
+		self.rewrite(TTL_WORD, |h| h.protocol = protocol.0);
+	}
+
+	#[inline]
+	pub fn checksum(&self) -> u16 {
+		u16::from_be(self.header().checksum)
+	}
+
+	/// Sets the header checksum as is; see `compute_checksum` to fill in the right one
+	#[inline]
+	pub fn set_checksum(&mut self, cksum: u16) {
+		self.header_mut().checksum = cksum.to_be();
+	}
+
+	#[inline]
+	pub fn src(&self) -> Ipv4Addr {
+		Ipv4Addr::from(self.header().src)
+	}
+
+	/// Sets the source address; checksums of a transport layer covering it are not updated
+	#[inline]
+	pub fn set_src(&mut self, ip: Ipv4Addr) {
+		let cksum = update_u32(self.checksum(), u32::from(self.src()), u32::from(ip));
+		let header = self.header_mut();
+		header.src = ip.octets();
+		header.checksum = cksum.to_be();
+	}
+
+	#[inline]
+	pub fn dst(&self) -> Ipv4Addr {
+		Ipv4Addr::from(self.header().dst)
+	}
+
+	/// Sets the destination address; checksums of a transport layer covering it are not updated
+	#[inline]
+	pub fn set_dst(&mut self, ip: Ipv4Addr) {
+		let cksum = update_u32(self.checksum(), u32::from(self.dst()), u32::from(ip));
+		let header = self.header_mut();
+		header.dst = ip.octets();
+		header.checksum = cksum.to_be();
+	}
+
+	/// Swaps the source and destination addresses, e.g. to send a reply back
+	///
+	/// The sum of the addresses stays the same, and so do the checksums.
+	#[inline]
+	pub fn swap_addresses(&mut self) {
+		let header = self.header_mut();
+		// byte arrays, so the packed fields can be borrowed
+		mem::swap(&mut header.src, &mut header.dst);
+	}
+
+	/// Options after the fixed header
+	#[inline]
+	pub fn options(&self) -> &[u8] {
+		let len = self.header_len() - Ipv4Header::size_of();
+		unsafe { slice::from_raw_parts((self.header.as_ptr() as *const u8).add(Ipv4Header::size_of()), len) }
+	}
+
+	/// Options after the fixed header, for updating; the checksum is left to `compute_checksum`
+	#[inline]
+	pub fn options_mut(&mut self) -> &mut [u8] {
+		let len = self.header_len() - Ipv4Header::size_of();
+		unsafe { slice::from_raw_parts_mut((self.header.as_ptr() as *mut u8).add(Ipv4Header::size_of()), len) }
+	}
+
+	#[inline]
+	fn header_bytes(&self) -> &[u8] {
+		unsafe { slice::from_raw_parts(self.header.as_ptr() as *const u8, self.header_len()) }
+	}
+
+	/// Computes the header checksum over the whole header and stores it
+	pub fn compute_checksum(&mut self) {
+		self.set_checksum(0);
+		let cksum = checksum(self.header_bytes());
+		self.set_checksum(cksum);
+	}
+
+	/// Does the header checksum match the header
+	pub fn is_checksum_valid(&self) -> bool {
+		checksum(self.header_bytes()) == 0
+	}
+}
+
+impl<E: Datalink> Packet for Ipv4<E> {
+	type Envelope = E;
+
+	#[inline]
+	fn envelope(&self) -> &Self::Envelope {
+		&self.envelope
+	}
+
+	#[inline]
+	fn envelope_mut(&mut self) -> &mut Self::Envelope {
+		&mut self.envelope
+	}
+
+	#[inline]
+	fn offset(&self) -> usize {
+		self.offset
+	}
+
+	#[inline]
+	fn header_len(&self) -> usize {
+		self.ihl() as usize * 4
+	}
+
+	/// Length of the payload as the header tells it, without any link layer padding
+	#[inline]
+	fn payload_len(&self) -> usize {
+		(self.total_length() as usize).saturating_sub(self.header_len())
+	}
+
+	fn try_parse(envelope: Self::Envelope) -> Result<Self, ParseError> {
+		envelope.expect_ether_type(EtherType::IPV4, "IPv4")?;
+		let offset = envelope.payload_offset();
+		let header = envelope.mbuf().read_data::<Ipv4Header>(offset)?;
+		let (version_ihl, total_length) = unsafe {
+			let h = header.as_ref();
+			(h.version_ihl, u16::from_be(h.total_length) as usize)
+		};
+		if version_ihl >> 4 != 4 {
+			return Err(ParseError::Invalid("IPv4", format!("version {}", version_ihl >> 4)));
+		}
+		let header_len = (version_ihl & 0x0f) as usize * 4;
+		if header_len < Ipv4Header::size_of() {
+			return Err(ParseError::Invalid("IPv4", format!("header length {}", header_len)));
+		}
+		// the options have to be in the first segment as well
+		envelope.mbuf().read_data_slice::<u8>(offset, header_len)?;
+		if total_length < header_len || offset + total_length > envelope.mbuf().pkt_len() {
+			return Err(ParseError::Invalid(
+				"IPv4",
+				format!(
+					"total length {} with a {} byte header in {} bytes",
+					total_length,
+					header_len,
+					envelope.mbuf().pkt_len() - offset
+				),
+			));
+		}
+		Ok(Self {
+			envelope,
+			header,
+			offset,
+		})
+	}
+
+	#[inline]
+	fn deparse(self) -> Self::Envelope {
+		self.envelope
+	}
+}
+
+impl<E: Datalink> IpPacket for Ipv4<E> {
+	#[inline]
+	fn next_protocol(&self) -> IpProtocol {
+		self.protocol()
+	}
+
+	#[inline]
+	fn has_l4_header(&self) -> bool {
+		self.fragment_offset() == 0
+	}
+
+	fn pseudo_header_sum(&self, len: u16) -> Checksum {
+		let mut sum = Checksum::new();
+		sum.add_bytes(&self.header().src);
+		sum.add_bytes(&self.header().dst);
+		sum.add_u16(self.protocol().0 as u16);
+		sum.add_u16(len);
+		sum
+	}
+}
+
+impl<E: Datalink> fmt::Debug for Ipv4<E> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Ipv4")
+			.field("src", &self.src())
+			.field("dst", &self.dst())
+			.field("protocol", &self.protocol())
+			.field("ttl", &self.ttl())
+			.field("total_length", &self.total_length())
+			.field("ihl", &self.ihl())
+			.field("dscp", &self.dscp())
+			.field("ecn", &self.ecn())
+			.field("identification", &self.identification())
+			.field("dont_fragment", &self.dont_fragment())
+			.field("more_fragments", &self.more_fragments())
+			.field("fragment_offset", &self.fragment_offset())
+			.field("checksum", &format_args!("{:#06x}", self.checksum()))
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::net::{testing, Ethernet};
+
+	fn parse(frame: &[u8]) -> Ipv4<Ethernet> {
+		Ethernet::parse(testing::mbuf(frame)).unwrap().parse::<Ipv4<Ethernet>>().unwrap()
+	}
+
+	/// The stored checksum is valid and the one a full computation gives
+	fn assert_checksum(ip: &mut Ipv4<Ethernet>, what: &str) {
+		assert!(ip.is_checksum_valid(), "{}", what);
+		let updated = ip.checksum();
+		ip.compute_checksum();
+		assert_eq!(updated, ip.checksum(), "{}", what);
+	}
+
+	#[test]
+	fn parses_the_header() {
+		let ip = parse(&testing::ipv4_frame(IpProtocol::UDP, &[0; 8]));
+		assert_eq!(ip.version(), 4);
+		assert_eq!(ip.header_len(), 20);
+		assert_eq!(ip.total_length(), 28);
+		assert_eq!(ip.payload_len(), 8);
+		assert_eq!(ip.protocol(), IpProtocol::UDP);
+		assert_eq!(ip.src(), Ipv4Addr::new(10, 0, 0, 1));
+		assert_eq!(ip.dst(), Ipv4Addr::new(10, 0, 0, 2));
+		assert!(ip.dont_fragment());
+		testing::release(ip.reset());
+	}
+
+	#[test]
+	fn computes_a_valid_checksum() {
+		let mut ip = parse(&testing::ipv4_frame(IpProtocol::UDP, &[0; 8]));
+		assert!(!ip.is_checksum_valid());
+		ip.compute_checksum();
+		assert!(ip.is_checksum_valid());
+		// !(0x4500 + 0x001c + 0x1c46 + 0x4000 + 0x4011 + 0x0a00 + 0x0001 + 0x0a00 + 0x0002)
+		assert_eq!(ip.checksum(), 0x0a89);
+		testing::release(ip.reset());
+	}
+
+	#[test]
+	fn setters_keep_the_checksum_valid() {
+		let mut ip = parse(&testing::ipv4_frame(IpProtocol::TCP, &[0; 20]));
+		ip.compute_checksum();
+
+		ip.set_ttl(1);
+		assert_checksum(&mut ip, "ttl");
+		assert_eq!(ip.decrement_ttl(), Some(0));
+		assert_checksum(&mut ip, "decremented ttl");
+		assert_eq!(ip.decrement_ttl(), None);
+		ip.set_dscp(46);
+		assert_checksum(&mut ip, "dscp");
+		ip.set_ecn(3);
+		assert_checksum(&mut ip, "ecn");
+		ip.set_identification(0xbeef);
+		assert_checksum(&mut ip, "identification");
+		ip.set_dont_fragment(false);
+		ip.set_more_fragments(true);
+		ip.set_fragment_offset(0x1234);
+		assert_checksum(&mut ip, "fragment");
+		ip.set_protocol(IpProtocol::UDP);
+		assert_checksum(&mut ip, "protocol");
+		ip.set_src(Ipv4Addr::new(192, 168, 255, 254));
+		assert_checksum(&mut ip, "src");
+		ip.set_dst(Ipv4Addr::new(172, 16, 0, 1));
+		assert_checksum(&mut ip, "dst");
+		ip.swap_addresses();
+		assert_checksum(&mut ip, "swapped addresses");
+		assert_eq!(ip.src(), Ipv4Addr::new(172, 16, 0, 1));
+		testing::release(ip.reset());
+	}
+}
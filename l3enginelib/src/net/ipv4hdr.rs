@@ -1,26 +1,52 @@
-use crate::apis::Mbuf;
-use std::net::Ipv4Addr;
+use crate::apis::{Mbuf, SizeOf};
+use std::{mem, net::Ipv4Addr, ptr};
 use dpdk_sys;
 
+use super::{EtherType, EthernetHeader, ParseError, VlanHeader};
+
+/// A copy of the IPv4 header of a packet
 pub struct Ipv4Hdr(dpdk_sys::rte_ipv4_hdr);
 
 impl Ipv4Hdr {
 	/// Get IPv4 header from mbuf
-	pub fn from_mbuf(buf: &mut Mbuf) -> Self {
-		Self(unsafe {*(dpdk_sys::_pkt_ipv4_hdr(buf.get_ptr()) as *mut dpdk_sys::rte_ipv4_hdr)})
+	///
+	/// VLAN tags after the Ethernet header are skipped. Fails if the packet is not
+	/// IPv4 or too short for the header.
+	pub fn from_mbuf(buf: &Mbuf) -> Result<Self, ParseError> {
+		// the ether type closes the Ethernet header and every VLAN tag
+		let mut offset = EthernetHeader::size_of();
+		let mut ether_type = [0u8; 2];
+		buf.read_bytes(offset - ether_type.len(), &mut ether_type)?;
+		while EtherType(u16::from_be_bytes(ether_type)).is_vlan() {
+			offset += VlanHeader::size_of();
+			buf.read_bytes(offset - ether_type.len(), &mut ether_type)?;
+		}
+		let found = EtherType(u16::from_be_bytes(ether_type));
+		if found != EtherType::IPV4 {
+			return Err(ParseError::EtherType("IPv4", found));
+		}
+
+		let mut bytes = [0u8; mem::size_of::<dpdk_sys::rte_ipv4_hdr>()];
+		buf.read_bytes(offset, &mut bytes)?;
+		let hdr = unsafe { ptr::read_unaligned(bytes.as_ptr() as *const dpdk_sys::rte_ipv4_hdr) };
+		if hdr.version_ihl >> 4 != 4 {
+			return Err(ParseError::Invalid("IPv4", format!("version {}", hdr.version_ihl >> 4)));
+		}
+		Ok(Self(hdr))
 	}
 
 	fn convert(&self, ip: u32) -> Ipv4Addr {
-		Ipv4Addr::new(((ip >> 24) & 0xFF) as u8, ((ip >> 16) & 0xFF) as u8, ((ip >> 8) & 0xFF) as u8, (ip & 0xFF) as u8,)
+		// addresses are kept in network byte order
+		Ipv4Addr::from(u32::from_be(ip))
 	}
 
 	/// Get source address
 	pub fn get_src_addr(&self) -> Ipv4Addr {
-		(&self).convert(self.0.src_addr)
+		self.convert(self.0.src_addr)
 	}
 
 	/// Get destination address
 	pub fn get_dst_addr(&self) -> Ipv4Addr {
-		(&self).convert(self.0.dst_addr)
+		self.convert(self.0.dst_addr)
 	}
-}
\ No newline at end of file
+}
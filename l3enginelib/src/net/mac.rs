@@ -1,17 +1,23 @@
-// development flags - remove in production
-#![allow(dead_code)]
-#![allow(unused_imports)]
-
 use super::MacError;
-use crate::apis::{Mbuf, Port};
-use std::{result::Result, fmt};
+use crate::apis::SizeOf;
 use dpdk_sys;
-use smoltcp::wire::EthernetAddress;
-use pnet::datalink::MacAddr;
+use serde::{de, Deserialize, Deserializer};
+use std::{fmt, str::FromStr};
 
-pub struct MacAddr([u8; 6]);
+/// An ethernet MAC address
+#[repr(transparent)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, SizeOf)]
+pub struct MacAddr(pub [u8; 6]);
 
 impl MacAddr {
+	pub const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+	pub const UNSPECIFIED: MacAddr = MacAddr([0; 6]);
+
+	#[inline]
+	pub fn new(a: u8, b: u8, c: u8, d: u8, e: u8, f: u8) -> Self {
+		Self([a, b, c, d, e, f])
+	}
+
 	#[inline]
 	pub fn into_ether_addr(mac: Self) -> dpdk_sys::rte_ether_addr {
 		dpdk_sys::rte_ether_addr { addr_bytes: mac.0 }
@@ -22,13 +28,71 @@ impl MacAddr {
 		Self(mac.addr_bytes)
 	}
 
-	pub fn to_ethernetaddr(&self) -> EthernetAddress {
-		EthernetAddress::from_bytes(&self.0)
+	/// Returns the six bytes of the address
+	#[inline]
+	pub fn octets(&self) -> [u8; 6] {
+		self.0
+	}
+
+	#[inline]
+	pub fn is_broadcast(&self) -> bool {
+		*self == Self::BROADCAST
+	}
+
+	/// Returns true for group addresses, broadcast included
+	#[inline]
+	pub fn is_multicast(&self) -> bool {
+		self.0[0] & 0x01 != 0
+	}
+}
+
+impl From<[u8; 6]> for MacAddr {
+	fn from(bytes: [u8; 6]) -> Self {
+		Self(bytes)
+	}
+}
+
+impl FromStr for MacAddr {
+	type Err = MacError;
+
+	/// Parse an address in `aa:bb:cc:dd:ee:ff` notation
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut bytes = [0u8; 6];
+		let mut parts = s.split(':');
+		for byte in bytes.iter_mut() {
+			*byte = parts
+				.next()
+				.filter(|part| part.len() == 2)
+				.and_then(|part| u8::from_str_radix(part, 16).ok())
+				.ok_or_else(|| MacError::BadFormat(String::from(s)))?;
+		}
+		if parts.next().is_some() {
+			return Err(MacError::BadFormat(String::from(s)));
+		}
+		Ok(Self(bytes))
+	}
+}
+
+impl<'de> Deserialize<'de> for MacAddr {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let s = String::deserialize(deserializer)?;
+		s.parse().map_err(de::Error::custom)
+	}
+}
+
+impl fmt::Display for MacAddr {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let b = &self.0;
+		write!(
+			f,
+			"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+			b[0], b[1], b[2], b[3], b[4], b[5]
+		)
 	}
 }
 
 impl fmt::Debug for MacAddr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		f.debug_struct(&format!("{}", self.to_ethernetaddr())).finish()
-    }
-}
\ No newline at end of file
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "MacAddr({})", self)
+	}
+}
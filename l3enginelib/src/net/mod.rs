@@ -1,21 +1,58 @@
+//! Packet headers, parsed in Rust straight from the mbuf
+//!
+//! Every protocol is a layer implementing `Packet`: parsing starts with
+//! `Ethernet::parse(mbuf)` and goes up one layer at a time with `parse`.
+//! Headers are read through `Mbuf::read_data`, so a parse fails on a packet
+//! too short for the header instead of reading past its data.
+
+mod arp;
+mod checksum;
+mod ethernet;
+mod icmpv4;
+mod ipv4;
 mod ipv4hdr;
-// mod mac;
-// mod arp;
+mod mac;
+mod packet;
+mod tcp;
+mod udp;
+mod vlan;
 
+pub use arp::*;
+pub use checksum::*;
+pub use ethernet::*;
+pub use icmpv4::*;
+pub use ipv4::*;
 pub use ipv4hdr::*;
-// pub use mac::*;
-// pub use arp::*;
+pub use mac::*;
+pub use packet::*;
+pub use tcp::*;
+pub use udp::*;
+pub use vlan::*;
 
+use crate::apis::BufError;
 use dpdk_sys;
 use libc::ENODEV;
+use std::ptr::{self, NonNull};
 use thiserror::Error;
 
+/// Big endian 16 bit word at byte `at` of a header
+///
+/// # Safety
+///
+/// `header` must point to at least `at + 2` readable bytes
+#[inline]
+unsafe fn header_word<T>(header: NonNull<T>, at: usize) -> u16 {
+	u16::from_be(ptr::read_unaligned((header.as_ptr() as *const u8).add(at) as *const u16))
+}
+
 #[derive(Debug, Error)]
 pub enum MacError {
 	#[error("invalid port id: {}", _0)]
 	InvalidID(u8),
 	#[error("Other error: {}", _0)]
 	OtherErr(i32),
+	#[error("invalid mac address: {}", _0)]
+	BadFormat(String),
 }
 
 impl MacError {
@@ -27,3 +64,136 @@ impl MacError {
 		}
 	}
 }
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+	/// The header does not fit in the packet's first segment
+	#[error("header out of bounds: {}", _0)]
+	Buf(#[from] BufError),
+	/// The layer below announces another protocol
+	#[error("ether type {} does not carry {}", _1, _0)]
+	EtherType(&'static str, EtherType),
+	/// The IP layer below announces another protocol
+	#[error("IP protocol {} does not carry {}", _1, _0)]
+	IpProtocol(&'static str, IpProtocol),
+	/// The header is malformed or describes something unsupported
+	#[error("bad {} header: {}", _0, _1)]
+	Invalid(&'static str, String),
+}
+
+/// Packets built without DPDK for the tests of the layers
+#[cfg(test)]
+pub(crate) mod testing {
+	use super::{Ethernet, Icmpv4, IpProtocol, Ipv4, Packet, ParseError, Tcp, Udp};
+	use crate::apis::Mbuf;
+	use std::mem;
+
+	const HEADROOM: usize = 128;
+
+	/// A single segment mbuf over a copy of `frame`
+	///
+	/// The mbuf comes from no mempool. It holds an extra reference, so dropping it once,
+	/// as a failed parse does, only drops that reference; `release` leaks it instead.
+	pub(crate) fn mbuf(frame: &[u8]) -> Mbuf {
+		let buf = Box::leak(vec![0u8; HEADROOM + frame.len()].into_boxed_slice());
+		buf[HEADROOM..].copy_from_slice(frame);
+		let raw: &mut dpdk_sys::rte_mbuf = Box::leak(Box::new(unsafe { mem::zeroed() }));
+		raw.buf_addr = buf.as_mut_ptr() as *mut _;
+		raw.buf_len = buf.len() as u16;
+		raw.data_off = HEADROOM as u16;
+		raw.data_len = frame.len() as u16;
+		raw.pkt_len = frame.len() as u32;
+		raw.nb_segs = 1;
+		raw.__bindgen_anon_2.refcnt = 2;
+		unsafe { Mbuf::from_ptr(raw) }
+	}
+
+	/// Leak an mbuf made by `mbuf` instead of dropping it
+	pub(crate) fn release(mbuf: Mbuf) {
+		mbuf.into_ptr();
+	}
+
+	/// An Ethernet frame carrying `l4` in an IPv4 packet from 10.0.0.1 to 10.0.0.2,
+	/// with a zero header checksum
+	pub(crate) fn ipv4_frame(protocol: IpProtocol, l4: &[u8]) -> Vec<u8> {
+		let mut frame = vec![
+			0x02, 0, 0, 0, 0, 0x01, 0x02, 0, 0, 0, 0, 0x02, 0x08, 0x00, // ethernet
+			0x45, 0x00, 0, 0, 0x1c, 0x46, 0x40, 0x00, 64, protocol.0, 0, 0, // ipv4
+			10, 0, 0, 1, 10, 0, 0, 2,
+		];
+		let total = (20 + l4.len()) as u16;
+		frame[16..18].copy_from_slice(&total.to_be_bytes());
+		frame.extend_from_slice(l4);
+		frame
+	}
+
+	/// The IPv4 packet of `frame`, its header checksum filled in
+	pub(crate) fn ipv4(frame: &[u8]) -> Ipv4<Ethernet> {
+		let mut ip = Ethernet::parse(mbuf(frame)).unwrap().parse::<Ipv4<Ethernet>>().unwrap();
+		ip.compute_checksum();
+		ip
+	}
+
+	/// A SYN from port 4660 to port 80 carrying `payload`, with a zero checksum
+	pub(crate) fn tcp_frame(payload: &[u8]) -> Vec<u8> {
+		let mut l4 = vec![
+			0x12, 0x34, 0x00, 0x50, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0,
+		];
+		l4.extend_from_slice(payload);
+		ipv4_frame(IpProtocol::TCP, &l4)
+	}
+
+	/// A datagram from port 5353 to port 53 carrying `payload`, without checksum
+	pub(crate) fn udp_frame(payload: &[u8]) -> Vec<u8> {
+		let len = (8 + payload.len()) as u16;
+		let mut l4 = vec![0x14, 0xe9, 0x00, 0x35, 0, 0, 0, 0];
+		l4[4..6].copy_from_slice(&len.to_be_bytes());
+		l4.extend_from_slice(payload);
+		ipv4_frame(IpProtocol::UDP, &l4)
+	}
+
+	/// An echo request with `id` and `seq` carrying `payload`, with a zero checksum
+	pub(crate) fn echo_request_frame(id: u16, seq: u16, payload: &[u8]) -> Vec<u8> {
+		let mut l4 = vec![8, 0, 0, 0];
+		l4.extend_from_slice(&id.to_be_bytes());
+		l4.extend_from_slice(&seq.to_be_bytes());
+		l4.extend_from_slice(payload);
+		ipv4_frame(IpProtocol::ICMP, &l4)
+	}
+
+	/// A message over IPv4 with a checksum of its own
+	pub(crate) trait Checksummed: Packet<Envelope = Ipv4<Ethernet>> {
+		fn checksum(&self) -> u16;
+		fn is_checksum_valid(&self) -> Result<bool, ParseError>;
+		fn compute_checksum(&mut self) -> Result<(), ParseError>;
+	}
+
+	macro_rules! checksummed {
+		($($packet:ident<$envelope:ty>),*) => {$(
+			impl Checksummed for $packet<$envelope> {
+				fn checksum(&self) -> u16 {
+					$packet::checksum(self)
+				}
+
+				fn is_checksum_valid(&self) -> Result<bool, ParseError> {
+					$packet::is_checksum_valid(self)
+				}
+
+				fn compute_checksum(&mut self) -> Result<(), ParseError> {
+					$packet::compute_checksum(self)
+				}
+			}
+		)*};
+	}
+
+	checksummed!(Tcp<Ipv4<Ethernet>>, Udp<Ipv4<Ethernet>>, Icmpv4<Ethernet>);
+
+	/// Both checksums are valid and the stored one of `packet` is the one a full computation gives
+	pub(crate) fn assert_checksums<P: Checksummed>(packet: &mut P, what: &str) {
+		assert!(packet.envelope().is_checksum_valid(), "ip, {}", what);
+		assert!(packet.is_checksum_valid().unwrap(), "{}", what);
+		let updated = packet.checksum();
+		packet.compute_checksum().unwrap();
+		assert_eq!(updated, packet.checksum(), "{}", what);
+	}
+}
@@ -0,0 +1,133 @@
+//! The `Packet` trait every protocol layer implements
+//!
+//! A packet is parsed one layer at a time, each layer taking the one below it as its
+//! envelope:
+//!
+//! ```ignore
+//! let ether = Ethernet::parse(mbuf)?;
+//! let arp = ether.parse::<Arp<Ethernet>>()?;
+//! ```
+//!
+//! Parsing a layer checks that the envelope announces it and that its header lies within
+//! the first segment of the mbuf, so header accessors need no further checks.
+//! `deparse` gives the envelope back and `reset` the mbuf.
+//! A failed parse drops the packet along with the envelope it was given.
+
+use crate::apis::Mbuf;
+
+use super::ParseError;
+
+/// A protocol layer of a packet
+pub trait Packet: Sized {
+	/// The layer this one is parsed from
+	type Envelope: Packet;
+
+	/// Returns the layer this one was parsed from
+	fn envelope(&self) -> &Self::Envelope;
+
+	/// Returns the layer this one was parsed from, for updating
+	fn envelope_mut(&mut self) -> &mut Self::Envelope;
+
+	/// Returns the mbuf holding the packet
+	#[inline]
+	fn mbuf(&self) -> &Mbuf {
+		self.envelope().mbuf()
+	}
+
+	/// Returns the mbuf holding the packet, for updating
+	///
+	/// # Safety
+	///
+	/// Parsed layers point into the data of the mbuf: the caller must not move,
+	/// shorten or remove the headers of this layer or the ones below it.
+	#[inline]
+	unsafe fn mbuf_mut(&mut self) -> &mut Mbuf {
+		self.envelope_mut().mbuf_mut()
+	}
+
+	/// Offset of the layer's header from the start of the packet
+	fn offset(&self) -> usize;
+
+	/// Length of the layer's header
+	fn header_len(&self) -> usize;
+
+	/// Offset of what follows the layer's header
+	#[inline]
+	fn payload_offset(&self) -> usize {
+		self.offset() + self.header_len()
+	}
+
+	/// Length of what follows the layer's header, up to the end of the packet
+	#[inline]
+	fn payload_len(&self) -> usize {
+		self.mbuf().pkt_len().saturating_sub(self.payload_offset())
+	}
+
+	/// Parses the layer from the payload of `envelope`
+	fn try_parse(envelope: Self::Envelope) -> Result<Self, ParseError>;
+
+	/// Parses the next layer from the payload of this one
+	#[inline]
+	fn parse<T: Packet<Envelope = Self>>(self) -> Result<T, ParseError> {
+		T::try_parse(self)
+	}
+
+	/// Returns the layer this one was parsed from
+	fn deparse(self) -> Self::Envelope;
+
+	/// Returns the mbuf, dropping every parsed layer
+	#[inline]
+	fn reset(self) -> Mbuf {
+		self.deparse().reset()
+	}
+}
+
+/// The mbuf itself is the bottom layer, without a header of its own
+impl Packet for Mbuf {
+	type Envelope = Mbuf;
+
+	#[inline]
+	fn envelope(&self) -> &Self::Envelope {
+		self
+	}
+
+	#[inline]
+	fn envelope_mut(&mut self) -> &mut Self::Envelope {
+		self
+	}
+
+	#[inline]
+	fn mbuf(&self) -> &Mbuf {
+		self
+	}
+
+	#[inline]
+	unsafe fn mbuf_mut(&mut self) -> &mut Mbuf {
+		self
+	}
+
+	#[inline]
+	fn offset(&self) -> usize {
+		0
+	}
+
+	#[inline]
+	fn header_len(&self) -> usize {
+		0
+	}
+
+	#[inline]
+	fn try_parse(envelope: Self::Envelope) -> Result<Self, ParseError> {
+		Ok(envelope)
+	}
+
+	#[inline]
+	fn deparse(self) -> Self::Envelope {
+		self
+	}
+
+	#[inline]
+	fn reset(self) -> Mbuf {
+		self
+	}
+}
@@ -0,0 +1,388 @@
+//! TCP segments
+//!
+//! Setters keep the checksum valid with incremental updates; on a segment carried by
+//! IPv4, `set_src_addr` and `set_dst_addr` rewrite an address in the IP header and fix
+//! both checksums, as a NAT does.
+
+use crate::apis::SizeOf;
+use std::{fmt, net::Ipv4Addr, ptr::NonNull, slice};
+
+use super::{
+	header_word, update_u16, update_u32, Datalink, IpPacket, IpProtocol, Ipv4, Packet, ParseError,
+};
+
+/// Fixed part of the TCP header as it is on the wire
+#[repr(C, packed)]
+#[derive(Clone, Copy, SizeOf)]
+pub struct TcpHeader {
+	// big endian, as every multi-byte field
+	src_port: u16,
+	dst_port: u16,
+	seq_no: u32,
+	ack_no: u32,
+	// data offset, reserved bits and flags
+	offset_flags: u16,
+	window: u16,
+	checksum: u16,
+	urgent_pointer: u16,
+}
+
+/// Byte offset of the data offset and flags word
+const FLAGS_WORD: usize = 12;
+
+/// The TCP layer of a packet
+pub struct Tcp<E: IpPacket> {
+	envelope: E,
+	header: NonNull<TcpHeader>,
+	offset: usize,
+}
+
+/// Getter and setter of every flag, by mask in the data offset and flags word
+macro_rules! tcp_flags {
+	($($get:ident, $set:ident, $mask:expr;)*) => {
+		$(
+			#[inline]
+			pub fn $get(&self) -> bool {
+				self.offset_flags() & $mask != 0
+			}
+
+			#[inline]
+			pub fn $set(&mut self, on: bool) {
+				let word = if on { self.offset_flags() | $mask } else { self.offset_flags() & !$mask };
+				self.set_word(FLAGS_WORD, |h| h.offset_flags = word.to_be());
+			}
+		)*
+	};
+}
+
+impl<E: IpPacket> Tcp<E> {
+	#[inline]
+	fn header(&self) -> &TcpHeader {
+		unsafe { self.header.as_ref() }
+	}
+
+	#[inline]
+	fn header_mut(&mut self) -> &mut TcpHeader {
+		unsafe { self.header.as_mut() }
+	}
+
+	/// Apply `f` to the header and fix the checksum for the word at byte `at` it changed
+	#[inline]
+	fn set_word<F: FnOnce(&mut TcpHeader)>(&mut self, at: usize, f: F) {
+		let old = unsafe { header_word(self.header, at) };
+		f(self.header_mut());
+		let new = unsafe { header_word(self.header, at) };
+		let cksum = update_u16(self.checksum(), old, new);
+		self.header_mut().checksum = cksum.to_be();
+	}
+
+	/// Fix the checksum for a 32 bit word it covers changing from `old` to `new`
+	#[inline]
+	fn update_checksum_u32(&mut self, old: u32, new: u32) {
+		let cksum = update_u32(self.checksum(), old, new);
+		self.header_mut().checksum = cksum.to_be();
+	}
+
+	#[inline]
+	pub fn src_port(&self) -> u16 {
+		u16::from_be(self.header().src_port)
+	}
+
+	#[inline]
+	pub fn set_src_port(&mut self, port: u16) {
+		self.set_word(0, |h| h.src_port = port.to_be());
+	}
+
+	#[inline]
+	pub fn dst_port(&self) -> u16 {
+		u16::from_be(self.header().dst_port)
+	}
+
+	#[inline]
+	pub fn set_dst_port(&mut self, port: u16) {
+		self.set_word(2, |h| h.dst_port = port.to_be());
+	}
+
+	#[inline]
+	pub fn seq_no(&self) -> u32 {
+		u32::from_be(self.header().seq_no)
+	}
+
+	#[inline]
+	pub fn set_seq_no(&mut self, seq_no: u32) {
+		self.update_checksum_u32(self.seq_no(), seq_no);
+		self.header_mut().seq_no = seq_no.to_be();
+	}
+
+	#[inline]
+	pub fn ack_no(&self) -> u32 {
+		u32::from_be(self.header().ack_no)
+	}
+
+	#[inline]
+	pub fn set_ack_no(&mut self, ack_no: u32) {
+		self.update_checksum_u32(self.ack_no(), ack_no);
+		self.header_mut().ack_no = ack_no.to_be();
+	}
+
+	#[inline]
+	fn offset_flags(&self) -> u16 {
+		u16::from_be(self.header().offset_flags)
+	}
+
+	/// Header length in 32 bit words, options included
+	///
+	/// There is no setter: options cannot be added or removed in place.
+	#[inline]
+	pub fn data_offset(&self) -> u8 {
+		(self.offset_flags() >> 12) as u8
+	}
+
+	/// The nine flag bits, `FIN` in the lowest
+	#[inline]
+	pub fn flags(&self) -> u16 {
+		self.offset_flags() & 0x01ff
+	}
+
+	#[inline]
+	pub fn set_flags(&mut self, flags: u16) {
+		let word = (self.offset_flags() & !0x01ff) | (flags & 0x01ff);
+		self.set_word(FLAGS_WORD, |h| h.offset_flags = word.to_be());
+	}
+
+	tcp_flags! {
+		ns, set_ns, 0x0100;
+		cwr, set_cwr, 0x0080;
+		ece, set_ece, 0x0040;
+		urg, set_urg, 0x0020;
+		ack, set_ack, 0x0010;
+		psh, set_psh, 0x0008;
+		rst, set_rst, 0x0004;
+		syn, set_syn, 0x0002;
+		fin, set_fin, 0x0001;
+	}
+
+	#[inline]
+	pub fn window(&self) -> u16 {
+		u16::from_be(self.header().window)
+	}
+
+	#[inline]
+	pub fn set_window(&mut self, window: u16) {
+		self.set_word(14, |h| h.window = window.to_be());
+	}
+
+	#[inline]
+	pub fn checksum(&self) -> u16 {
+		u16::from_be(self.header().checksum)
+	}
+
+	/// Sets the checksum as is; see `compute_checksum` to fill in the right one
+	#[inline]
+	pub fn set_checksum(&mut self, cksum: u16) {
+		self.header_mut().checksum = cksum.to_be();
+	}
+
+	#[inline]
+	pub fn urgent_pointer(&self) -> u16 {
+		u16::from_be(self.header().urgent_pointer)
+	}
+
+	#[inline]
+	pub fn set_urgent_pointer(&mut self, pointer: u16) {
+		self.set_word(18, |h| h.urgent_pointer = pointer.to_be());
+	}
+
+	/// Options after the fixed header
+	#[inline]
+	pub fn options(&self) -> &[u8] {
+		let len = self.header_len() - TcpHeader::size_of();
+		unsafe { slice::from_raw_parts((self.header.as_ptr() as *const u8).add(TcpHeader::size_of()), len) }
+	}
+
+	/// Options after the fixed header, for updating; the checksum is left to `compute_checksum`
+	#[inline]
+	pub fn options_mut(&mut self) -> &mut [u8] {
+		let len = self.header_len() - TcpHeader::size_of();
+		unsafe { slice::from_raw_parts_mut((self.header.as_ptr() as *mut u8).add(TcpHeader::size_of()), len) }
+	}
+
+	/// Computes the checksum over the pseudo header, the header and the payload and stores it
+	pub fn compute_checksum(&mut self) -> Result<(), ParseError> {
+		self.set_checksum(0);
+		let cksum = self.sum()?;
+		self.set_checksum(!cksum);
+		Ok(())
+	}
+
+	/// Does the checksum match the segment
+	pub fn is_checksum_valid(&self) -> Result<bool, ParseError> {
+		Ok(self.sum()? == 0xffff)
+	}
+
+	/// One's complement sum of the pseudo header and the whole segment
+	fn sum(&self) -> Result<u16, ParseError> {
+		let len = self.envelope.payload_len();
+		let mut sum = self.envelope.pseudo_header_sum(len as u16);
+		sum.add_mbuf(self.mbuf(), self.offset, len)?;
+		Ok(sum.sum())
+	}
+}
+
+impl<E: Datalink> Tcp<Ipv4<E>> {
+	/// Sets the source address of the IP header, keeping both checksums valid
+	#[inline]
+	pub fn set_src_addr(&mut self, ip: Ipv4Addr) {
+		self.update_checksum_u32(u32::from(self.envelope.src()), u32::from(ip));
+		self.envelope.set_src(ip);
+	}
+
+	/// Sets the destination address of the IP header, keeping both checksums valid
+	#[inline]
+	pub fn set_dst_addr(&mut self, ip: Ipv4Addr) {
+		self.update_checksum_u32(u32::from(self.envelope.dst()), u32::from(ip));
+		self.envelope.set_dst(ip);
+	}
+}
+
+impl<E: IpPacket> Packet for Tcp<E> {
+	type Envelope = E;
+
+	#[inline]
+	fn envelope(&self) -> &Self::Envelope {
+		&self.envelope
+	}
+
+	#[inline]
+	fn envelope_mut(&mut self) -> &mut Self::Envelope {
+		&mut self.envelope
+	}
+
+	#[inline]
+	fn offset(&self) -> usize {
+		self.offset
+	}
+
+	#[inline]
+	fn header_len(&self) -> usize {
+		self.data_offset() as usize * 4
+	}
+
+	#[inline]
+	fn payload_len(&self) -> usize {
+		self.envelope.payload_len().saturating_sub(self.header_len())
+	}
+
+	fn try_parse(envelope: Self::Envelope) -> Result<Self, ParseError> {
+		envelope.expect_protocol(IpProtocol::TCP, "TCP")?;
+		let offset = envelope.payload_offset();
+		let header = envelope.mbuf().read_data::<TcpHeader>(offset)?;
+		let header_len = (u16::from_be(unsafe { header.as_ref() }.offset_flags) >> 12) as usize * 4;
+		if header_len < TcpHeader::size_of() || header_len > envelope.payload_len() {
+			return Err(ParseError::Invalid(
+				"TCP",
+				format!("header length {} in a {} byte segment", header_len, envelope.payload_len()),
+			));
+		}
+		// the options have to be in the first segment as well
+		envelope.mbuf().read_data_slice::<u8>(offset, header_len)?;
+		Ok(Self {
+			envelope,
+			header,
+			offset,
+		})
+	}
+
+	#[inline]
+	fn deparse(self) -> Self::Envelope {
+		self.envelope
+	}
+}
+
+impl<E: IpPacket> fmt::Debug for Tcp<E> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Tcp")
+			.field("src_port", &self.src_port())
+			.field("dst_port", &self.dst_port())
+			.field("seq_no", &self.seq_no())
+			.field("ack_no", &self.ack_no())
+			.field("data_offset", &self.data_offset())
+			.field("flags", &format_args!("{:#05x}", self.flags()))
+			.field("window", &self.window())
+			.field("checksum", &format_args!("{:#06x}", self.checksum()))
+			.field("urgent_pointer", &self.urgent_pointer())
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::net::{testing, testing::assert_checksums, Ethernet};
+
+	/// A SYN carrying an odd length payload, both checksums valid
+	fn segment() -> Tcp<Ipv4<Ethernet>> {
+		let mut tcp = testing::ipv4(&testing::tcp_frame(b"hello")).parse::<Tcp<Ipv4<Ethernet>>>().unwrap();
+		tcp.compute_checksum().unwrap();
+		tcp
+	}
+
+	#[test]
+	fn parses_the_header() {
+		let tcp = segment();
+		assert_eq!(tcp.src_port(), 0x1234);
+		assert_eq!(tcp.dst_port(), 80);
+		assert_eq!(tcp.seq_no(), 1);
+		assert_eq!(tcp.data_offset(), 5);
+		assert!(tcp.syn() && !tcp.ack());
+		assert_eq!(tcp.payload_len(), 5);
+		assert!(tcp.is_checksum_valid().unwrap());
+		testing::release(tcp.reset());
+	}
+
+	#[test]
+	fn a_changed_payload_invalidates_the_checksum() {
+		let mut tcp = segment();
+		let offset = tcp.payload_offset();
+		unsafe { tcp.mbuf_mut().data_address(offset).write(b'j') };
+		assert!(!tcp.is_checksum_valid().unwrap());
+		testing::release(tcp.reset());
+	}
+
+	#[test]
+	fn address_rewrites_keep_both_checksums_valid() {
+		let mut tcp = segment();
+		tcp.set_src_addr(Ipv4Addr::new(203, 0, 113, 7));
+		assert_checksums(&mut tcp, "src address");
+		tcp.set_dst_addr(Ipv4Addr::new(198, 51, 100, 200));
+		assert_checksums(&mut tcp, "dst address");
+		assert_eq!(tcp.envelope().src(), Ipv4Addr::new(203, 0, 113, 7));
+		assert_eq!(tcp.envelope().dst(), Ipv4Addr::new(198, 51, 100, 200));
+		testing::release(tcp.reset());
+	}
+
+	#[test]
+	fn header_rewrites_keep_the_checksum_valid() {
+		let mut tcp = segment();
+		tcp.set_src_port(40000);
+		assert_checksums(&mut tcp, "src port");
+		tcp.set_dst_port(8080);
+		assert_checksums(&mut tcp, "dst port");
+		tcp.set_seq_no(0xdead_beef);
+		assert_checksums(&mut tcp, "seq no");
+		tcp.set_ack_no(0x0102_0304);
+		assert_checksums(&mut tcp, "ack no");
+		tcp.set_ack(true);
+		tcp.set_syn(false);
+		tcp.set_fin(true);
+		assert_checksums(&mut tcp, "flags");
+		tcp.set_flags(0x01ff);
+		assert_checksums(&mut tcp, "all flags");
+		assert_eq!(tcp.data_offset(), 5);
+		tcp.set_window(512);
+		assert_checksums(&mut tcp, "window");
+		tcp.set_urgent_pointer(3);
+		assert_checksums(&mut tcp, "urgent pointer");
+		testing::release(tcp.reset());
+	}
+}
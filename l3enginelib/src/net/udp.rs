@@ -0,0 +1,274 @@
+//! UDP datagrams
+//!
+//! Over IPv4 a zero checksum means the sender computed none: setters leave it at zero,
+//! and a computed checksum of zero is sent as `0xffff`.
+
+use crate::apis::SizeOf;
+use std::{fmt, net::Ipv4Addr, ptr::NonNull};
+
+use super::{
+	header_word, update_u16, update_u32, Datalink, IpPacket, IpProtocol, Ipv4, Packet, ParseError,
+};
+
+/// UDP header as it is on the wire
+#[repr(C, packed)]
+#[derive(Clone, Copy, SizeOf)]
+pub struct UdpHeader {
+	// big endian, as every field
+	src_port: u16,
+	dst_port: u16,
+	length: u16,
+	checksum: u16,
+}
+
+/// The UDP layer of a packet
+pub struct Udp<E: IpPacket> {
+	envelope: E,
+	header: NonNull<UdpHeader>,
+	offset: usize,
+}
+
+impl<E: IpPacket> Udp<E> {
+	#[inline]
+	fn header(&self) -> &UdpHeader {
+		unsafe { self.header.as_ref() }
+	}
+
+	#[inline]
+	fn header_mut(&mut self) -> &mut UdpHeader {
+		unsafe { self.header.as_mut() }
+	}
+
+	/// Store a checksum fixed by `update`, unless the datagram has none
+	#[inline]
+	fn update_checksum<F: FnOnce(u16) -> u16>(&mut self, update: F) {
+		if self.checksum() == 0 {
+			return;
+		}
+		let cksum = match update(self.checksum()) {
+			0 => 0xffff,
+			cksum => cksum,
+		};
+		self.header_mut().checksum = cksum.to_be();
+	}
+
+	/// Apply `f` to the header and fix the checksum for the word at byte `at` it changed
+	#[inline]
+	fn set_word<F: FnOnce(&mut UdpHeader)>(&mut self, at: usize, f: F) {
+		let old = unsafe { header_word(self.header, at) };
+		f(self.header_mut());
+		let new = unsafe { header_word(self.header, at) };
+		self.update_checksum(|cksum| update_u16(cksum, old, new));
+	}
+
+	#[inline]
+	pub fn src_port(&self) -> u16 {
+		u16::from_be(self.header().src_port)
+	}
+
+	#[inline]
+	pub fn set_src_port(&mut self, port: u16) {
+		self.set_word(0, |h| h.src_port = port.to_be());
+	}
+
+	#[inline]
+	pub fn dst_port(&self) -> u16 {
+		u16::from_be(self.header().dst_port)
+	}
+
+	#[inline]
+	pub fn set_dst_port(&mut self, port: u16) {
+		self.set_word(2, |h| h.dst_port = port.to_be());
+	}
+
+	/// Length of the datagram, header included
+	#[inline]
+	pub fn length(&self) -> u16 {
+		u16::from_be(self.header().length)
+	}
+
+	/// Sets the length field; the pseudo header repeats it, so it is not part of the
+	/// incremental update and `compute_checksum` is needed afterwards
+	#[inline]
+	pub fn set_length(&mut self, len: u16) {
+		self.header_mut().length = len.to_be();
+	}
+
+	#[inline]
+	pub fn checksum(&self) -> u16 {
+		u16::from_be(self.header().checksum)
+	}
+
+	/// Sets the checksum as is; 0 for none
+	#[inline]
+	pub fn set_checksum(&mut self, cksum: u16) {
+		self.header_mut().checksum = cksum.to_be();
+	}
+
+	/// Computes the checksum over the pseudo header, the header and the payload and stores it
+	pub fn compute_checksum(&mut self) -> Result<(), ParseError> {
+		self.set_checksum(0);
+		let cksum = match !self.sum()? {
+			0 => 0xffff,
+			cksum => cksum,
+		};
+		self.set_checksum(cksum);
+		Ok(())
+	}
+
+	/// Does the checksum match the datagram; a datagram without one passes
+	pub fn is_checksum_valid(&self) -> Result<bool, ParseError> {
+		if self.checksum() == 0 {
+			return Ok(true);
+		}
+		Ok(self.sum()? == 0xffff)
+	}
+
+	/// One's complement sum of the pseudo header and the whole datagram
+	fn sum(&self) -> Result<u16, ParseError> {
+		let len = self.length();
+		let mut sum = self.envelope.pseudo_header_sum(len);
+		sum.add_mbuf(self.mbuf(), self.offset, len as usize)?;
+		Ok(sum.sum())
+	}
+}
+
+impl<E: Datalink> Udp<Ipv4<E>> {
+	/// Sets the source address of the IP header, keeping both checksums valid
+	#[inline]
+	pub fn set_src_addr(&mut self, ip: Ipv4Addr) {
+		let old = u32::from(self.envelope.src());
+		self.update_checksum(|cksum| update_u32(cksum, old, u32::from(ip)));
+		self.envelope.set_src(ip);
+	}
+
+	/// Sets the destination address of the IP header, keeping both checksums valid
+	#[inline]
+	pub fn set_dst_addr(&mut self, ip: Ipv4Addr) {
+		let old = u32::from(self.envelope.dst());
+		self.update_checksum(|cksum| update_u32(cksum, old, u32::from(ip)));
+		self.envelope.set_dst(ip);
+	}
+}
+
+impl<E: IpPacket> Packet for Udp<E> {
+	type Envelope = E;
+
+	#[inline]
+	fn envelope(&self) -> &Self::Envelope {
+		&self.envelope
+	}
+
+	#[inline]
+	fn envelope_mut(&mut self) -> &mut Self::Envelope {
+		&mut self.envelope
+	}
+
+	#[inline]
+	fn offset(&self) -> usize {
+		self.offset
+	}
+
+	#[inline]
+	fn header_len(&self) -> usize {
+		UdpHeader::size_of()
+	}
+
+	/// Length of the payload as the header tells it
+	#[inline]
+	fn payload_len(&self) -> usize {
+		(self.length() as usize).saturating_sub(self.header_len())
+	}
+
+	fn try_parse(envelope: Self::Envelope) -> Result<Self, ParseError> {
+		envelope.expect_protocol(IpProtocol::UDP, "UDP")?;
+		let offset = envelope.payload_offset();
+		let header = envelope.mbuf().read_data::<UdpHeader>(offset)?;
+		let length = u16::from_be(unsafe { header.as_ref() }.length) as usize;
+		if length < UdpHeader::size_of() || length > envelope.payload_len() {
+			return Err(ParseError::Invalid(
+				"UDP",
+				format!("length {} in a {} byte payload", length, envelope.payload_len()),
+			));
+		}
+		Ok(Self {
+			envelope,
+			header,
+			offset,
+		})
+	}
+
+	#[inline]
+	fn deparse(self) -> Self::Envelope {
+		self.envelope
+	}
+}
+
+impl<E: IpPacket> fmt::Debug for Udp<E> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Udp")
+			.field("src_port", &self.src_port())
+			.field("dst_port", &self.dst_port())
+			.field("length", &self.length())
+			.field("checksum", &format_args!("{:#06x}", self.checksum()))
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::net::{testing, testing::assert_checksums, Checksum, Ethernet};
+
+	fn parse(frame: &[u8]) -> Udp<Ipv4<Ethernet>> {
+		testing::ipv4(frame).parse::<Udp<Ipv4<Ethernet>>>().unwrap()
+	}
+
+	#[test]
+	fn address_and_port_rewrites_keep_both_checksums_valid() {
+		let mut udp = parse(&testing::udp_frame(b"query"));
+		udp.compute_checksum().unwrap();
+		assert_ne!(udp.checksum(), 0);
+		udp.set_dst_addr(Ipv4Addr::new(198, 51, 100, 53));
+		assert_checksums(&mut udp, "dst address");
+		udp.set_src_addr(Ipv4Addr::new(203, 0, 113, 1));
+		assert_checksums(&mut udp, "src address");
+		udp.set_src_port(1024);
+		assert_checksums(&mut udp, "src port");
+		udp.set_dst_port(5353);
+		assert_checksums(&mut udp, "dst port");
+		testing::release(udp.reset());
+	}
+
+	#[test]
+	fn a_datagram_without_checksum_keeps_none() {
+		let mut udp = parse(&testing::udp_frame(b"query"));
+		assert_eq!(udp.checksum(), 0);
+		assert!(udp.is_checksum_valid().unwrap());
+		udp.set_dst_addr(Ipv4Addr::new(198, 51, 100, 53));
+		udp.set_dst_port(1);
+		assert_eq!(udp.checksum(), 0);
+		assert!(udp.envelope().is_checksum_valid());
+		testing::release(udp.reset());
+	}
+
+	#[test]
+	fn a_computed_zero_is_sent_as_ones() {
+		// pick the last payload word so the datagram sums to 0xffff with a zero checksum field
+		let mut frame = testing::udp_frame(&[0xab, 0xcd, 0, 0]);
+		let mut sum = Checksum::new();
+		sum.add_bytes(&frame[26..34]);
+		sum.add_u16(IpProtocol::UDP.0 as u16);
+		sum.add_u16(12);
+		sum.add_bytes(&frame[34..]);
+		let filler = 0xffff - sum.sum();
+		let end = frame.len();
+		frame[end - 2..].copy_from_slice(&filler.to_be_bytes());
+
+		let mut udp = parse(&frame);
+		udp.compute_checksum().unwrap();
+		assert_eq!(udp.checksum(), 0xffff);
+		assert!(udp.is_checksum_valid().unwrap());
+		testing::release(udp.reset());
+	}
+}
@@ -0,0 +1,228 @@
+//! 802.1Q and 802.1ad VLAN tags
+//!
+//! A tag sits between the Ethernet header and the payload and carries the ether type
+//! of the payload itself. A QinQ frame is parsed as a `Vlan<Vlan<Ethernet>>`.
+
+use crate::apis::SizeOf;
+use std::{fmt, ptr::NonNull};
+
+use super::{Datalink, EtherType, Packet, ParseError};
+
+/// VLAN tag as it is on the wire
+#[repr(C, packed)]
+#[derive(Clone, Copy, SizeOf)]
+pub struct VlanHeader {
+	// big endian
+	tci: u16,
+	// big endian
+	ether_type: u16,
+}
+
+/// The VLAN tag of a packet
+pub struct Vlan<E: Datalink> {
+	envelope: E,
+	header: NonNull<VlanHeader>,
+	offset: usize,
+}
+
+impl<E: Datalink> Vlan<E> {
+	#[inline]
+	fn header(&self) -> &VlanHeader {
+		unsafe { self.header.as_ref() }
+	}
+
+	#[inline]
+	fn header_mut(&mut self) -> &mut VlanHeader {
+		unsafe { self.header.as_mut() }
+	}
+
+	/// The tag control information: priority, drop eligibility and VLAN id
+	#[inline]
+	pub fn tci(&self) -> u16 {
+		u16::from_be(self.header().tci)
+	}
+
+	#[inline]
+	pub fn set_tci(&mut self, tci: u16) {
+		self.header_mut().tci = tci.to_be();
+	}
+
+	/// The VLAN id, the low 12 bits of the TCI
+	#[inline]
+	pub fn vid(&self) -> u16 {
+		self.tci() & 0x0fff
+	}
+
+	#[inline]
+	pub fn set_vid(&mut self, vid: u16) {
+		let tci = (self.tci() & !0x0fff) | (vid & 0x0fff);
+		self.set_tci(tci);
+	}
+
+	/// The priority code point, the top 3 bits of the TCI
+	#[inline]
+	pub fn pcp(&self) -> u8 {
+		(self.tci() >> 13) as u8
+	}
+
+	#[inline]
+	pub fn set_pcp(&mut self, pcp: u8) {
+		let tci = (self.tci() & 0x1fff) | ((pcp as u16 & 0x7) << 13);
+		self.set_tci(tci);
+	}
+
+	/// The drop eligible indicator
+	#[inline]
+	pub fn dei(&self) -> bool {
+		self.tci() & 0x1000 != 0
+	}
+
+	/// The ether type of the payload
+	#[inline]
+	pub fn ether_type(&self) -> EtherType {
+		EtherType(u16::from_be(self.header().ether_type))
+	}
+
+	#[inline]
+	pub fn set_ether_type(&mut self, ether_type: EtherType) {
+		self.header_mut().ether_type = ether_type.0.to_be();
+	}
+}
+
+impl<E: Datalink> Packet for Vlan<E> {
+	type Envelope = E;
+
+	#[inline]
+	fn envelope(&self) -> &Self::Envelope {
+		&self.envelope
+	}
+
+	#[inline]
+	fn envelope_mut(&mut self) -> &mut Self::Envelope {
+		&mut self.envelope
+	}
+
+	#[inline]
+	fn offset(&self) -> usize {
+		self.offset
+	}
+
+	#[inline]
+	fn header_len(&self) -> usize {
+		VlanHeader::size_of()
+	}
+
+	fn try_parse(envelope: Self::Envelope) -> Result<Self, ParseError> {
+		let ether_type = envelope.next_ether_type();
+		if !ether_type.is_vlan() {
+			return Err(ParseError::EtherType("VLAN", ether_type));
+		}
+		let offset = envelope.payload_offset();
+		let header = envelope.mbuf().read_data::<VlanHeader>(offset)?;
+		Ok(Self {
+			envelope,
+			header,
+			offset,
+		})
+	}
+
+	#[inline]
+	fn deparse(self) -> Self::Envelope {
+		self.envelope
+	}
+}
+
+impl<E: Datalink> Datalink for Vlan<E> {
+	#[inline]
+	fn next_ether_type(&self) -> EtherType {
+		self.ether_type()
+	}
+}
+
+impl<E: Datalink> fmt::Debug for Vlan<E> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Vlan")
+			.field("vid", &self.vid())
+			.field("pcp", &self.pcp())
+			.field("dei", &self.dei())
+			.field("ether_type", &self.ether_type())
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::apis::{BufError, Mbuf};
+	use crate::net::{testing, Ethernet, IpProtocol};
+
+	/// An 802.1ad tag for VLAN 100 with priority 5 around an 802.1Q tag for VLAN 200,
+	/// carrying an IPv4 header
+	fn qinq_frame() -> Vec<u8> {
+		let untagged = testing::ipv4_frame(IpProtocol::UDP, &[]);
+		let mut frame = untagged[..12].to_vec();
+		frame.extend_from_slice(&[0x88, 0xa8, 0xa0, 0x64, 0x81, 0x00, 0x00, 0xc8]);
+		frame.extend_from_slice(&untagged[12..]);
+		frame
+	}
+
+	fn parse(mbuf: Mbuf) -> Vlan<Vlan<Ethernet>> {
+		let ether = Ethernet::parse(mbuf).unwrap();
+		ether.parse::<Vlan<Ethernet>>().unwrap().parse::<Vlan<Vlan<Ethernet>>>().unwrap()
+	}
+
+	#[test]
+	fn parses_both_tags_of_a_qinq_frame() {
+		let inner = parse(testing::mbuf(&qinq_frame()));
+		assert_eq!(inner.envelope().envelope().ether_type(), EtherType::QINQ);
+		assert_eq!((inner.vid(), inner.pcp(), inner.dei()), (200, 0, false));
+		assert_eq!(inner.next_ether_type(), EtherType::IPV4);
+		assert_eq!((inner.offset(), inner.payload_offset(), inner.payload_len()), (18, 22, 20));
+
+		let outer = inner.deparse();
+		assert_eq!((outer.vid(), outer.pcp(), outer.dei()), (100, 5, false));
+		assert_eq!(outer.next_ether_type(), EtherType::VLAN);
+		assert_eq!((outer.offset(), outer.payload_offset()), (14, 18));
+		testing::release(outer.reset());
+	}
+
+	#[test]
+	fn tci_setters_keep_the_other_fields() {
+		let mut inner = parse(testing::mbuf(&qinq_frame()));
+		inner.set_pcp(7);
+		inner.set_vid(0xfff);
+		assert_eq!((inner.tci(), inner.pcp(), inner.vid()), (0xefff, 7, 0xfff));
+		inner.set_vid(0x1001);
+		assert_eq!((inner.pcp(), inner.vid()), (7, 1));
+		inner.set_ether_type(EtherType::ARP);
+
+		let inner = parse(inner.reset());
+		assert_eq!((inner.tci(), inner.next_ether_type()), (0xe001, EtherType::ARP));
+		assert_eq!(inner.envelope().vid(), 100);
+		testing::release(inner.reset());
+	}
+
+	#[test]
+	fn an_untagged_frame_is_a_parse_error() {
+		let ether = Ethernet::parse(testing::mbuf(&testing::ipv4_frame(IpProtocol::UDP, &[]))).unwrap();
+		match ether.parse::<Vlan<Ethernet>>() {
+			Err(ParseError::EtherType("VLAN", EtherType::IPV4)) => {}
+			other => panic!("{:?}", other),
+		}
+	}
+
+	#[test]
+	fn a_short_tag_is_a_parse_error() {
+		let frame = qinq_frame();
+		let ether = Ethernet::parse(testing::mbuf(&frame[..16])).unwrap();
+		match ether.parse::<Vlan<Ethernet>>() {
+			Err(ParseError::Buf(BufError::OutOfBuffer(4, 2))) => {}
+			other => panic!("2 byte tag: {:?}", other),
+		}
+		let outer = Ethernet::parse(testing::mbuf(&frame[..18])).unwrap().parse::<Vlan<Ethernet>>().unwrap();
+		match outer.parse::<Vlan<Vlan<Ethernet>>>() {
+			Err(ParseError::Buf(BufError::BadOffset(18, 18))) => {}
+			other => panic!("missing inner tag: {:?}", other),
+		}
+	}
+}
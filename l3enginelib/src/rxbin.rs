@@ -4,14 +4,41 @@
 //! 	1. External packets from the NIC
 //! 	2. Internal packets from the packetiser
 
-use crate::{FROM_PACKETISER, MEMPOOL, OUT_PKTS, PROCESSOR_THREAD, PROC_CHANNEL, TO_PACKETISER};
+use crate::{COUNTERS, CONFIG, FROM_PACKETISER, MEMPOOL, OUT_PKTS, PROC_CHANNEL, TO_PACKETISER};
 use crossbeam_queue::SegQueue;
-use l3enginelib::apis::{Mbuf, Mempool, Port};
+use l3enginelib::{
+	apis::{DropReason, L2Type, Mbuf, Mempool, Port, Stage},
+	net::{EtherType, Ethernet, Packet},
+};
 use state::Storage;
 
-pub(crate) fn get_external_pkts(ports: &Vec<Port>) -> usize {
-	let queue_id = unsafe { dpdk_sys::_rte_lcore_id() as u16 };
-	let pkts = ports[0].receive(queue_id);
+/// Poll the rx queue of the calling lcore on every port
+///
+/// Flow rules the NIC could not take are applied here (see `Port::apply_flows`).
+/// Every packet keeps the id of the port it arrived on in its port tag
+/// (`Mbuf::port`); unless a later stage retags it, it leaves through the same port
+pub(crate) fn get_external_pkts(ports: &[Port]) -> usize {
+	let counters = COUNTERS.get();
+	let mut pkts = Vec::new();
+	for port in ports {
+		let mut received = port.receive();
+		for pkt in &mut received {
+			if let Some(meta) = pkt.meta_mut() {
+				meta.reset(port.id);
+			}
+		}
+		if let Some(port_counters) = counters.port(port.id).filter(|_| !received.is_empty()) {
+			port_counters.count_in_pkts(&received);
+		}
+		// even without new packets, other lcores may have steered some to this one
+		let (kept, filtered) = port.apply_flows(received);
+		if let Some(port_counters) = counters.port(port.id).filter(|_| filtered > 0) {
+			port_counters.count_drops(DropReason::Filtered, filtered as u64);
+		}
+		pkts.extend(kept);
+	}
+	let stage = counters.stage(Stage::GetExternalPkts);
+	stage.count_in_pkts(&pkts);
 	let out_pkts = OUT_PKTS.get();
 	let ring_pkts = TO_PACKETISER.get();
 	let len = pkts.len();
@@ -19,17 +46,30 @@ pub(crate) fn get_external_pkts(ports: &Vec<Port>) -> usize {
 	let mut cnt = 0;
 
 	for pkt in pkts {
-		let ether_hdr = unsafe { dpdk_sys::_pkt_ether_hdr(pkt.get_ptr()) };
-		if !ether_hdr.is_null() {
-			let ether_type = unsafe { (*ether_hdr).ether_type };
-			if ether_type != 0 {
+		// the NIC already found an ethernet header, only its ether type is left to check
+		if pkt.packet_type().l2() != L2Type::Unknown {
+			match ether_type(&pkt) {
+				Some(ether_type) if ether_type != EtherType(0) => {
+					cnt += 1;
+					stage.count_out(1, pkt.pkt_len() as u64);
+					ring_pkts.push(pkt);
+				}
+				// without an ether type: the packet is freed here
+				_ => stage.count_drops(DropReason::Malformed, 1),
+			}
+			continue;
+		}
+		match Ethernet::parse(pkt) {
+			Ok(ether) if ether.ether_type() != EtherType(0) => {
 				cnt += 1;
-				ring_pkts.push(pkt);
 				#[cfg(feature = "debug")]
-				println!("ether type: {:x}", u16::from_be(ether_type));
+				println!("ether type: {}", ether.ether_type());
+				let pkt = ether.reset();
+				stage.count_out(1, pkt.pkt_len() as u64);
+				ring_pkts.push(pkt);
 			// match server.detect_arp(&pkt) {
 			// 	Some(_ip) => {
-			// 		let mp = MEMPOOL.get();
+			// 		let mp = MEMPOOL.get().local();
 			// 		if let Some(out_arp) = server.send_arp_reply(&mut pkt, mp) {
 			// 			#[cfg(feature = "debug")]
 			// 			println!("arp type");
@@ -38,19 +78,16 @@ pub(crate) fn get_external_pkts(ports: &Vec<Port>) -> usize {
 			// 	}
 			// 	None => {
 			// 		// ring_pkts.push(pkt);
-			// 		if ether_type == 8 {
-			// 			// IPv4 packet
+			// 		if ether.ether_type() == EtherType::IPV4 {
 			// 			#[cfg(feature = "debug")]
 			// 			println!("ipv4 type");
 			// 			ring_pkts.push(pkt);
 			// 		}
 			// 	}
 			// }
-			} else {
-				drop(pkt);
 			}
-		} else {
-			drop(pkt);
+			// too short for an ethernet header, or without an ether type: the packet is freed here
+			_ => stage.count_drops(DropReason::Malformed, 1),
 		}
 	}
 	#[cfg(feature = "debug")]
@@ -60,18 +97,31 @@ pub(crate) fn get_external_pkts(ports: &Vec<Port>) -> usize {
 	len
 }
 
+/// Ether type of a frame, read without parsing the ethernet header
+fn ether_type(pkt: &Mbuf) -> Option<EtherType> {
+	let mut bytes = [0u8; 2];
+	// after the destination and source addresses
+	pkt.read_bytes(2 * dpdk_sys::RTE_ETHER_ADDR_LEN as usize, &mut bytes).ok()?;
+	Some(EtherType(u16::from_be_bytes(bytes)))
+}
+
 pub(crate) fn get_from_packetiser() -> usize {
 	let queue_id = unsafe { dpdk_sys::_rte_lcore_id() as u16 };
 	let out_pkts = OUT_PKTS.get();
 	let ring_pkts = FROM_PACKETISER.get();
 	let ch = PROC_CHANNEL.get();
+	let client_id = CONFIG.get().packetiser.client_id;
 	let len = ring_pkts.len(); // number of packets packetiser has sent
-	let mp = MEMPOOL.get();
+	let mp = MEMPOOL.get().local();
+	let stage = COUNTERS.get().stage(Stage::GetFromPacketiser);
+	let client = COUNTERS.get().client(client_id);
 	let mut pkts = Vec::with_capacity(len);
 	for l in 0..len {
 		match Mbuf::new(mp) {
 			Ok(buf) => pkts.push(buf),
 			Err(_) => {
+				stage.alloc_failures.add(1);
+				mp.check_low_water();
 				pkts.truncate(l);
 				break;
 			}
@@ -80,8 +130,14 @@ pub(crate) fn get_from_packetiser() -> usize {
 	let pkt_len = pkts.len(); // number of buffers created
 	let mut s_len = 0; // number of packets gotten out of the packetiser ring
 	for mut pkt in pkts {
-		match ch.receive(PROCESSOR_THREAD, &mut pkt) {
+		match ch.receive(client_id, &mut pkt) {
 			Ok(()) => {
+				let bytes = pkt.pkt_len() as u64;
+				stage.count_in(1, bytes);
+				stage.count_out(1, bytes);
+				if let Some(client) = client {
+					client.count_in(1, bytes);
+				}
 				out_pkts.push(pkt);
 				s_len += 1;
 			}
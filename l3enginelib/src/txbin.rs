@@ -4,38 +4,117 @@
 //! 	1. External packets out of the NIC
 //! 	2. Internal packets to the packetiser
 
-use crate::{OUT_PKTS, PROCESSOR_THREAD, PROC_CHANNEL, TO_PACKETISER};
-use l3enginelib::apis::Port;
+use crate::{COUNTERS, CONFIG, OUT_PKTS, PROC_CHANNEL, TO_PACKETISER};
+use l3enginelib::apis::{DropReason, Mbuf, Port, RingClientMapError, Stage};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-pub(crate) fn send_pkts_out(ports: &Vec<Port>) -> usize {
-	let queue_id = unsafe { dpdk_sys::_rte_lcore_id() as u16 };
+/// Port tag of a freshly allocated buffer (`MBUF_INVALID_PORT`)
+const UNTAGGED: u16 = u16::MAX;
+
+/// Set once a missing tx queue has been reported, so the log is not flooded
+static NO_TX_QUEUE_LOGGED: AtomicBool = AtomicBool::new(false);
+
+/// Send every outgoing packet through the port its port tag (`Mbuf::port`) names
+///
+/// Untagged packets, i.e. ones built from scratch, leave through the first port.
+/// Packets tagged with a port the engine does not drive, e.g. one detached since
+/// they arrived, are dropped
+pub(crate) fn send_pkts_out(ports: &[Port]) -> usize {
 	let out_pkts = OUT_PKTS.get();
-	let mut pkts = Vec::with_capacity(out_pkts.len());
 
 	if out_pkts.is_empty() {
 		return 0usize;
 	}
 
+	let stage = COUNTERS.get().stage(Stage::SendPktsOut);
+	let mut per_port: Vec<Vec<Mbuf>> = ports.iter().map(|_| Vec::new()).collect();
+	let mut dropped = Vec::new();
 	while let Some(pkt) = out_pkts.pop() {
-		pkts.push(pkt);
+		stage.count_in(1, pkt.pkt_len() as u64);
+		let tag = pkt.port();
+		let egress = match tag {
+			UNTAGGED if !ports.is_empty() => Some(0),
+			_ => ports.iter().position(|port| port.id == tag),
+		};
+		match egress {
+			Some(i) => per_port[i].push(pkt),
+			None => dropped.push(pkt),
+		}
 	}
-	ports[0].send(pkts, queue_id ^ 1)
+	if !dropped.is_empty() {
+		log::debug!("send_pkts_out: dropping {} pkt(s) for unknown ports", dropped.len());
+		stage.count_drops(DropReason::NoPort, dropped.len() as u64);
+		Mbuf::free_bulk(dropped);
+	}
+
+	ports
+		.iter()
+		.zip(per_port)
+		.filter(|(_, pkts)| !pkts.is_empty())
+		.map(|(port, pkts)| {
+			let lens = pkts.iter().map(|pkt| pkt.pkt_len() as u64).collect::<Vec<_>>();
+			let (sent, reason) = match port.send(pkts) {
+				Ok(sent) => (sent, DropReason::TxFull),
+				Err(e) => {
+					// a queue map bug rather than backpressure
+					if !NO_TX_QUEUE_LOGGED.swap(true, Ordering::Relaxed) {
+						log::error!("send_pkts_out: port {}: {}, dropping its packets", port.id, e);
+					}
+					(0, DropReason::NoTxQueue)
+				}
+			};
+			let bytes = lens[..sent].iter().sum();
+			stage.count_out(sent as u64, bytes);
+			if let Some(port_counters) = COUNTERS.get().port(port.id) {
+				port_counters.count_out(sent as u64, bytes);
+				port_counters.count_drops(reason, (lens.len() - sent) as u64);
+			}
+			sent
+		})
+		.sum()
 }
 
 pub(crate) fn send_to_packetiser() -> usize {
 	let queue_id = unsafe { dpdk_sys::_rte_lcore_id() as u16 };
 	let ring_pkts = TO_PACKETISER.get();
 	let ch = PROC_CHANNEL.get();
+	let client_id = CONFIG.get().packetiser.client_id;
 	if ring_pkts.is_empty() {
 		return 0usize;
 	}
 	// #[cfg(feature = "debug")]
 	// println!("sending to packetiser");
+	let stage = COUNTERS.get().stage(Stage::SendToPacketiser);
+	let client = COUNTERS.get().client(client_id);
 	let mut len = 0;
-	while let Some(pkt) = ring_pkts.pop() {
-		match ch.send(PROCESSOR_THREAD, pkt) {
-			Ok(()) => len += 1,
-			Err(_) => break,
+	while let Some(mut pkt) = ring_pkts.pop() {
+		if let Some(meta) = pkt.meta_mut() {
+			meta.set_client_id(client_id);
+		}
+		let bytes = pkt.pkt_len() as u64;
+		stage.count_in(1, bytes);
+		// a packet the channel does not take is freed
+		match ch.send(client_id, pkt) {
+			Ok(()) => {
+				stage.count_out(1, bytes);
+				if let Some(client) = client {
+					client.count_out(1, bytes);
+				}
+				len += 1
+			}
+			Err(RingClientMapError::ClientNotFound(_)) => {
+				stage.count_drops(DropReason::NoClient, 1);
+				break;
+			}
+			Err(_) => {
+				stage.ring_full.add(1);
+				stage.count_drops(DropReason::RingFull, 1);
+				if let Some(client) = client {
+					client.ring_full.add(1);
+					client.count_drops(DropReason::RingFull, 1);
+				}
+				break;
+			}
 		}
 	}
 	len
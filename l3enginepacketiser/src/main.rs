@@ -18,15 +18,23 @@
 mod net;
 mod packetiser;
 
-use ctrlc;
+use l3enginelib::{
+    apis::Port,
+    config::{EngineConfig, DEFAULT_CONFIG_PATH, READY_MSG, SHUTDOWN_MSG, STOPPED_MSG},
+};
 use net::{EthDevEmulator, IfaceEmulator, SockSet};
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
 use std::{
+    env,
     process::exit,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    thread::sleep,
+    thread::{self, sleep},
     time::Duration,
 };
 
@@ -34,27 +42,35 @@ use packetiser::{Packetiser, RoutingTable};
 use state::Storage;
 use zmq::Context;
 
-#[cfg(not(feature = "debug"))]
-pub const BURST_MAX: usize = 512;
-#[cfg(feature = "debug")]
-pub const BURST_MAX: usize = 32;
 pub(crate) static TABLE: Storage<RoutingTable> = Storage::new();
 
-const PACKETISER_ZMQ_PORT: &str = "tcp://localhost:5555";
-
+/// Clear `kr` on SIGINT or SIGTERM
 fn handle_signal(kr: Arc<AtomicBool>) {
-    ctrlc::set_handler(move || {
-        kr.store(false, Ordering::SeqCst);
-    })
-    .expect("Error setting Ctrl-C handler");
+    let mut signals = Signals::new([SIGINT, SIGTERM]).expect("Error setting signal handlers");
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            kr.store(false, Ordering::SeqCst);
+        }
+    });
 }
 
 // DEVFLAGS: development flags - remove in production
 #[allow(while_true)]
 // use packetiser;
 fn main() {
-    packetiser::start();
-    let proc = packetiser::Packetiser::new(BURST_MAX);
+    let conf_path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from(DEFAULT_CONFIG_PATH));
+    let config = match EngineConfig::load(&conf_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("packetiser: bad configuration: {}", e);
+            exit(1);
+        }
+    };
+
+    packetiser::start(&config);
+    let proc = packetiser::Packetiser::new(&config);
     #[cfg(feature = "debug")]
     println!("packetiser created");
     TABLE.set(RoutingTable::new());
@@ -63,28 +79,43 @@ fn main() {
     println!("packetiser: sending ready msg to main");
     let context = Context::new();
     let requester = context.socket(zmq::REQ).unwrap(); // fatal error
-    assert!(requester.connect(PACKETISER_ZMQ_PORT).is_ok());
-    requester.send("Hello", 0).unwrap();
+    assert!(requester.connect(&config.control.packetiser_connect).is_ok());
+    requester.send(READY_MSG, 0).unwrap();
     #[cfg(feature = "debug")]
     println!("packetiser: sent ready msg to main");
 
     #[cfg(feature = "debug")]
     println!("packetiser: created routing table");
 
-    // handling Ctrl+C
+    // handling Ctrl+C and SIGTERM
     let keep_running = Arc::new(AtomicBool::new(true));
     let kr = keep_running.clone();
     handle_signal(keep_running.clone());
 
     // create device
-    let mac = [0x90, 0xe2, 0xba, 0xb2, 0x98, 0x48];
+    // the engine has configured the ports; ask the ethdev layer for the address of the first one
+    let port_conf = &config.ports[0];
+    let mac = Port::new(&port_conf.name, port_conf.id)
+        .and_then(|port| port.mac_addr())
+        .expect("packetiser: cannot read port mac address")
+        .octets();
     let addr = [10, 10, 1, 1];
     let prefix = 24;
     let mut sockets = SockSet::new(mac, addr, prefix);
     #[cfg(feature = "debug")]
     println!("packetiser: sockets created");
 
+    // set once the engine has asked us to stop
+    let mut engine_stopping = false;
     while kr.load(Ordering::SeqCst) {
+        // the engine replies to the ready message when it shuts down
+        if let Ok(Ok(msg)) = requester.recv_string(zmq::DONTWAIT) {
+            if msg == SHUTDOWN_MSG {
+                log::info!("packetiser: engine is shutting down");
+                engine_stopping = true;
+                break;
+            }
+        }
         sockets.process_pkts();
         match proc.recv_from_engine_burst() {
             Ok(_count) =>
@@ -135,4 +166,13 @@ fn main() {
             }
         }
     }
+
+    // everything we hold goes back to the mempool before the engine frees it
+    let drained = proc.drain();
+    log::info!("packetiser: freed {} queued pkt(s)", drained);
+    if engine_stopping {
+        if let Err(e) = requester.send(STOPPED_MSG, 0) {
+            log::warn!("packetiser: cannot tell the engine we stopped: {}", e);
+        }
+    }
 }
@@ -1,6 +1,5 @@
 //! Implements the support for TCP at the packetiser
 
-use ctrlc::set_handler;
 use smoltcp::{
 	iface::EthernetInterface,
 	socket::{
@@ -20,17 +20,19 @@
 // DEVFLAGS: development flags - remove in production
 #![allow(dead_code)]
 
-use crate::{BURST_MAX, TABLE};
+use crate::TABLE;
 use chashmap::CHashMap;
 use crossbeam_queue::SegQueue;
 use l3enginelib::{
-	apis::{eal_init, Channel, Mbuf, MemoryError, Mempool, RingClientMap, RingClientMapError},
-	net::Ipv4Hdr,
+	apis::{
+		eal_init, Channel, Mbuf, MemoryError, Mempool, MempoolSet, RingClientMap,
+		RingClientMapError,
+	},
+	config::EngineConfig,
+	net::{Ipv4Hdr, ParseError},
 };
 use std::{net::Ipv4Addr, result::Result};
 
-const G_MEMPOOL_NAME: &str = "GLOBAL_MEMPOOL";
-
 pub(crate) struct RoutingTable {
 	ip_id_map: CHashMap<Ipv4Addr, u16>,
 	id_ip_map: CHashMap<u16, Ipv4Addr>,
@@ -75,7 +77,7 @@ impl RoutingTable {
 
 pub struct Packetiser {
 	channel: Channel, // receive and transmit packets from and to the main process
-	mempool: Mempool, // mempool to use
+	mempool: MempoolSet, // mempools to use, by socket
 	clientmap: RingClientMap,
 	pub(crate) i_bufqueue: SegQueue<Mbuf>, // packets that have been received from the primary process
 	pub(crate) o_bufqueue: SegQueue<Mbuf>, // packets that have been received from clients
@@ -86,10 +88,9 @@ pub struct Packetiser {
 pub static mut LAST_ALLOCATED_ID: u16 = 1; // ID of packetiser itself
 
 impl Packetiser {
-	const PACKETISER_ID: u16 = 1;
-
-	pub fn new(cap: usize) -> Self {
-		let channel = Channel::lookup(Self::PACKETISER_ID).unwrap(); // fatal error
+	pub fn new(config: &EngineConfig) -> Self {
+		let cap = config.packetiser.burst;
+		let channel = Channel::lookup(config.packetiser.client_id).unwrap(); // fatal error
 		#[cfg(feature = "debug")]
 		{
 			println!("found channel");
@@ -102,10 +103,12 @@ impl Packetiser {
 				channel.engine_to_client.get_ptr()
 			);
 		}
-		let mempool = Mempool::lookup(G_MEMPOOL_NAME).unwrap(); // fatal error
+		// the engine's pools; `local` picks the one on our socket
+		let mempool = MempoolSet::lookup(&config.mempool.name).unwrap(); // fatal error
+		mempool.set_low_water_pct(config.mempool.low_water_pct);
 		#[cfg(feature = "debug")]
-		println!("found mempool, address: {:p}", mempool.get_ptr());
-		let clientmap = RingClientMap::new();
+		println!("found mempools: {:?}", mempool);
+		let clientmap = RingClientMap::with_ring_size(config.rings.size as usize);
 		let i_bufqueue = SegQueue::new();
 		let o_bufqueue = SegQueue::new();
 		let allocated_ids = Vec::new();
@@ -157,16 +160,18 @@ impl Packetiser {
 		// let range = self.i_bufqueue.capacity() - self.i_bufqueue.len();
 
 		// get those many packets only
-		let mut pkts = Vec::with_capacity(BURST_MAX);
-		for _ in 0..BURST_MAX {
-			let buf_res = Mbuf::new(&self.mempool);
+		let mut pkts = Vec::with_capacity(self.cap);
+		for _ in 0..self.cap {
+			let buf_res = Mbuf::new(self.mempool.local());
 			match buf_res {
 				Ok(buf) => pkts.push(buf),
 				Err(_) => break, // break the loop when no more buffers can be created
 			}
 		}
 		if pkts.len() == 0 {
-			log::error!("recv_from_engine_bulk: no buffer could be created");
+			let mp = self.mempool.local();
+			mp.check_low_water();
+			log::error!("recv_from_engine_bulk: no buffer could be created: {}", mp.stats());
 			return Err(MemoryError::new());
 		}
 		let len = pkts.len();
@@ -191,7 +196,7 @@ impl Packetiser {
 			return 0;
 		}
 
-		let mut pkts = Vec::with_capacity(BURST_MAX);
+		let mut pkts = Vec::with_capacity(self.cap);
 		for pkt in self.o_bufqueue.pop() {
 			pkts.push(pkt);
 		}
@@ -212,7 +217,19 @@ impl Packetiser {
 		self.clientmap.receive(key, pkt)
 	}
 
-	pub(crate) fn ipv4hdr(&self, pkt: &mut Mbuf) -> Ipv4Hdr {
+	/// Free every packet held in the buffers and in the client rings; returns how many there were
+	pub(crate) fn drain(&self) -> usize {
+		let mut count = 0;
+		for queue in &[&self.i_bufqueue, &self.o_bufqueue] {
+			while let Some(pkt) = queue.pop() {
+				drop(pkt);
+				count += 1;
+			}
+		}
+		count + self.clientmap.drain()
+	}
+
+	pub(crate) fn ipv4hdr(&self, pkt: &Mbuf) -> Result<Ipv4Hdr, ParseError> {
 		Ipv4Hdr::from_mbuf(pkt)
 	}
 
@@ -220,9 +237,9 @@ impl Packetiser {
 	pub(crate) fn store_incoming(&self) -> Result<(), MemoryError> {
 		// let mut len = self.i_bufqueue.capacity() - self.i_bufqueue.len();
 		let mut len = 0;
-		let mut pkts = Vec::with_capacity(BURST_MAX);
-		for i in 0..BURST_MAX {
-			match Mbuf::new(&self.mempool) {
+		let mut pkts = Vec::with_capacity(self.cap);
+		for i in 0..self.cap {
+			match Mbuf::new(self.mempool.local()) {
 				Ok(buf) => {
 					pkts.push(buf);
 					len = i + 1;
@@ -243,8 +260,14 @@ impl Packetiser {
 
 	pub(crate) fn forward_incoming_packets(&self) {
 		for _ in 0..self.i_bufqueue.len() {
-			let mut pkt = self.i_bufqueue.pop().unwrap();
-			let iphdr = Ipv4Hdr::from_mbuf(&mut pkt);
+			let pkt = self.i_bufqueue.pop().unwrap();
+			let iphdr = match Ipv4Hdr::from_mbuf(&pkt) {
+				Ok(iphdr) => iphdr,
+				Err(e) => {
+					log::debug!("packetiser: dropping packet: {}", e);
+					continue;
+				}
+			};
 			let client_ip = iphdr.get_dst_addr();
 			let client_id = TABLE.get().id_from_ip(client_ip);
 			self.clientmap.send(client_id, pkt).unwrap();
@@ -254,10 +277,10 @@ impl Packetiser {
 	/// Store packets to be sent to the main process in the outgoing buffer
 	pub(crate) fn store_outgoing(&self) -> Result<(), MemoryError> {
 		// let mut len = self.o_bufqueue.capacity() - self.o_bufqueue.len();
-		let mut pkts = Vec::with_capacity(BURST_MAX);
+		let mut pkts = Vec::with_capacity(self.cap);
 		let mut len = 0;
-		for i in 0..BURST_MAX {
-			match Mbuf::new(&self.mempool) {
+		for i in 0..self.cap {
+			match Mbuf::new(self.mempool.local()) {
 				Ok(buf) => {
 					pkts.push(buf);
 					len = i + 1;
@@ -272,7 +295,7 @@ impl Packetiser {
 		// a simple round robing policy to collect packets from clients
 		for _ in 0..len {
 			for key in &self.allocated_ids {
-				let mut pkt = Mbuf::new(&self.mempool)?;
+				let mut pkt = Mbuf::new(self.mempool.local())?;
 				match self.clientmap.receive(*key, &mut pkt) {
 					Ok(_) => self.o_bufqueue.push(pkt),
 					Err(_) => continue,
@@ -297,36 +320,16 @@ impl Packetiser {
 		Ok(())
 	}
 
-	fn u32_to_ipaddr(&self, ip: u32) -> Ipv4Addr {
-		Ipv4Addr::new(
-			((ip >> 24) & 0xFF) as u8,
-			((ip >> 16) & 0xFF) as u8,
-			((ip >> 8) & 0xFF) as u8,
-			(ip & 0xFF) as u8,
-		)
-	}
-
-	/// Get the header of an IPv4 packet
+	/// Get the destination address of an IPv4 packet
 	pub fn get_ip_hdr(&self, buf: &mut Mbuf) -> Option<Ipv4Addr> {
-		let ipv4_hdr = unsafe { dpdk_sys::_pkt_ipv4_hdr(buf.get_ptr()) };
 		#[cfg(feature = "debug")]
 		println!("in get_ip_hdr");
-		if ipv4_hdr.is_null() {
-			return None;
-		}
-		let dst_addr = unsafe { (*ipv4_hdr).dst_addr };
-		Some(Self::u32_to_ipaddr(&self, dst_addr))
+		Ipv4Hdr::from_mbuf(buf).ok().map(|iphdr| iphdr.get_dst_addr())
 	}
 }
 
-pub(crate) fn start() {
-	let args = vec![
-		String::from("-l 2-3"),
-		String::from("-n 4"),
-		String::from("--proc-type=secondary"),
-		String::from("--"),
-		String::from("-n 0"),
-	];
+pub(crate) fn start(config: &EngineConfig) {
+	let args = config.packetiser_eal_args();
 	#[cfg(feature = "debug")]
 	println!("packetiser args: {:?}", &args);
 	eal_init(args).unwrap();